@@ -0,0 +1,19 @@
+DROP TABLE IF EXISTS associates;
+DROP TABLE IF EXISTS talks;
+DROP TABLE IF EXISTS users;
+DROP TABLE IF EXISTS categories;
+DROP TABLE IF EXISTS public_messages1;
+DROP TABLE IF EXISTS private_messages1;
+DROP TABLE IF EXISTS relations;
+
+DROP TABLE IF EXISTS psn_user_trophy_titles;
+DROP TABLE IF EXISTS psn_user_trophy_sets;
+DROP TABLE IF EXISTS trophy_sets;
+DROP TABLE IF EXISTS trophy_sets_argument;
+
+DROP TYPE IF EXISTS trophy_set;
+DROP TYPE IF EXISTS should_before_after;
+DROP TYPE IF EXISTS should_absent_time;
+
+DROP TABLE IF EXISTS topics;
+DROP TABLE IF EXISTS posts;
@@ -0,0 +1,201 @@
+CREATE TABLE users
+(
+id              OID          NOT NULL UNIQUE PRIMARY KEY,
+username        VARCHAR(32)  NOT NULL UNIQUE,
+email           VARCHAR(100) NOT NULL UNIQUE,
+hashed_password VARCHAR(64)  NOT NULL,
+avatar_url      VARCHAR(128) NOT NULL,
+signature       VARCHAR(256) NOT NULL,
+created_at      TIMESTAMP    NOT NULL DEFAULT CURRENT_TIMESTAMP,
+privilege       OID          NOT NULL DEFAULT 1,
+show_email      BOOLEAN      NOT NULL DEFAULT TRUE
+);
+
+CREATE TABLE categories
+(
+id               OID          NOT NULL UNIQUE PRIMARY KEY,
+name             VARCHAR(128) NOT NULL UNIQUE,
+thumbnail        VARCHAR(256) NOT NULL
+);
+
+CREATE TABLE topics
+(
+id              OID           NOT NULL UNIQUE PRIMARY KEY,
+user_id         OID           NOT NULL,
+category_id     OID           NOT NULL,
+title           VARCHAR(1024) NOT NULL,
+body            VARCHAR(1024) NOT NULL,
+thumbnail       VARCHAR(1024) NOT NULL,
+created_at      TIMESTAMP     NOT NULL DEFAULT CURRENT_TIMESTAMP,
+updated_at      TIMESTAMP     NOT NULL DEFAULT CURRENT_TIMESTAMP,
+is_locked       BOOLEAN       NOT NULL DEFAULT FALSE,
+is_visible      BOOLEAN       NOT NULL DEFAULT TRUE
+);
+
+CREATE TABLE posts
+(
+id              OID           NOT NULL UNIQUE PRIMARY KEY,
+user_id         OID           NOT NULL,
+topic_id        OID           NOT NULL,
+category_id     OID           NOT NULL,
+post_id         OID,
+post_content    VARCHAR(1024) NOT NULL,
+created_at      TIMESTAMP     NOT NULL DEFAULT CURRENT_TIMESTAMP,
+updated_at      TIMESTAMP     NOT NULL DEFAULT CURRENT_TIMESTAMP,
+is_locked       BOOLEAN       NOT NULL DEFAULT FALSE
+);
+
+CREATE TABLE associates
+(
+id               OID          NOT NULL UNIQUE PRIMARY KEY,
+user_id          OID          NOT NULL UNIQUE,
+psn_id           VARCHAR(128) UNIQUE,
+live_id          VARCHAR(128) UNIQUE,
+last_update_time TIMESTAMP    NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE TABLE talks
+(
+id              OID             NOT NULL UNIQUE PRIMARY KEY,
+name            VARCHAR(128)    NOT NULL UNIQUE,
+description     VARCHAR(128)    NOT NULL,
+secret          VARCHAR(128)    NOT NULL DEFAULT '1',
+privacy         OID             NOT NULL DEFAULT 0,
+owner           OID             NOT NULL,
+admin           OID[]           NOT NULL,
+users           OID[]           NOT NULL
+);
+
+CREATE TABLE relations
+(
+id          OID             NOT NULL UNIQUE PRIMARY KEY,
+friends     OID[]
+);
+
+CREATE TABLE public_messages1
+(
+talk_id     OID             NOT NULL PRIMARY KEY,
+time        TIMESTAMP       NOT NULL DEFAULT CURRENT_TIMESTAMP,
+text        VARCHAR(1024)   NOT NULL
+);
+
+CREATE TABLE private_messages1
+(
+from_id     OID             NOT NULL,
+to_id       OID             NOT NULL PRIMARY KEY,
+time        TIMESTAMP       NOT NULL DEFAULT CURRENT_TIMESTAMP,
+text        VARCHAR(1024)   NOT NULL
+);
+
+CREATE INDEX pub_message_time_order ON public_messages1 (time DESC);
+CREATE INDEX prv_message_time_order ON private_messages1 (time DESC);
+
+CREATE UNIQUE INDEX users_username ON users (username);
+CREATE UNIQUE INDEX users_email ON users (email);
+CREATE UNIQUE INDEX categories_name ON categories (name);
+CREATE UNIQUE INDEX talks_name ON talks (name);
+CREATE UNIQUE INDEX associates_psn_id ON associates (psn_id);
+CREATE UNIQUE INDEX associates_live_id ON associates (live_id);
+
+
+CREATE TABLE psn_user_trophy_titles
+(
+np_id                   VARCHAR(32)         NOT NULL,
+np_communication_id     VARCHAR(32)         NOT NULL,
+is_visible              BOOLEAN             NOT NULL DEFAULT TRUE,
+progress                OID                 NOT NULL DEFAULT 0,
+earned_platinum         OID                 NOT NULL DEFAULT 0,
+earned_gold             OID                 NOT NULL DEFAULT 0,
+earned_silver           OID                 NOT NULL DEFAULT 0,
+earned_bronze           OID                 NOT NULL DEFAULT 0,
+last_update_date        TIMESTAMP           NOT NULL
+);
+
+CREATE TABLE trophy_sets
+(
+np_communication_id     VARCHAR(32)         NOT NULL,
+trophy_id               OID                 NOT NULL,
+trophy_hidden           BOOLEAN             NOT NULL,
+trophy_type             VARCHAR(16)         NOT NULL,
+trophy_name             VARCHAR(128)        NOT NULL,
+trophy_detail           VARCHAR(1024)       NOT NULL,
+trophy_icon_url         VARCHAR(1024)       NOT NULL,
+trophy_rare             INTEGER             NOT NULL,
+trophy_earned_rate      VARCHAR(16)         NOT NULL
+);
+
+CREATE TYPE should_before_after AS
+(
+trophy_id               OID,
+reason                  VARCHAR(1024),
+agreement               OID,
+disagreement            OID
+);
+
+CREATE TYPE should_absent_time AS
+(
+beginning               TIMESTAMP,
+ending                  TIMESTAMP,
+is_regular              BOOLEAN,
+reason                  VARCHAR(1024),
+agreement               OID,
+disagreement            OID
+);
+
+CREATE TABLE trophy_sets_argument
+(
+np_communication_id     VARCHAR(32)         NOT NULL,
+trophy_id               INTEGER             NOT NULL,
+should_before           should_before_after[],
+should_after            should_before_after[],
+should_absent_time      should_absent_time[]
+);
+
+CREATE TYPE trophy_set AS
+(
+trophy_id               INTEGER,
+earned_date             TIMESTAMP,
+first_earned_date       TIMESTAMP
+);
+
+CREATE TABLE psn_user_trophy_sets
+(
+np_id                   VARCHAR(32)         NOT NULL,
+np_communication_id     VARCHAR(32)         NOT NULL,
+is_visible              BOOLEAN             NOT NULL DEFAULT TRUE,
+trophy_set              trophy_set[]
+);
+
+CREATE UNIQUE INDEX user_trophy_titles ON psn_user_trophy_titles (np_id, np_communication_id);
+CREATE UNIQUE INDEX user_trophy_sets ON psn_user_trophy_sets (np_id, np_communication_id);
+
+INSERT INTO users (id, username, email, hashed_password, signature, avatar_url, privilege)
+VALUES (1, 'adminuser', 'admin@pixelshare', '$2y$06$z6K5TMA2TQbls77he7cEsOQQ4ekgCNvuxkg6eSKdHHLO9u6sY9d3C', 'AdminUser', 'ac.jpg', 9),
+(2, 'testtest1', 'test123@test123', '$2y$06$z6K5TMA2TQbls77he7cEsOQQ4ekgCNvuxkg6eSKdHHLO9u6sY9d3C', 'AdminUser', 'ac.jpg', 0),
+(3, 'testtest2', 'test223@test123', '$2y$06$z6K5TMA2TQbls77he7cEsOQQ4ekgCNvuxkg6eSKdHHLO9u6sY9d3C', 'AdminUser', 'ac.jpg', 1),
+(4, 'testtest3', 'test323@test123', '$2y$06$z6K5TMA2TQbls77he7cEsOQQ4ekgCNvuxkg6eSKdHHLO9u6sY9d3C', 'AdminUser', 'ac.jpg', 2);
+
+INSERT INTO relations (id, friends)
+VALUES (1, ARRAY[2,3,4]);
+
+INSERT INTO categories (id, name, thumbnail)
+VALUES (1, 'General', 'category_default.png');
+
+INSERT INTO categories (id, name, thumbnail)
+VALUES (2, 'Announcement', 'category_default.png'),
+(3, 'Armored Core', 'ac.jpg'),
+(4, 'Ace Combat', 'ace.jpg'),
+(5, 'Persona', 'persona.jpg');
+
+INSERT INTO talks (id, name, description, owner, admin, users)
+VALUES (1, 'general', 'ac.jpg', 1, ARRAY [1], ARRAY [1]),
+(2, 'special', 'ac.jpg', 1, ARRAY [1], ARRAY [1]),
+(3, 'test1', 'ac.jpg', 1, ARRAY [1], ARRAY [1]),
+(4, 'test2', 'ac.jpg', 1, ARRAY [1], ARRAY [1]),
+(5, 'test3', 'ac.jpg', 1, ARRAY [1], ARRAY [1]);
+
+INSERT INTO topics (id, user_id, category_id, title, body, thumbnail)
+VALUES (1, 1, 1, 'Welcome To PixelShare', 'PixelShare is a gaming oriented community.', '');
+
+INSERT INTO posts (id, user_id, topic_id, category_id, post_content)
+VALUES (1, 1, 1, 1, 'First Reply Only to stop cache build from complaining');
@@ -0,0 +1,7 @@
+DROP TRIGGER posts_search_vector_trigger ON posts;
+DROP TRIGGER topics_search_vector_trigger ON topics;
+DROP FUNCTION posts_search_vector_update();
+DROP FUNCTION topics_search_vector_update();
+
+ALTER TABLE posts DROP COLUMN search_vector;
+ALTER TABLE topics DROP COLUMN search_vector;
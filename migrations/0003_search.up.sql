@@ -0,0 +1,34 @@
+ALTER TABLE topics ADD COLUMN search_vector TSVECTOR;
+ALTER TABLE posts ADD COLUMN search_vector TSVECTOR;
+
+CREATE FUNCTION topics_search_vector_update() RETURNS TRIGGER AS $$
+BEGIN
+    NEW.search_vector :=
+        setweight(to_tsvector('simple', NEW.title), 'A') ||
+        setweight(to_tsvector('simple', NEW.body), 'B');
+    RETURN NEW;
+END
+$$ LANGUAGE plpgsql;
+
+CREATE FUNCTION posts_search_vector_update() RETURNS TRIGGER AS $$
+BEGIN
+    NEW.search_vector := to_tsvector('simple', NEW.post_content);
+    RETURN NEW;
+END
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER topics_search_vector_trigger
+    BEFORE INSERT OR UPDATE OF title, body ON topics
+    FOR EACH ROW EXECUTE PROCEDURE topics_search_vector_update();
+
+CREATE TRIGGER posts_search_vector_trigger
+    BEFORE INSERT OR UPDATE OF post_content ON posts
+    FOR EACH ROW EXECUTE PROCEDURE posts_search_vector_update();
+
+UPDATE topics SET search_vector =
+    setweight(to_tsvector('simple', title), 'A') ||
+    setweight(to_tsvector('simple', body), 'B');
+UPDATE posts SET search_vector = to_tsvector('simple', post_content);
+
+CREATE INDEX topics_search_vector_idx ON topics USING GIN (search_vector);
+CREATE INDEX posts_search_vector_idx ON posts USING GIN (search_vector);
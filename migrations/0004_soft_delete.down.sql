@@ -0,0 +1,6 @@
+ALTER TABLE posts
+    DROP COLUMN deleted_by,
+    DROP COLUMN deleted_at;
+ALTER TABLE topics
+    DROP COLUMN deleted_by,
+    DROP COLUMN deleted_at;
@@ -0,0 +1,6 @@
+ALTER TABLE topics
+    ADD COLUMN deleted_at TIMESTAMP,
+    ADD COLUMN deleted_by OID;
+ALTER TABLE posts
+    ADD COLUMN deleted_at TIMESTAMP,
+    ADD COLUMN deleted_by OID;
@@ -0,0 +1,24 @@
+-- a revision is the content of topic/post before an edit.
+-- edited_by and edited_at are the user made the edit and the time of it.
+CREATE TABLE topic_revisions
+(
+id              SERIAL        NOT NULL PRIMARY KEY,
+topic_id        OID           NOT NULL,
+title           VARCHAR(1024) NOT NULL,
+body            VARCHAR(1024) NOT NULL,
+thumbnail       VARCHAR(1024) NOT NULL,
+edited_by       OID           NOT NULL,
+edited_at       TIMESTAMP     NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE TABLE post_revisions
+(
+id              SERIAL        NOT NULL PRIMARY KEY,
+post_id         OID           NOT NULL,
+post_content    VARCHAR(1024) NOT NULL,
+edited_by       OID           NOT NULL,
+edited_at       TIMESTAMP     NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX topic_revisions_topic_id_idx ON topic_revisions (topic_id);
+CREATE INDEX post_revisions_post_id_idx ON post_revisions (post_id);
@@ -0,0 +1,11 @@
+UPDATE users SET privilege = 9
+WHERE id IN (SELECT ur.user_id FROM user_roles ur JOIN roles r ON r.id = ur.role_id
+    WHERE r.name = 'super_admin' AND ur.category_id IS NULL);
+
+UPDATE users SET privilege = 3
+WHERE privilege = 2 AND id IN (SELECT ur.user_id FROM user_roles ur JOIN roles r ON r.id = ur.role_id
+    WHERE r.name = 'admin' AND ur.category_id IS NULL);
+
+DROP TABLE IF EXISTS user_roles;
+DROP TABLE IF EXISTS role_permissions;
+DROP TABLE IF EXISTS roles;
@@ -0,0 +1,48 @@
+CREATE TABLE roles
+(
+id              SERIAL        NOT NULL PRIMARY KEY,
+name            VARCHAR(64)   NOT NULL UNIQUE
+);
+
+CREATE TABLE role_permissions
+(
+role_id         INT4          NOT NULL REFERENCES roles (id) ON DELETE CASCADE,
+permission      VARCHAR(64)   NOT NULL,
+PRIMARY KEY (role_id, permission)
+);
+
+-- role is granted in all categories when category_id is null.
+CREATE TABLE user_roles
+(
+user_id         OID           NOT NULL,
+role_id         INT4          NOT NULL REFERENCES roles (id) ON DELETE CASCADE,
+category_id     OID
+);
+
+CREATE UNIQUE INDEX user_roles_unique_idx ON user_roles (user_id, role_id, COALESCE(category_id, 0));
+
+INSERT INTO roles (name) VALUES ('moderator'), ('admin'), ('super_admin');
+
+INSERT INTO role_permissions (role_id, permission)
+SELECT r.id, p.permission FROM roles r
+CROSS JOIN (VALUES
+    ('topic.edit_any'), ('topic.lock'), ('topic.hide'), ('topic.delete_any'),
+    ('post.edit_any'), ('post.lock'), ('post.delete_any')
+) AS p (permission);
+
+INSERT INTO role_permissions (role_id, permission)
+SELECT r.id, p.permission FROM roles r
+CROSS JOIN (VALUES ('category.manage'), ('user.manage')) AS p (permission)
+WHERE r.name IN ('admin', 'super_admin');
+
+INSERT INTO role_permissions (role_id, permission)
+SELECT r.id, p.permission FROM roles r
+CROSS JOIN (VALUES ('role.manage'), ('psn.admin')) AS p (permission)
+WHERE r.name = 'super_admin';
+
+-- privilege 3 to 8 were admins and 9 was super admin. privilege only keeps the account state from now on.
+INSERT INTO user_roles (user_id, role_id)
+SELECT u.id, r.id FROM users u JOIN roles r
+ON (r.name = 'super_admin' AND u.privilege >= 9) OR (r.name = 'admin' AND u.privilege BETWEEN 3 AND 8);
+
+UPDATE users SET privilege = 2 WHERE privilege > 2;
@@ -0,0 +1,3 @@
+ALTER TABLE categories
+    DROP COLUMN IF EXISTS role,
+    DROP COLUMN IF EXISTS visibility;
@@ -0,0 +1,5 @@
+-- visibility 0 is public, 1 is members only and 2 is only visible to users with the role.
+-- moderators of category are users granted the moderator role in it.
+ALTER TABLE categories
+    ADD COLUMN visibility OID NOT NULL DEFAULT 0,
+    ADD COLUMN role VARCHAR(64) REFERENCES roles (name) ON UPDATE CASCADE ON DELETE SET NULL;
@@ -0,0 +1,5 @@
+DROP INDEX IF EXISTS categories_parent_id;
+
+ALTER TABLE categories
+    DROP COLUMN IF EXISTS position,
+    DROP COLUMN IF EXISTS parent_id;
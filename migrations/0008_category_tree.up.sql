@@ -0,0 +1,6 @@
+-- top level categories have null parent_id. position orders categories under the same parent.
+ALTER TABLE categories
+    ADD COLUMN parent_id OID REFERENCES categories (id) ON DELETE SET NULL,
+    ADD COLUMN position OID NOT NULL DEFAULT 0;
+
+CREATE INDEX categories_parent_id ON categories (parent_id);
@@ -0,0 +1,8 @@
+CREATE TABLE topic_tags
+(
+topic_id        OID           NOT NULL,
+tag             VARCHAR(32)   NOT NULL,
+PRIMARY KEY (topic_id, tag)
+);
+
+CREATE INDEX topic_tags_tag ON topic_tags (tag);
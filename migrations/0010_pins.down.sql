@@ -0,0 +1,3 @@
+DELETE FROM role_permissions WHERE permission = 'topic.pin';
+
+DROP TABLE IF EXISTS topic_pins;
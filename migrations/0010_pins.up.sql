@@ -0,0 +1,14 @@
+-- category pins are kept when topic is moved to another category.
+CREATE TABLE topic_pins
+(
+topic_id        OID           NOT NULL,
+is_global       BOOLEAN       NOT NULL DEFAULT FALSE,
+pinned_by       OID           NOT NULL,
+pinned_at       TIMESTAMP     NOT NULL DEFAULT CURRENT_TIMESTAMP,
+expires_at      TIMESTAMP,
+PRIMARY KEY (topic_id, is_global)
+);
+
+INSERT INTO role_permissions (role_id, permission)
+SELECT r.id, 'topic.pin' FROM roles r
+WHERE r.name IN ('moderator', 'admin', 'super_admin');
@@ -0,0 +1,3 @@
+INSERT INTO role_permissions (role_id, permission)
+SELECT r.id, 'topic.move' FROM roles r
+WHERE r.name IN ('moderator', 'admin', 'super_admin');
@@ -0,0 +1,17 @@
+CREATE TABLE topic_reactions
+(
+topic_id        OID           NOT NULL,
+user_id         OID           NOT NULL,
+reaction        VARCHAR(32)   NOT NULL,
+created_at      TIMESTAMP     NOT NULL DEFAULT CURRENT_TIMESTAMP,
+PRIMARY KEY (topic_id, user_id, reaction)
+);
+
+CREATE TABLE post_reactions
+(
+post_id         OID           NOT NULL,
+user_id         OID           NOT NULL,
+reaction        VARCHAR(32)   NOT NULL,
+created_at      TIMESTAMP     NOT NULL DEFAULT CURRENT_TIMESTAMP,
+PRIMARY KEY (post_id, user_id, reaction)
+);
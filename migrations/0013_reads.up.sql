@@ -0,0 +1,17 @@
+CREATE TABLE topic_reads
+(
+user_id             OID           NOT NULL,
+topic_id            OID           NOT NULL,
+last_read_post_id   OID,
+read_count          OID           NOT NULL DEFAULT 0,
+read_at             TIMESTAMP     NOT NULL,
+PRIMARY KEY (user_id, topic_id)
+);
+
+CREATE TABLE category_reads
+(
+user_id             OID           NOT NULL,
+category_id         OID           NOT NULL,
+read_at             TIMESTAMP     NOT NULL,
+PRIMARY KEY (user_id, category_id)
+);
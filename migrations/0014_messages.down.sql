@@ -0,0 +1,28 @@
+CREATE TABLE public_messages1
+(
+talk_id     OID             NOT NULL PRIMARY KEY,
+time        TIMESTAMP       NOT NULL DEFAULT CURRENT_TIMESTAMP,
+text        VARCHAR(1024)   NOT NULL
+);
+
+CREATE TABLE private_messages1
+(
+from_id     OID             NOT NULL,
+to_id       OID             NOT NULL PRIMARY KEY,
+time        TIMESTAMP       NOT NULL DEFAULT CURRENT_TIMESTAMP,
+text        VARCHAR(1024)   NOT NULL
+);
+
+CREATE INDEX pub_message_time_order ON public_messages1 (time DESC);
+CREATE INDEX prv_message_time_order ON private_messages1 (time DESC);
+
+-- old tables only hold the last message of every talk and receiver.
+INSERT INTO public_messages1 (talk_id, time, text)
+SELECT DISTINCT ON (talk_id) talk_id, time, text FROM public_messages
+WHERE deleted_at IS NULL ORDER BY talk_id, time DESC;
+INSERT INTO private_messages1 (from_id, to_id, time, text)
+SELECT DISTINCT ON (to_id) from_id, to_id, time, text FROM private_messages
+WHERE deleted_at IS NULL ORDER BY to_id, time DESC;
+
+DROP TABLE IF EXISTS private_messages;
+DROP TABLE IF EXISTS public_messages;
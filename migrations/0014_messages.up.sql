@@ -0,0 +1,34 @@
+CREATE TABLE public_messages
+(
+id          BIGSERIAL       PRIMARY KEY,
+talk_id     OID             NOT NULL,
+user_id     OID             NOT NULL,
+text        VARCHAR(1024)   NOT NULL,
+time        TIMESTAMP       NOT NULL DEFAULT CURRENT_TIMESTAMP,
+edited_at   TIMESTAMP,
+deleted_at  TIMESTAMP
+);
+
+CREATE TABLE private_messages
+(
+id          BIGSERIAL       PRIMARY KEY,
+from_id     OID             NOT NULL,
+to_id       OID             NOT NULL,
+text        VARCHAR(1024)   NOT NULL,
+time        TIMESTAMP       NOT NULL DEFAULT CURRENT_TIMESTAMP,
+edited_at   TIMESTAMP,
+deleted_at  TIMESTAMP
+);
+
+-- history is paged by message id. private messages of a conversation share the same ordered user pair.
+CREATE INDEX public_messages_talk_id ON public_messages (talk_id, id);
+CREATE INDEX private_messages_conversation ON private_messages (LEAST(from_id, to_id), GREATEST(from_id, to_id), id);
+
+-- sender of old public messages is unknown.
+INSERT INTO public_messages (talk_id, user_id, text, time)
+SELECT talk_id, 0, text, time FROM public_messages1;
+INSERT INTO private_messages (from_id, to_id, text, time)
+SELECT from_id, to_id, text, time FROM private_messages1;
+
+DROP TABLE public_messages1;
+DROP TABLE private_messages1;
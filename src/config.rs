@@ -6,13 +6,46 @@ pub(crate) fn conf_admin(cfg: &mut ServiceConfig) {
     cfg.service(
         web::scope("/admin")
             .service(web::resource("/user").route(web::post().to(router::admin::update_user)))
+            .service(
+                web::resource("/user/{id}/roles").route(web::get().to(router::admin::user_roles)),
+            )
+            .service(web::resource("/role/grant").route(web::post().to(router::admin::grant_role)))
+            .service(
+                web::resource("/role/revoke").route(web::post().to(router::admin::revoke_role)),
+            )
             .service(web::resource("/post").route(web::post().to(router::admin::update_post)))
             .service(web::resource("/topic").route(web::post().to(router::admin::update_topic)))
+            .service(
+                web::resource("/topic/tags").route(web::post().to(router::admin::set_topic_tags)),
+            )
+            .service(web::resource("/topic/move").route(web::post().to(router::admin::move_topic)))
+            .service(
+                web::resource("/topic/merge").route(web::post().to(router::admin::merge_topic)),
+            )
+            .service(
+                web::resource("/topic/split").route(web::post().to(router::admin::split_topic)),
+            )
+            .service(
+                web::resource("/post/restore/{pid}")
+                    .route(web::post().to(router::admin::restore_post)),
+            )
+            .service(
+                web::resource("/topic/restore/{tid}")
+                    .route(web::post().to(router::admin::restore_topic)),
+            )
+            .service(
+                web::resource("/post/rollback/{rid}")
+                    .route(web::post().to(router::admin::rollback_post)),
+            )
+            .service(
+                web::resource("/topic/rollback/{rid}")
+                    .route(web::post().to(router::admin::rollback_topic)),
+            )
             .service(
                 web::scope("/category")
                     .service(
                         web::resource("/remove/{category_id}")
-                            .route(web::get().to(router::admin::remove_category)),
+                            .route(web::post().to(router::admin::remove_category)),
                     )
                     .service(
                         web::resource("/update")
@@ -28,6 +61,8 @@ pub(crate) fn conf_auth(cfg: &mut ServiceConfig) {
         web::scope("/auth")
             .service(web::resource("/register").route(web::post().to(router::auth::register)))
             .service(web::resource("/login").route(web::post().to(router::auth::login)))
+            .service(web::resource("/refresh").route(web::post().to(router::auth::refresh)))
+            .service(web::resource("/logout").route(web::post().to(router::auth::logout)))
             .service(
                 web::resource("/activation/mail")
                     .route(web::post().to(router::auth::add_activation_mail)),
@@ -35,6 +70,15 @@ pub(crate) fn conf_auth(cfg: &mut ServiceConfig) {
             .service(
                 web::resource("/activation/mail/{uuid}")
                     .route(web::get().to(router::auth::activate_by_mail)),
+            )
+            .service(
+                web::resource("/reset/request")
+                    .route(web::post().to(router::auth::request_password_reset)),
+            )
+            .service(
+                web::resource("/reset/confirm/{uuid}")
+                    .route(web::get().to(router::auth::password_reset_page))
+                    .route(web::post().to(router::auth::confirm_password_reset)),
             ),
     );
 }
@@ -50,27 +94,57 @@ pub(crate) fn conf_psn(cfg: &mut ServiceConfig) {
     );
 }
 
-pub(crate) fn conf_test(cfg: &mut ServiceConfig) {
-    cfg.service(
-        web::scope("/test")
-            .service(web::resource("/raw").route(web::get().to(router::test::raw)))
-            .service(web::resource("/raw_cache").route(web::get().to(router::test::raw_cache)))
-            .service(web::resource("/topic").route(web::get().to(router::test::add_topic)))
-            .service(web::resource("/post").route(web::get().to(router::test::add_post))),
-    );
+pub(crate) fn conf_test(cfg: &mut ServiceConfig, capture_mails: bool) {
+    let scope = web::scope("/test")
+        .service(web::resource("/raw").route(web::get().to(router::test::raw)))
+        .service(web::resource("/raw_cache").route(web::get().to(router::test::raw_cache)))
+        .service(web::resource("/topic").route(web::get().to(router::test::add_topic)))
+        .service(web::resource("/post").route(web::get().to(router::test::add_post)));
+
+    // only mounted with memory mail transport.
+    if capture_mails {
+        cfg.service(
+            scope.service(web::resource("/mails").route(web::get().to(router::test::mails))),
+        );
+    } else {
+        cfg.service(scope);
+    }
 }
 
 pub(crate) fn conf_comm(cfg: &mut ServiceConfig) {
     cfg.service(web::resource("/categories").route(web::get().to(router::category::query_handler)))
+        .service(
+            web::resource("/categories/read/{cid}")
+                .route(web::post().to(router::category::mark_read)),
+        )
+        .service(web::resource("/search").route(web::get().to(router::search::query_handler)))
+        .service(web::resource("/reaction").route(web::post().to(router::reaction::react)))
         .service(
             web::scope("/post")
                 .service(web::resource("/update").route(web::post().to(router::post::update)))
+                .service(web::resource("/delete/{pid}").route(web::post().to(router::post::delete)))
+                .service(
+                    web::resource("/{pid}/revisions").route(web::get().to(router::post::revisions)),
+                )
+                .service(
+                    web::resource("/{pid}/diff").route(web::get().to(router::post::revision_diff)),
+                )
                 .service(web::resource("/{pid}").route(web::get().to(router::post::get)))
                 .service(web::resource("").route(web::post().to(router::post::add))),
         )
         .service(
             web::scope("/topic")
                 .service(web::resource("/update").route(web::post().to(router::topic::update)))
+                .service(
+                    web::resource("/delete/{tid}").route(web::post().to(router::topic::delete)),
+                )
+                .service(
+                    web::resource("/{tid}/revisions")
+                        .route(web::get().to(router::topic::revisions)),
+                )
+                .service(
+                    web::resource("/{tid}/diff").route(web::get().to(router::topic::revision_diff)),
+                )
                 .service(
                     web::resource("")
                         .route(web::get().to(router::topic::query_handler))
@@ -1,106 +1,324 @@
-use crate::handler::db::MyPostgresPool;
+use crate::handler::{db::MyPostgresPool, permission::UserPerm};
 use crate::model::{
-    category::{Category, CategoryRequest},
+    category::{Category, CategoryRemoveReport, CategoryRemoveRequest, CategoryRequest},
     errors::ResError,
+    permission::{Permission, PermissionSet, RoleRequest, Scope, UserRole},
+    pin::TopicPin,
     post::{Post, PostRequest},
-    topic::{Topic, TopicRequest},
+    tag::{TopicTagRequest, TopicTags},
+    topic::{
+        Topic, TopicMergeRequest, TopicMoveReport, TopicMoveRequest, TopicRequest,
+        TopicSplitRequest,
+    },
     user::UpdateRequest,
 };
 
 impl MyPostgresPool {
     pub(crate) async fn admin_add_category(
         &self,
-        self_level: u32,
+        perm: &UserPerm,
         req: CategoryRequest,
     ) -> Result<Vec<Category>, ResError> {
-        update_category_check(self_level, &req)?;
+        let perms = perm.resolve(Scope::Global).await?;
+        perms.require(Permission::CategoryManage)?;
+        perms.require_some(&req.moderators, Permission::RoleManage)?;
         self.add_category(req).await
     }
 
+    // category managers could only change name, thumbnail and position of their own category.
+    // visibility and parent are changed with global permission and moderators are changed by role managers.
     pub(crate) async fn admin_update_category(
         &self,
-        self_level: u32,
+        perm: &UserPerm,
         req: CategoryRequest,
     ) -> Result<Vec<Category>, ResError> {
-        update_category_check(self_level, &req)?;
+        let cid = req.id.ok_or(ResError::BadRequest)?;
+
+        let perms = perm.resolve(Scope::Category(cid)).await?;
+        perms.require(Permission::CategoryManage)?;
+        perms.require_some(&req.moderators, Permission::RoleManage)?;
+
+        if req.visibility.is_some() || req.role.is_some() || req.parent_id.is_some() {
+            perm.require(Permission::CategoryManage, Scope::Global)
+                .await?;
+        }
+
         self.update_category(req).await
     }
 
+    // category removal affects all topics in it so only global permission is accepted.
     pub(crate) async fn admin_remove_category(
         &self,
+        perm: &UserPerm,
         cid: u32,
-        self_level: u32,
-    ) -> Result<(), ResError> {
-        check_admin_level(&Some(1), self_level, 9)?;
-        self.remove_category(cid).await
+        req: CategoryRemoveRequest,
+    ) -> Result<CategoryRemoveReport, ResError> {
+        perm.require(Permission::CategoryManage, Scope::Global)
+            .await?;
+        self.remove_category(cid, perm.jwt.user_id, req).await
     }
 
     pub(crate) async fn admin_update_topic(
         &self,
-        self_level: u32,
+        perm: &UserPerm,
         t: &TopicRequest,
-    ) -> Result<Vec<Topic>, ResError> {
-        update_topic_check(self_level, &t)?;
-        self.update_topic(t).await
+    ) -> Result<(Vec<Topic>, Option<TopicPin>, Option<bool>), ResError> {
+        let tid = t.id.ok_or(ResError::BadRequest)?;
+        let perms = perm.resolve(Scope::Topic(tid)).await?;
+        update_topic_check(&perms, &t)?;
+
+        // global pins are shown in all categories so they need global permission.
+        if let Some(pin) = t.pin.as_ref() {
+            if pin.scope.is_global() {
+                perm.require(Permission::TopicPin, Scope::Global).await?;
+            }
+        }
+
+        // old visibility is compared with the updated one to add or remove topic from cache lists.
+        let was_visible = match t.is_visible {
+            Some(_) => self
+                .get_topics(&[tid])
+                .await?
+                .0
+                .first()
+                .map(|t| t.is_visible),
+            None => None,
+        };
+
+        let topics = if t.is_pin_only() {
+            self.get_topics(&[tid]).await?.0
+        } else {
+            self.update_topic(t, perm.jwt.user_id).await?
+        };
+
+        let visible = match (was_visible, topics.first()) {
+            (Some(old), Some(topic)) if old != topic.is_visible => Some(topic.is_visible),
+            _ => None,
+        };
+
+        let pin = match t.pin.as_ref() {
+            Some(pin) => {
+                let topic = topics.first().ok_or(ResError::NotFound)?;
+                Some(self.set_topic_pin(topic, pin, perm.jwt.user_id).await?)
+            }
+            None => None,
+        };
+
+        Ok((topics, pin, visible))
+    }
+
+    pub(crate) async fn admin_move_topic(
+        &self,
+        perm: &UserPerm,
+        req: TopicMoveRequest,
+    ) -> Result<TopicMoveReport, ResError> {
+        perm.require(Permission::TopicMove, Scope::Topic(req.topic_id))
+            .await?;
+        perm.require(Permission::TopicMove, Scope::Category(req.category_id))
+            .await?;
+        self.move_topic(&req).await
+    }
+
+    pub(crate) async fn admin_merge_topic(
+        &self,
+        perm: &UserPerm,
+        req: TopicMergeRequest,
+    ) -> Result<TopicMoveReport, ResError> {
+        perm.require(Permission::TopicMove, Scope::Topic(req.topic_id))
+            .await?;
+        perm.require(Permission::TopicMove, Scope::Topic(req.target_id))
+            .await?;
+        self.merge_topic(&req, perm.jwt.user_id).await
+    }
+
+    pub(crate) async fn admin_split_topic(
+        &self,
+        perm: &UserPerm,
+        req: TopicSplitRequest,
+    ) -> Result<TopicMoveReport, ResError> {
+        perm.require(Permission::TopicMove, Scope::Topic(req.topic_id))
+            .await?;
+        if let Some(cid) = req.category_id {
+            perm.require(Permission::TopicMove, Scope::Category(cid))
+                .await?;
+        }
+        self.split_topic(&req).await
+    }
+
+    // tags are curated by users could edit the topic.
+    pub(crate) async fn admin_set_topic_tags(
+        &self,
+        perm: &UserPerm,
+        req: TopicTagRequest,
+    ) -> Result<(Vec<String>, TopicTags), ResError> {
+        perm.require(Permission::TopicEditAny, Scope::Topic(req.topic_id))
+            .await?;
+        self.set_topic_tags(&req).await
     }
 
     pub(crate) async fn admin_update_post(
         &self,
-        self_level: u32,
+        perm: &UserPerm,
         p: PostRequest,
     ) -> Result<Vec<Post>, ResError> {
-        update_post_check(self_level, &p)?;
-        self.update_post(p).await
+        let pid = p.id.ok_or(ResError::BadRequest)?;
+        let perms = perm.resolve(Scope::Post(pid)).await?;
+        update_post_check(&perms, &p)?;
+        self.update_post(p, perm.jwt.user_id).await
     }
 
+    // roll back topic to the content of revision. the content before roll back is stored as a new revision.
+    pub(crate) async fn admin_rollback_topic(
+        &self,
+        perm: &UserPerm,
+        rid: i32,
+    ) -> Result<Vec<Topic>, ResError> {
+        let r = self.get_topic_revisions_by_id(&[rid]).await?;
+        let r = r.into_iter().next().ok_or(ResError::NotFound)?;
+
+        perm.require(Permission::TopicEditAny, Scope::Topic(r.topic_id))
+            .await?;
+
+        let req = TopicRequest {
+            id: Some(r.topic_id),
+            user_id: None,
+            category_id: 0,
+            title: Some(r.title),
+            body: Some(r.body),
+            thumbnail: Some(r.thumbnail),
+            is_locked: None,
+            is_visible: None,
+            pin: None,
+        };
+
+        self.update_topic(&req, perm.jwt.user_id).await
+    }
+
+    pub(crate) async fn admin_rollback_post(
+        &self,
+        perm: &UserPerm,
+        rid: i32,
+    ) -> Result<Vec<Post>, ResError> {
+        let r = self.get_post_revisions_by_id(&[rid]).await?;
+        let r = r.into_iter().next().ok_or(ResError::NotFound)?;
+
+        perm.require(Permission::PostEditAny, Scope::Post(r.post_id))
+            .await?;
+
+        let req = PostRequest {
+            id: Some(r.post_id),
+            user_id: None,
+            topic_id: None,
+            category_id: 0,
+            post_id: None,
+            post_content: Some(r.post_content),
+            is_locked: None,
+        };
+
+        self.update_post(req, perm.jwt.user_id).await
+    }
+
+    pub(crate) async fn admin_restore_topic(
+        &self,
+        perm: &UserPerm,
+        tid: u32,
+    ) -> Result<Vec<Topic>, ResError> {
+        perm.require(Permission::TopicDeleteAny, Scope::Topic(tid))
+            .await?;
+        self.restore_topic(tid).await
+    }
+
+    pub(crate) async fn admin_restore_post(
+        &self,
+        perm: &UserPerm,
+        pid: u32,
+    ) -> Result<Vec<Post>, ResError> {
+        perm.require(Permission::PostDeleteAny, Scope::Post(pid))
+            .await?;
+        self.restore_post(pid).await
+    }
+
+    // roles could only be granted in the scope self has role.manage permission.
+    pub(crate) async fn admin_grant_role(
+        &self,
+        perm: &UserPerm,
+        req: RoleRequest,
+    ) -> Result<Vec<UserRole>, ResError> {
+        perm.require(Permission::RoleManage, role_scope(&req))
+            .await?;
+        self.grant_role(&req).await
+    }
+
+    pub(crate) async fn admin_revoke_role(
+        &self,
+        perm: &UserPerm,
+        req: RoleRequest,
+    ) -> Result<Vec<UserRole>, ResError> {
+        perm.require(Permission::RoleManage, role_scope(&req))
+            .await?;
+        self.revoke_role(&req).await
+    }
+
+    // privilege is the account state and only accepts 0 blocked, 1 inactive and 2 active.
+    // target user can't have any global permission self doesn't have.
+    // user managers could only be updated by who could also manage roles.
     pub(crate) async fn update_user_check(
         &self,
-        self_level: u32,
+        perm: &UserPerm,
         u: UpdateRequest,
     ) -> Result<UpdateRequest, ResError> {
+        let perms = perm.resolve(Scope::Global).await?;
+        perms.require(Permission::UserManage)?;
+
+        if u.privilege.map(|p| p > 2).unwrap_or(false) {
+            return Err(ResError::BadRequestExplained(
+                "Privilege only accepts 0(blocked), 1(inactive) and 2(active). Use roles for admins"
+                    .into(),
+            ));
+        }
+
         let id = vec![u.id.as_ref().copied().unwrap_or(0)];
 
         let user = self.get_users(&id).await?;
         let user = user.first().ok_or(ResError::BadRequest)?;
 
-        if self_level <= user.privilege {
+        let target = self.get_permissions(user.id, Scope::Global).await?;
+        if !perms.contains_all(&target)
+            || (target.has(Permission::UserManage) && !perms.has(Permission::RoleManage))
+        {
             return Err(ResError::Unauthorized);
         }
 
-        check_admin_level(&u.privilege, self_level, 9).map(|_| u)
+        Ok(u)
     }
 }
 
 type QueryResult = Result<(), ResError>;
 
-fn update_category_check(lv: u32, req: &CategoryRequest) -> QueryResult {
-    check_admin_level(&req.name, lv, 3)?;
-    check_admin_level(&req.thumbnail, lv, 3)
-}
-
-fn update_topic_check(lv: u32, req: &TopicRequest) -> QueryResult {
-    check_admin_level(&req.title, lv, 3)?;
-    check_admin_level(&req.body, lv, 3)?;
-    check_admin_level(&req.thumbnail, lv, 3)?;
-    check_admin_level(&req.is_locked, lv, 2)
+fn update_topic_check(perms: &PermissionSet, req: &TopicRequest) -> QueryResult {
+    perms.require_some(&req.title, Permission::TopicEditAny)?;
+    perms.require_some(&req.body, Permission::TopicEditAny)?;
+    perms.require_some(&req.thumbnail, Permission::TopicEditAny)?;
+    perms.require_some(&req.is_locked, Permission::TopicLock)?;
+    perms.require_some(&req.is_visible, Permission::TopicHide)?;
+    perms.require_some(&req.pin, Permission::TopicPin)
 }
 
-fn update_post_check(lv: u32, req: &PostRequest) -> QueryResult {
-    check_admin_level(&req.topic_id, lv, 3)?;
-    check_admin_level(&req.post_id, lv, 3)?;
-    check_admin_level(&req.post_content, lv, 3)?;
-    check_admin_level(&req.is_locked, lv, 2)
+fn update_post_check(perms: &PermissionSet, req: &PostRequest) -> QueryResult {
+    // posts are moved between topics by merge and split so the cache is kept consistent.
+    if req.topic_id.is_some() {
+        return Err(ResError::BadRequestExplained(
+            "Use topic merge or split to move posts".to_owned(),
+        ));
+    }
+    perms.require_some(&req.post_id, Permission::PostEditAny)?;
+    perms.require_some(&req.post_content, Permission::PostEditAny)?;
+    perms.require_some(&req.is_locked, Permission::PostLock)
 }
 
-fn check_admin_level<T: Sized>(
-    t: &Option<T>,
-    self_admin_level: u32,
-    baseline_admin_level: u32,
-) -> Result<(), ResError> {
-    if let Some(_value) = t {
-        if self_admin_level < baseline_admin_level {
-            return Err(ResError::Unauthorized);
-        }
+fn role_scope(req: &RoleRequest) -> Scope {
+    match req.category_id {
+        Some(cid) => Scope::Category(cid),
+        None => Scope::Global,
     }
-    Ok(())
 }
@@ -1,11 +1,17 @@
+use std::future::Future;
+use std::pin::Pin;
+
 use actix_web::{dev, FromRequest, HttpRequest};
+use redis::{cmd, pipe};
 use tokio_postgres::types::{ToSql, Type};
 
 use crate::handler::{
     cache::MyRedisPool,
+    data::DataRc,
     db::{MyPostgresPool, ParseRowStream},
 };
 use crate::model::{
+    cache_schema::HashMapBrown,
     errors::ResError,
     user::{AuthRequest, AuthResponse, User},
 };
@@ -13,8 +19,13 @@ use crate::util::jwt::JwtPayLoad;
 
 pub type UserJwt = JwtPayLoad;
 
+// refresh token life in seconds.
+const REFRESH_TOKEN_LIFE: usize = 2_592_000;
+
 const USER_BY_NAME_EMAIL: &str = "SELECT * FROM users WHERE username=$1 OR email=$2";
 const USER_BY_NAME: &str = "SELECT * FROM users WHERE username=$1";
+const USER_BY_EMAIL: &str = "SELECT * FROM users WHERE email=$1";
+const UPDATE_PASSWORD: &str = "UPDATE users SET hashed_password=$1 WHERE id=$2 RETURNING *";
 const INSERT_USER: &str =
     "INSERT INTO users (id, username, email, hashed_password, avatar_url, signature)
     VALUES ($1, $2, $3, $4, $5, $6)
@@ -29,14 +40,23 @@ const INSERT_USER_TYPES: &[Type; 6] = &[
     Type::VARCHAR,
 ];
 
-// jwt token extractor from request
+// jwt token extractor from request.
+// the token generation is checked against redis so revoked tokens are rejected before expiring.
 impl FromRequest for JwtPayLoad {
     type Error = ResError;
-    type Future = futures::future::Ready<Result<UserJwt, Self::Error>>;
+    type Future = Pin<Box<dyn Future<Output = Result<UserJwt, Self::Error>>>>;
     type Config = ();
 
     fn from_request(req: &HttpRequest, _: &mut dev::Payload) -> Self::Future {
-        futures::future::ready(extract_jwt(req))
+        let jwt = extract_jwt(req);
+        let cache_pool = req.app_data::<DataRc<MyRedisPool>>().cloned();
+
+        Box::pin(async move {
+            let jwt = jwt?;
+            let cache_pool = cache_pool.ok_or(ResError::InternalServerError)?;
+            cache_pool.check_token_gen(&jwt).await?;
+            Ok(jwt)
+        })
     }
 }
 
@@ -104,7 +124,7 @@ impl MyPostgresPool {
             .await
     }
 
-    pub(crate) async fn login(&self, req: AuthRequest) -> Result<AuthResponse, ResError> {
+    pub(crate) async fn login(&self, req: AuthRequest) -> Result<User, ResError> {
         let pool = self.get().await?;
         let (cli, _) = &*pool;
 
@@ -123,19 +143,157 @@ impl MyPostgresPool {
 
         crate::util::hash::verify_password(req.password.as_str(), user.hashed_password.as_str())?;
 
-        let token = JwtPayLoad::new(user.id, user.privilege).sign()?;
+        Ok(user)
+    }
+
+    pub(crate) async fn get_user_by_email(&self, email: &str) -> Result<User, ResError> {
+        let pool = self.get().await?;
+        let (cli, _) = &*pool;
+
+        let st = cli.prepare_typed(USER_BY_EMAIL, &[Type::VARCHAR]).await?;
+        let params: [&(dyn ToSql + Sync); 1] = [&email];
+
+        cli.query_raw(&st, params.iter().map(|s| *s as _))
+            .await?
+            .parse_row::<User>()
+            .await?
+            .pop()
+            .ok_or(ResError::NotFound)
+    }
+
+    pub(crate) async fn update_password(
+        &self,
+        uid: u32,
+        password: &str,
+    ) -> Result<Vec<User>, ResError> {
+        let hash = crate::util::hash::hash_password(password)?;
+
+        let pool = self.get().await?;
+        let (cli, _) = &*pool;
+
+        let st = cli
+            .prepare_typed(UPDATE_PASSWORD, &[Type::VARCHAR, Type::OID])
+            .await?;
+        let params: [&(dyn ToSql + Sync); 2] = [&hash, &uid];
 
-        Ok(AuthResponse { token, user })
+        cli.query_raw(&st, params.iter().map(|s| *s as _))
+            .await?
+            .parse_row()
+            .await
     }
 }
 
 impl MyRedisPool {
-    pub async fn get_uid_from_uuid(&self, uuid: &str) -> Result<u32, ResError> {
-        let hm = self.get_hash_map_brown(uuid).await?;
-        Ok(hm
-            .0
-            .get("user_id")
-            .ok_or(ResError::Unauthorized)?
-            .parse::<u32>()?)
+    // sign a short lived access token and store a new refresh token for user.
+    pub(crate) async fn generate_auth_response(
+        &self,
+        user: User,
+    ) -> Result<AuthResponse, ResError> {
+        let gen = self.get_token_gen(user.id).await?;
+        let token = JwtPayLoad::new(user.id, user.privilege, gen).sign()?;
+
+        let refresh_token = uuid::Uuid::new_v4().to_string();
+        let key = format!("refresh_token:{}", refresh_token);
+
+        let mut pip = pipe();
+        pip.atomic();
+        pip.cmd("HMSET")
+            .arg(key.as_str())
+            .arg(&[("user_id", user.id), ("gen", gen)])
+            .ignore()
+            .cmd("EXPIRE")
+            .arg(key.as_str())
+            .arg(REFRESH_TOKEN_LIFE)
+            .ignore();
+
+        let mut conn = self.get().await?.get_conn().clone();
+        pip.query_async::<_, ()>(&mut conn).await?;
+
+        Ok(AuthResponse {
+            token,
+            refresh_token,
+            user,
+        })
+    }
+
+    // refresh tokens are single use. return user id of the token and remove it from redis.
+    pub(crate) async fn take_refresh_token(&self, token: &str) -> Result<u32, ResError> {
+        let key = format!("refresh_token:{}", token);
+
+        let mut pip = pipe();
+        pip.atomic();
+        pip.cmd("HGETALL")
+            .arg(key.as_str())
+            .cmd("DEL")
+            .arg(key.as_str());
+
+        let mut conn = self.get().await?.get_conn().clone();
+        let (HashMapBrown(hm), _) = pip
+            .query_async::<_, (HashMapBrown<String, u32>, u32)>(&mut conn)
+            .await?;
+
+        let uid = *hm.get("user_id").ok_or(ResError::Unauthorized)?;
+        let gen = *hm.get("gen").ok_or(ResError::Unauthorized)?;
+
+        if gen != self.get_token_gen(uid).await? {
+            return Err(ResError::Unauthorized);
+        }
+
+        Ok(uid)
+    }
+
+    pub(crate) async fn get_token_gen(&self, uid: u32) -> Result<u32, ResError> {
+        let mut conn = self.get().await?.get_conn().clone();
+        cmd("GET")
+            .arg(&format!("user:{}:token_gen", uid))
+            .query_async::<_, Option<u32>>(&mut conn)
+            .await
+            .map(|gen| gen.unwrap_or(0))
+            .map_err(Into::into)
+    }
+
+    // increase user's token generation so all issued access and refresh tokens are rejected.
+    pub(crate) async fn revoke_tokens(&self, uid: u32) -> Result<(), ResError> {
+        let mut conn = self.get().await?.get_conn().clone();
+        cmd("INCR")
+            .arg(&format!("user:{}:token_gen", uid))
+            .query_async::<_, u32>(&mut conn)
+            .await
+            .map(|_| ())
+            .map_err(Into::into)
+    }
+
+    async fn check_token_gen(&self, jwt: &JwtPayLoad) -> Result<(), ResError> {
+        if jwt.gen == self.get_token_gen(jwt.user_id).await? {
+            Ok(())
+        } else {
+            Err(ResError::Unauthorized)
+        }
+    }
+
+    // activation and password reset uuid are single use. return user id of the uuid and remove
+    // it from redis.
+    pub(crate) async fn take_uid_from_activation_uuid(&self, uuid: &str) -> Result<u32, ResError> {
+        self.take_uid_from_mail_key(format!("activation:{}", uuid))
+            .await
+    }
+
+    pub(crate) async fn take_uid_from_reset_uuid(&self, uuid: &str) -> Result<u32, ResError> {
+        self.take_uid_from_mail_key(format!("reset:{}", uuid)).await
+    }
+
+    async fn take_uid_from_mail_key(&self, key: String) -> Result<u32, ResError> {
+        let mut pip = pipe();
+        pip.atomic();
+        pip.cmd("HGET")
+            .arg(key.as_str())
+            .arg("user_id")
+            .cmd("DEL")
+            .arg(key.as_str());
+
+        let mut conn = self.get().await?.get_conn().clone();
+        let (uid, _) = pip.query_async::<_, (Option<u32>, u32)>(&mut conn).await?;
+
+        uid.ok_or(ResError::Unauthorized)
     }
 }
@@ -6,8 +6,11 @@ use chrono::NaiveDateTime;
 use redis::{aio::MultiplexedConnection, cmd, pipe, Pipeline};
 use redis_tang::{Builder, Pool, PoolRef, RedisManager};
 
+use crate::handler::pin::remove_pins;
+use crate::handler::tag::{add_tags, remove_tags, reply_tags, topic_tags};
+
 use crate::model::{
-    cache_schema::{HashMapBrown, RefTo},
+    cache_schema::RefTo,
     category::Category,
     common::{SelfId, SelfIdString, SelfUserId},
     errors::ResError,
@@ -17,7 +20,9 @@ use crate::model::{
 };
 
 // page offsets of list query
-const LIMIT: usize = 20;
+pub(crate) const LIMIT: usize = 20;
+// ids read at once when paging a list with hidden topics left out.
+const VISIBLE_SCAN_CHUNK: usize = 200;
 // use LEX_BASE minus pid and tid before adding to zrange.
 const LEX_BASE: u32 = std::u32::MAX;
 
@@ -79,6 +84,16 @@ impl MyRedisPool {
             return Ok(());
         }
 
+        Self::add_mail_cache(conn, uid, format!("activation:{}", uuid), mail).await
+    }
+
+    // push mail to mail_queue and store the uuid key of mail link with user id.
+    pub(crate) async fn add_mail_cache(
+        mut conn: MultiplexedConnection,
+        uid: u32,
+        key: String,
+        mail: String,
+    ) -> Result<(), ResError> {
         let mut pip = pipe();
         pip.atomic();
         pip.cmd("ZADD")
@@ -87,30 +102,18 @@ impl MyRedisPool {
             .arg(mail.as_str())
             .ignore()
             .cmd("HSET")
-            .arg(uuid.as_str())
+            .arg(key.as_str())
             .arg("user_id")
             .arg(uid)
             .ignore()
             .cmd("EXPIRE")
-            .arg(uuid.as_str())
+            .arg(key.as_str())
             .arg(MAIL_LIFE)
             .ignore();
 
         pip.query_async(&mut conn).await.map_err(Into::into)
     }
 
-    pub(crate) async fn get_hash_map_brown(
-        &self,
-        key: &str,
-    ) -> Result<HashMapBrown<String, String>, ResError> {
-        let mut conn = self.get().await?.get_conn().clone();
-        cmd("HGETALL")
-            .arg(key)
-            .query_async(&mut conn)
-            .await
-            .map_err(Into::into)
-    }
-
     pub(crate) async fn get_queue(&self, key: &str) -> Result<String, ResError> {
         let mut pip = pipe();
         pip.atomic();
@@ -128,20 +131,6 @@ impl MyRedisPool {
 
         s.pop().ok_or(ResError::NoCache)
     }
-
-    pub(crate) fn del_cache(
-        mut conn: MultiplexedConnection,
-        key: String,
-    ) -> Pin<Box<dyn Future<Output = Result<(), ResError>> + Send>> {
-        Box::pin(async move {
-            let conn = &mut conn;
-            cmd("del")
-                .arg(key.as_str())
-                .query_async(conn)
-                .await
-                .map_err(Into::into)
-        })
-    }
 }
 
 // methods get cache from redis
@@ -285,7 +274,7 @@ impl MyRedisPool {
         Self::from_redis_with_perm_uids(&mut conn, ids, set_key).await
     }
 
-    async fn from_redis_with_perm_uids<T>(
+    pub(crate) async fn from_redis_with_perm_uids<T>(
         conn: &mut MultiplexedConnection,
         ids: Vec<u32>,
         set_key: &[u8],
@@ -349,9 +338,14 @@ impl MyRedisPool {
     pub(crate) async fn add_post(&self, posts: &[Post]) -> Result<(), ResError> {
         let post = posts.first().ok_or(ResError::InternalServerError)?;
         let pip = AsyncPipelinePost { post };
-        let pip = pip.await;
+        let mut pip = pip.await;
 
         let mut conn = self.get().await?.get_conn().clone();
+
+        let tags = topic_tags(&mut conn, post.topic_id).await?;
+        let time = post.created_at.timestamp_millis();
+        reply_tags(&mut pip, post.topic_id, &tags, Some(time), 1);
+
         pip.query_async(&mut conn).await.map_err(Into::into)
     }
 
@@ -376,6 +370,323 @@ impl MyRedisPool {
         pip.query_async(&mut conn).await.map_err(Into::into)
     }
 
+    // remove a soft deleted topic and its posts from all lists and fix the category counters.
+    // pids are the alive posts of the topic.
+    pub(crate) async fn remove_topic(&self, t: &Topic, pids: &[u32]) -> Result<(), ResError> {
+        let tid = t.id;
+        let cid = t.category_id;
+
+        let mut conn = self.get().await?.get_conn().clone();
+        // topic:{tid}:tags is kept for restoring.
+        let tags = topic_tags(&mut conn, tid).await?;
+
+        let mut pip = pipe();
+        pip.atomic();
+
+        remove_tags(&mut pip, tid, &tags);
+        // pins are kept in database and they are added back when restoring.
+        remove_pins(&mut pip, t);
+
+        for c in [cid.to_string().as_str(), "all"].iter() {
+            pip.cmd("ZREM")
+                .arg(&format!("category:{}:topics_time", c))
+                .arg(tid)
+                .ignore()
+                .cmd("ZREM")
+                .arg(&format!("category:{}:topics_reply", c))
+                .arg(tid)
+                .ignore()
+                .cmd("ZREM")
+                .arg(&format!("category:{}:topics_reaction", c))
+                .arg(tid)
+                .ignore()
+                .cmd("LREM")
+                .arg(&format!("category:{}:list_pop", c))
+                .arg(0)
+                .arg(tid)
+                .ignore()
+                .cmd("LREM")
+                .arg(&format!("category:{}:list_hot", c))
+                .arg(0)
+                .arg(tid)
+                .ignore();
+        }
+
+        pip.cmd("DEL")
+            .arg(&[
+                format!("topic:{}:set", tid),
+                format!("topic:{}:set_perm", tid),
+                format!("topic:{}:posts_reply", tid),
+                format!("topic:{}:posts_time_created", tid),
+            ])
+            .ignore()
+            .cmd("HINCRBY")
+            .arg(&format!("category:{}:set", cid))
+            .arg("topic_count_own")
+            .arg(-1)
+            .ignore();
+
+        if !pids.is_empty() {
+            let keys = pids
+                .iter()
+                .flat_map(|pid| {
+                    vec![
+                        format!("post:{}:set", pid),
+                        format!("post:{}:set_perm", pid),
+                    ]
+                })
+                .collect::<Vec<String>>();
+
+            pip.cmd("DEL")
+                .arg(keys)
+                .ignore()
+                .cmd("ZREM")
+                .arg(&format!("category:{}:posts_time", cid))
+                .arg(pids)
+                .ignore()
+                .cmd("HINCRBY")
+                .arg(&format!("category:{}:set", cid))
+                .arg("post_count_own")
+                .arg(-(pids.len() as i64))
+                .ignore();
+        }
+
+        pip.query_async(&mut conn).await.map_err(Into::into)
+    }
+
+    // add a restored topic back to lists. posts are (tid, pid, reply_count, last_reply_time, created_at) of the topic's alive posts.
+    pub(crate) async fn restore_topic(
+        &self,
+        t: &Topic,
+        posts: Vec<(u32, u32, Option<u32>, Option<NaiveDateTime>, NaiveDateTime)>,
+    ) -> Result<(), ResError> {
+        let tid = t.id;
+        let cid = t.category_id;
+        let count = posts.len();
+        let last_reply_time = posts.iter().map(|p| p.4).max();
+        let time = last_reply_time.unwrap_or(t.created_at).timestamp_millis();
+
+        let mut conn = self.get().await?.get_conn().clone();
+        let tags = topic_tags(&mut conn, tid).await?;
+
+        let mut pip = pipe();
+        pip.atomic();
+
+        add_tags(&mut pip, tid, &tags, time as f64, count as f64);
+
+        for c in [cid.to_string().as_str(), "all"].iter() {
+            pip.cmd("ZADD")
+                .arg(&format!("category:{}:topics_time", c))
+                .arg(time)
+                .arg(tid)
+                .ignore()
+                .cmd("ZADD")
+                .arg(&format!("category:{}:topics_reply", c))
+                .arg(count)
+                .arg(tid)
+                .ignore();
+        }
+
+        pip.cmd("HSET")
+            .arg(&format!("topic:{}:set_perm", tid))
+            .arg("reply_count")
+            .arg(count)
+            .ignore()
+            .cmd("HINCRBY")
+            .arg(&format!("category:{}:set", cid))
+            .arg("topic_count_own")
+            .arg(1)
+            .ignore()
+            .cmd("HINCRBY")
+            .arg(&format!("category:{}:set", cid))
+            .arg("post_count_own")
+            .arg(count)
+            .ignore();
+
+        if let Some(time) = last_reply_time {
+            pip.cmd("HSET")
+                .arg(&format!("topic:{}:set_perm", tid))
+                .arg("last_reply_time")
+                .arg(time.to_string())
+                .ignore();
+        }
+
+        for (_, pid, _, _, time) in posts.iter() {
+            pip.cmd("ZADD")
+                .arg(&format!("category:{}:posts_time", cid))
+                .arg(time.timestamp_millis())
+                .arg(*pid)
+                .ignore();
+        }
+
+        pip.query_async::<_, ()>(&mut conn).await?;
+
+        if !posts.is_empty() {
+            build_posts_cache_list(true, posts, &mut conn).await?;
+        }
+
+        Ok(())
+    }
+
+    // remove a soft deleted post from lists and fix the counters of category, topic and the post it replied to.
+    pub(crate) async fn remove_post(&self, p: &Post) -> Result<(), ResError> {
+        let pid = p.id;
+        let tid = p.topic_id;
+        let cid = p.category_id;
+
+        let mut conn = self.get().await?.get_conn().clone();
+        let tags = topic_tags(&mut conn, tid).await?;
+
+        let mut pip = pipe();
+        pip.atomic();
+
+        reply_tags(&mut pip, tid, &tags, None, -1);
+
+        pip.cmd("ZREM")
+            .arg(&format!("topic:{}:posts_time_created", tid))
+            .arg(pid)
+            .ignore()
+            .cmd("ZREM")
+            .arg(&format!("topic:{}:posts_reply", tid))
+            .arg(LEX_BASE - pid)
+            .ignore()
+            .cmd("ZREM")
+            .arg(&format!("category:{}:posts_time", cid))
+            .arg(pid)
+            .ignore()
+            .cmd("DEL")
+            .arg(&[
+                format!("post:{}:set", pid),
+                format!("post:{}:set_perm", pid),
+            ])
+            .ignore()
+            .cmd("HINCRBY")
+            .arg(&format!("category:{}:set", cid))
+            .arg("post_count_own")
+            .arg(-1)
+            .ignore()
+            .cmd("HINCRBY")
+            .arg(&format!("topic:{}:set_perm", tid))
+            .arg("reply_count")
+            .arg(-1)
+            .ignore();
+
+        // use XX so a topic already removed from the lists is not added back.
+        for c in [cid.to_string().as_str(), "all"].iter() {
+            pip.cmd("ZADD")
+                .arg(&format!("category:{}:topics_reply", c))
+                .arg("XX")
+                .arg("INCR")
+                .arg(-1)
+                .arg(tid)
+                .ignore();
+        }
+
+        if let Some(post_id) = p.post_id {
+            pip.cmd("HINCRBY")
+                .arg(&format!("post:{}:set_perm", post_id))
+                .arg("reply_count")
+                .arg(-1)
+                .ignore()
+                .cmd("ZADD")
+                .arg(&format!("topic:{}:posts_reply", tid))
+                .arg("XX")
+                .arg("INCR")
+                .arg(-1)
+                .arg(LEX_BASE - post_id)
+                .ignore();
+        }
+
+        pip.query_async(&mut conn).await.map_err(Into::into)
+    }
+
+    // add a restored post back to lists. count and last_reply_time are the post's own reply stat.
+    pub(crate) async fn restore_post(
+        &self,
+        p: &Post,
+        count: u32,
+        last_reply_time: Option<NaiveDateTime>,
+    ) -> Result<(), ResError> {
+        let pid = p.id;
+        let tid = p.topic_id;
+        let cid = p.category_id;
+        let time = p.created_at.timestamp_millis();
+
+        let mut conn = self.get().await?.get_conn().clone();
+        let tags = topic_tags(&mut conn, tid).await?;
+
+        let mut pip = pipe();
+        pip.atomic();
+
+        reply_tags(&mut pip, tid, &tags, None, 1);
+
+        pip.cmd("ZADD")
+            .arg(&format!("topic:{}:posts_time_created", tid))
+            .arg(time)
+            .arg(pid)
+            .ignore()
+            .cmd("ZADD")
+            .arg(&format!("topic:{}:posts_reply", tid))
+            .arg(count)
+            .arg(LEX_BASE - pid)
+            .ignore()
+            .cmd("ZADD")
+            .arg(&format!("category:{}:posts_time", cid))
+            .arg(time)
+            .arg(pid)
+            .ignore()
+            .cmd("HSET")
+            .arg(&format!("post:{}:set_perm", pid))
+            .arg("reply_count")
+            .arg(count)
+            .ignore()
+            .cmd("HINCRBY")
+            .arg(&format!("category:{}:set", cid))
+            .arg("post_count_own")
+            .arg(1)
+            .ignore()
+            .cmd("HINCRBY")
+            .arg(&format!("topic:{}:set_perm", tid))
+            .arg("reply_count")
+            .arg(1)
+            .ignore();
+
+        if let Some(time) = last_reply_time {
+            pip.cmd("HSET")
+                .arg(&format!("post:{}:set_perm", pid))
+                .arg("last_reply_time")
+                .arg(time.to_string())
+                .ignore();
+        }
+
+        for c in [cid.to_string().as_str(), "all"].iter() {
+            pip.cmd("ZADD")
+                .arg(&format!("category:{}:topics_reply", c))
+                .arg("XX")
+                .arg("INCR")
+                .arg(1)
+                .arg(tid)
+                .ignore();
+        }
+
+        if let Some(post_id) = p.post_id {
+            pip.cmd("HINCRBY")
+                .arg(&format!("post:{}:set_perm", post_id))
+                .arg("reply_count")
+                .arg(1)
+                .ignore()
+                .cmd("ZADD")
+                .arg(&format!("topic:{}:posts_reply", tid))
+                .arg("XX")
+                .arg("INCR")
+                .arg(1)
+                .arg(LEX_BASE - post_id)
+                .ignore();
+        }
+
+        pip.query_async(&mut conn).await.map_err(Into::into)
+    }
+
     //    pub(crate) async fn bulk_add_update_cache(
     //        &self,
     //        t: Vec<&Topic>,
@@ -497,7 +808,7 @@ impl MyRedisPool {
 /// ids from list and sorted set will return an error if the result is empty.
 /// we assume no data can be found on database if we don't have according id in cache.
 impl MyRedisPool {
-    async fn ids_from_list(
+    pub(crate) async fn ids_from_list(
         conn: &mut MultiplexedConnection,
         list_key: &str,
         start: usize,
@@ -517,7 +828,7 @@ impl MyRedisPool {
         }
     }
 
-    async fn ids_from_zrange(
+    pub(crate) async fn ids_from_zrange(
         conn: &mut MultiplexedConnection,
         list_key: &str,
         is_rev: bool,
@@ -545,6 +856,66 @@ impl MyRedisPool {
             Ok(ids)
         }
     }
+
+    // page of topic ids from a list or a reversed sorted set with topics of hidden categories left out.
+    // the list is scanned from start so the pages are filled with visible topics only.
+    pub(crate) async fn visible_topic_ids(
+        conn: &mut MultiplexedConnection,
+        key: &str,
+        is_list: bool,
+        hidden: &[u32],
+        page: usize,
+    ) -> Result<Vec<u32>, ResError> {
+        let mut pip = pipe();
+        for cid in hidden.iter() {
+            pip.cmd("ZRANGE")
+                .arg(&format!("category:{}:topics_time", cid))
+                .arg(0)
+                .arg(-1);
+        }
+        let hidden_tids = pip
+            .query_async::<_, Vec<Vec<u32>>>(conn)
+            .await?
+            .into_iter()
+            .flatten()
+            .collect::<hashbrown::HashSet<u32>>();
+
+        let mut skip = (page - 1) * LIMIT;
+        let mut ids = Vec::with_capacity(LIMIT);
+        let mut start = 0;
+
+        loop {
+            let chunk = cmd(if is_list { "LRANGE" } else { "ZREVRANGE" })
+                .arg(key)
+                .arg(start)
+                .arg(start + VISIBLE_SCAN_CHUNK - 1)
+                .query_async::<_, Vec<u32>>(conn)
+                .await?;
+            let len = chunk.len();
+
+            for id in chunk.into_iter().filter(|id| !hidden_tids.contains(id)) {
+                if skip > 0 {
+                    skip -= 1;
+                } else {
+                    ids.push(id);
+                    if ids.len() == LIMIT {
+                        return Ok(ids);
+                    }
+                }
+            }
+
+            if len < VISIBLE_SCAN_CHUNK {
+                break;
+            }
+            start += VISIBLE_SCAN_CHUNK;
+        }
+
+        if ids.is_empty() {
+            Err(ResError::NoContent)
+        } else {
+            Ok(ids)
+        }
+    }
 }
 
 struct AsyncPipelineTopic<'a> {
@@ -576,10 +947,10 @@ impl Future for AsyncPipelineTopic<'_> {
             .arg(key.as_str())
             .arg(HASH_LIFE)
             .ignore()
-            // update category's topic_count
+            // update category's own topic_count
             .cmd("HINCRBY")
             .arg(&format!("category:{}:set", cid))
-            .arg("topic_count")
+            .arg("topic_count_own")
             .arg(1)
             .ignore()
             // add self time to category's topics_time sorted set
@@ -643,10 +1014,10 @@ impl Future for AsyncPipelinePost<'_> {
             .arg(post_key.as_str())
             .arg(HASH_LIFE)
             .ignore()
-            // update category's post_count
+            // update category's own post_count
             .cmd("HINCRBY")
             .arg(&format!("category:{}:set", cid))
-            .arg("post_count")
+            .arg("post_count_own")
             .arg(1)
             .ignore()
             // update topic's reply_count
@@ -1000,13 +1371,13 @@ pub(crate) async fn build_topics_cache_list(
 
 pub(crate) async fn build_posts_cache_list(
     is_init: bool,
-    vec: Vec<(u32, u32, Option<u32>, NaiveDateTime)>,
+    vec: Vec<(u32, u32, Option<u32>, Option<NaiveDateTime>, NaiveDateTime)>,
     conn: &mut MultiplexedConnection,
 ) -> Result<(), ResError> {
     let mut pipe = pipe();
     pipe.atomic();
 
-    for (tid, pid, count, time) in vec.into_iter() {
+    for (tid, pid, count, last_reply_time, time) in vec.into_iter() {
         // only build these two zrange when init a new database.
         if is_init {
             let time = time.timestamp_millis();
@@ -1030,6 +1401,14 @@ pub(crate) async fn build_posts_cache_list(
                 .arg(count)
                 .ignore();
         }
+
+        if let Some(time) = last_reply_time {
+            pipe.cmd("HSET")
+                .arg(&format!("post:{}:set_perm", pid))
+                .arg("last_reply_time")
+                .arg(time.to_string())
+                .ignore();
+        }
     }
 
     pipe.query_async(conn).await.map_err(Into::into)
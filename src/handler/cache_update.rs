@@ -9,10 +9,19 @@ use crate::handler::{
     db::MyPostgresPool,
     messenger::{ErrReportMsg, ErrReportServiceAddr},
 };
-use crate::model::{cache_schema::HashMapBrown, common::dur, errors::ResError};
+use crate::model::{
+    cache_schema::HashMapBrown,
+    category::{Category, CategoryRemoveReport},
+    common::dur,
+    errors::ResError,
+    reaction::ReactionReport,
+    topic::TopicMoveReport,
+};
+use crate::util::env::RankConfig;
 
 const LIST_INTERVAL: Duration = dur(5000);
 const FAILED_INTERVAL: Duration = dur(3000);
+const READ_INTERVAL: Duration = dur(30000);
 
 #[actor]
 pub struct CacheService {
@@ -20,6 +29,7 @@ pub struct CacheService {
     cache_pool: MyRedisPool,
     rep_addr: Option<ErrReportServiceAddr>,
     message: VecDeque<CacheFailedMessage>,
+    rank: RankConfig,
 }
 
 #[handler_v2]
@@ -44,6 +54,9 @@ impl CacheService {
                 let c = self.db_pool.get_categories(&[id]).await?;
                 self.cache_pool.add_category(&c).await
             }
+            CacheFailedMessage::FailedCategoryRemove(r) => {
+                self.cache_pool.remove_category(&r).await
+            }
             CacheFailedMessage::FailedUser(id) => {
                 let u = self.db_pool.get_users(&[id]).await?;
                 self.cache_pool.update_users(&u).await
@@ -56,7 +69,83 @@ impl CacheService {
                 let (p, _) = self.db_pool.get_posts(&[id]).await?;
                 self.cache_pool.update_posts(&p).await
             }
+            // skip the retry if topic/post is restored or deleted again in between.
+            CacheFailedMessage::FailedTopicDelete(id) => {
+                // hidden topic is removed from lists the same as deleted one.
+                let mut t = self.db_pool.get_deleted_topic(id).await?;
+                if t.is_empty() {
+                    t = self.db_pool.get_topics(&[id]).await?.0;
+                    t.retain(|t| !t.is_visible);
+                }
+                match t.first() {
+                    Some(t) => {
+                        self.cache_pool
+                            .remove_topic_with_posts(&self.db_pool, t)
+                            .await
+                    }
+                    None => Ok(()),
+                }
+            }
+            CacheFailedMessage::FailedTopicRestore(id) => {
+                let (t, _) = self.db_pool.get_topics(&[id]).await?;
+                match t.first().filter(|t| t.is_visible) {
+                    Some(t) => {
+                        self.cache_pool
+                            .restore_topic_with_posts(&self.db_pool, t)
+                            .await
+                    }
+                    None => Ok(()),
+                }
+            }
+            CacheFailedMessage::FailedPostDelete(id) => {
+                match self.db_pool.get_deleted_post(id).await?.first() {
+                    Some(p) => self.cache_pool.remove_post(p).await,
+                    None => Ok(()),
+                }
+            }
+            CacheFailedMessage::FailedPostRestore(id) => {
+                let (p, _) = self.db_pool.get_posts(&[id]).await?;
+                match p.first() {
+                    Some(p) => {
+                        self.cache_pool
+                            .restore_post_with_stat(&self.db_pool, p)
+                            .await
+                    }
+                    None => Ok(()),
+                }
+            }
+            CacheFailedMessage::FailedTopicTags(id) => {
+                let old = self.cache_pool.get_topic_tags(id).await?;
+                let t = self.db_pool.get_topic_tags(id).await?;
+                self.cache_pool.update_topic_tags(&old, &t).await
+            }
+            CacheFailedMessage::FailedTopicMove(r) => {
+                self.cache_pool
+                    .rebuild_moved_topics(&self.db_pool, &r)
+                    .await
+            }
+            CacheFailedMessage::FailedReaction(r) => self.cache_pool.update_reaction(&r).await,
+            CacheFailedMessage::FailedTopicPin(id) => {
+                let (t, _) = self.db_pool.get_topics(&[id]).await?;
+                match t.first() {
+                    Some(t) => {
+                        let pins = self.db_pool.get_topic_pins(id).await?;
+                        self.cache_pool.set_topic_pins(t, &pins).await
+                    }
+                    None => Ok(()),
+                }
+            }
+        }
+    }
+
+    // read states are added back to dirty set when failed to persist and they are retried in next interval.
+    async fn persist_reads(&self) -> Result<(), ResError> {
+        let (keys, t, c) = self.cache_pool.take_dirty_reads().await?;
+        if let Err(e) = self.db_pool.save_reads(&t, &c).await {
+            self.cache_pool.add_dirty_reads(&keys).await?;
+            return Err(e);
         }
+        Ok(())
     }
 
     fn send_err_rep(&self, e: ResError) {
@@ -75,6 +164,7 @@ pub async fn init_cache_service(
     db_pool: MyPostgresPool,
     cache_pool: MyRedisPool,
     rep_addr: Option<ErrReportServiceAddr>,
+    rank: RankConfig,
 ) -> CacheServiceAddr {
     let builder = CacheService::builder(move || {
         let rep_addr = rep_addr.clone();
@@ -86,6 +176,7 @@ pub async fn init_cache_service(
                 cache_pool,
                 rep_addr,
                 message: Default::default(),
+                rank,
             }
         }
     });
@@ -94,7 +185,7 @@ pub async fn init_cache_service(
 
     addr.run_interval(LIST_INTERVAL, |service| {
         Box::pin(async move {
-            if let Err(e) = service.cache_pool.handle_list_update().await {
+            if let Err(e) = service.cache_pool.handle_list_update(service.rank).await {
                 service.send_err_rep(e);
             }
         })
@@ -115,6 +206,16 @@ pub async fn init_cache_service(
     .await
     .expect("Failed to start CacheService interval task for updating list order");
 
+    addr.run_interval(READ_INTERVAL, |service| {
+        Box::pin(async move {
+            if let Err(e) = service.persist_reads().await {
+                service.send_err_rep(e);
+            }
+        })
+    })
+    .await
+    .expect("Failed to start CacheService interval task for persisting read states");
+
     addr
 }
 
@@ -125,51 +226,70 @@ pub enum CacheFailedMessage {
     FailedTopic(u32),
     FailedPost(u32),
     FailedCategory(u32),
+    FailedCategoryRemove(CategoryRemoveReport),
     FailedUser(u32),
     FailedTopicUpdate(u32),
     FailedPostUpdate(u32),
+    FailedTopicDelete(u32),
+    FailedTopicRestore(u32),
+    FailedPostDelete(u32),
+    FailedPostRestore(u32),
+    FailedTopicTags(u32),
+    FailedTopicPin(u32),
+    FailedTopicMove(TopicMoveReport),
+    FailedReaction(ReactionReport),
 }
 
 impl MyRedisPool {
     // iterate all categories cache and update list as well as the topic/post count for every category
-    async fn handle_list_update(&self) -> Result<(), ResError> {
+    async fn handle_list_update(&self, rank: RankConfig) -> Result<(), ResError> {
         let cat = self.get_categories_all().await?;
 
-        let yesterday = Utc::now().naive_utc().timestamp_millis() - 86_400_000;
+        let now = Utc::now().naive_utc().timestamp_millis();
+        let yesterday = now - 86_400_000;
 
         let mut pool = self.get().await?;
         let conn = &mut *pool;
 
+        let mut counts_new = Vec::with_capacity(cat.len());
         for c in cat.iter() {
-            // update_list will also update topic count new.
-            let _ = update_list(Some(c.id), yesterday, conn).await;
-            let _ = update_post_count(c.id, yesterday, conn).await;
+            // update_list will also return topic count new.
+            let topic_new = update_list(Some(c.id), yesterday, rank.reaction_weight, conn)
+                .await
+                .unwrap_or(0);
+            let post_new = count_post_new(c.id, yesterday, conn).await.unwrap_or(0);
+            counts_new.push((topic_new, post_new));
+            let _ = update_hot_list(Some(c.id), now, rank, conn).await;
         }
-        let _ = update_list(None, yesterday, conn).await;
+        let _ = update_list(None, yesterday, rank.reaction_weight, conn).await;
+        let _ = update_hot_list(None, now, rank, conn).await;
 
-        Ok(())
+        update_counts(&cat, counts_new, conn).await
     }
 }
 
 type ListWithSortedRange = (HashMapBrown<u32, i64>, Vec<(u32, u32)>);
 
+// return the new topic count of category.
+// topics are sorted by reply count plus reaction count multiplied by reaction_weight.
 async fn update_list(
     cid: Option<u32>,
     yesterday: i64,
+    reaction_weight: f64,
     conn: &mut MultiplexedConnection,
-) -> Result<(), ResError> {
-    let (list_key, time_key, reply_key, set_key) = match cid.as_ref() {
+) -> Result<u32, ResError> {
+    let (list_key, time_key, reply_key, reaction_key) = match cid.as_ref() {
         Some(cid) => (
             format!("category:{}:list_pop", cid),
             format!("category:{}:topics_time", cid),
             format!("category:{}:topics_reply", cid),
-            Some(format!("category:{}:set", cid)),
+            format!("category:{}:topics_reaction", cid),
         ),
         None => (
             "category:all:list_pop".to_owned(),
             "category:all:topics_time".to_owned(),
             "category:all:topics_reply".to_owned(),
-            None,
+            "category:all:topics_reaction".to_owned(),
         ),
     };
 
@@ -188,21 +308,14 @@ async fn update_list(
 
     let (HashMapBrown(tids), counts) = pip.query_async::<_, ListWithSortedRange>(conn).await?;
 
+    let reactions = reaction_scores(reaction_key.as_str(), reaction_weight, conn).await?;
+
+    let topic_new = tids.len() as u32;
+
     let mut should_update = false;
     let mut pip = pipe();
     pip.atomic();
 
-    if !tids.is_empty() {
-        if let Some(key) = set_key {
-            pip.cmd("HSET")
-                .arg(&key)
-                .arg("topic_count_new")
-                .arg(tids.len())
-                .ignore();
-            should_update = true;
-        }
-    }
-
     let block = actix_web::web::block(move || {
         use std::cmp::Ordering;
 
@@ -211,26 +324,17 @@ async fn update_list(
             .filter(|(tid, _)| tids.contains_key(tid))
             .collect::<Vec<(u32, u32)>>();
 
+        let score = |tid: &u32, count: u32| {
+            let reaction = reactions.get(tid).copied().unwrap_or(0).max(0);
+            f64::from(count) + reaction as f64 * reaction_weight
+        };
+
+        // higher score first and the newer one wins a tie.
         counts.sort_by(|(a0, a1), (b0, b1)| {
-            if a1 == b1 {
-                if let Some(a) = tids.get(a0) {
-                    if let Some(b) = tids.get(b0) {
-                        match a.cmp(b) {
-                            Ordering::Greater => return Ordering::Less,
-                            Ordering::Less => return Ordering::Greater,
-                            _ => (),
-                        }
-                        // if a > b {
-                        //     return Ordering::Less;
-                        // } else if a < b {
-                        //     return Ordering::Greater;
-                        // };
-                    }
-                }
-                Ordering::Equal
-            } else {
-                Ordering::Greater
-            }
+            score(b0, *b1)
+                .partial_cmp(&score(a0, *a1))
+                .unwrap_or(Ordering::Equal)
+                .then_with(|| tids.get(b0).cmp(&tids.get(a0)))
         });
 
         Ok::<_, ResError>(counts.into_iter().map(|(id, _)| id).collect::<Vec<u32>>())
@@ -252,32 +356,175 @@ async fn update_list(
     if should_update {
         pip.query_async::<_, ()>(conn).await?;
     };
-    Ok(())
+    Ok(topic_new)
 }
 
-async fn update_post_count(
+// hot score is the reply and reaction counts decayed exponentially by the time since the last reply.
+// topics with no reply in hot_window hours are dropped from the list.
+async fn update_hot_list(
+    cid: Option<u32>,
+    now: i64,
+    rank: RankConfig,
+    conn: &mut MultiplexedConnection,
+) -> Result<(), ResError> {
+    let c = cid
+        .map(|cid| cid.to_string())
+        .unwrap_or_else(|| "all".to_owned());
+    let list_key = format!("category:{}:list_hot", c);
+
+    let times = cmd("ZREVRANGEBYSCORE")
+        .arg(&format!("category:{}:topics_time", c))
+        .arg("+inf")
+        .arg(now - rank.hot_window * 3_600_000)
+        .arg("WITHSCORES")
+        .query_async::<_, Vec<(u32, i64)>>(conn)
+        .await?;
+
+    // reply and reaction counts are only fetched for topics in the window.
+    let counts = topic_scores(&format!("category:{}:topics_reply", c), &times, conn).await?;
+    let reactions = if rank.hot_reaction_weight > 0.0 {
+        topic_scores(&format!("category:{}:topics_reaction", c), &times, conn).await?
+    } else {
+        vec![]
+    };
+
+    let block = actix_web::web::block(move || {
+        let mut scores = times
+            .into_iter()
+            .zip(counts.into_iter())
+            .enumerate()
+            .filter_map(|(i, ((tid, time), count))| {
+                let count = count?;
+                let reaction = reactions.get(i).copied().flatten().unwrap_or(0.0).max(0.0);
+                let age = (now - time).max(0) as f64 / 3_600_000.0;
+                let score = (1.0 + count + reaction * rank.hot_reaction_weight)
+                    * 0.5f64.powf(age / rank.hot_half_life);
+                Some((tid, score))
+            })
+            .collect::<Vec<(u32, f64)>>();
+
+        scores.sort_by(|(_, a), (_, b)| b.partial_cmp(a).unwrap_or(std::cmp::Ordering::Equal));
+
+        Ok::<_, ResError>(scores.into_iter().map(|(id, _)| id).collect::<Vec<u32>>())
+    });
+
+    let ids = block.await?;
+
+    let mut pip = pipe();
+    pip.atomic();
+    pip.cmd("del").arg(&list_key).ignore();
+    if !ids.is_empty() {
+        pip.cmd("rpush").arg(&list_key).arg(ids).ignore();
+    }
+    pip.query_async(conn).await.map_err(Into::into)
+}
+
+// scores of the given topics in a sorted set. None for topics not in the set.
+async fn topic_scores(
+    key: &str,
+    topics: &[(u32, i64)],
+    conn: &mut MultiplexedConnection,
+) -> Result<Vec<Option<f64>>, ResError> {
+    if topics.is_empty() {
+        return Ok(vec![]);
+    }
+    let mut pip = pipe();
+    for (tid, _) in topics.iter() {
+        pip.cmd("ZSCORE").arg(key).arg(*tid);
+    }
+    pip.query_async(conn).await.map_err(Into::into)
+}
+
+// reaction counts of topics. they are skipped when reactions are not blended.
+async fn reaction_scores(
+    key: &str,
+    reaction_weight: f64,
+    conn: &mut MultiplexedConnection,
+) -> Result<hashbrown::HashMap<u32, i64>, ResError> {
+    if reaction_weight <= 0.0 {
+        return Ok(Default::default());
+    }
+    let HashMapBrown(r) = cmd("ZRANGEBYSCORE")
+        .arg(key)
+        .arg("(0")
+        .arg("+inf")
+        .arg("WITHSCORES")
+        .query_async(conn)
+        .await?;
+    Ok(r)
+}
+
+async fn count_post_new(
     cid: u32,
     yesterday: i64,
     conn: &mut MultiplexedConnection,
-) -> Result<(), ResError> {
+) -> Result<u32, ResError> {
     let time_key = format!("category:{}:posts_time", cid);
-    let set_key = format!("category:{}:set", cid);
 
-    let count = cmd("ZCOUNT")
+    cmd("ZCOUNT")
         .arg(time_key.as_str())
         .arg(yesterday)
         .arg("+inf")
         .query_async::<_, u32>(conn)
+        .await
+        .map_err(Into::into)
+}
+
+// counts of category are the sum of its own counts and the counts of all subcategories.
+// counts_new are the new topic and post counts of categories in the same order.
+async fn update_counts(
+    cat: &[Category],
+    counts_new: Vec<(u32, u32)>,
+    conn: &mut MultiplexedConnection,
+) -> Result<(), ResError> {
+    if cat.is_empty() {
+        return Ok(());
+    }
+
+    let mut pip = pipe();
+    for c in cat.iter() {
+        pip.cmd("HMGET")
+            .arg(&format!("category:{}:set", c.id))
+            .arg("topic_count_own")
+            .arg("post_count_own");
+    }
+    let counts_own = pip
+        .query_async::<_, Vec<(Option<u32>, Option<u32>)>>(conn)
         .await?;
 
-    if count > 0 {
-        cmd("HMSET")
-            .arg(set_key.as_str())
-            .arg(&[("post_count_new", count)])
-            .query_async::<_, ()>(conn)
-            .await?;
-        Ok(())
-    } else {
-        Ok(())
+    // [topic_count, post_count, topic_count_new, post_count_new]
+    let mut total = vec![[0u32; 4]; cat.len()];
+
+    for (i, c) in cat.iter().enumerate() {
+        let (topic, post) = counts_own.get(i).copied().unwrap_or((None, None));
+        let (topic_new, post_new) = counts_new.get(i).copied().unwrap_or((0, 0));
+        let counts = [topic.unwrap_or(0), post.unwrap_or(0), topic_new, post_new];
+
+        let mut ids = c.ancestors(cat);
+        ids.push(c.id);
+
+        for id in ids.into_iter() {
+            if let Some(j) = cat.iter().position(|c| c.id == id) {
+                for (t, n) in total[j].iter_mut().zip(counts.iter()) {
+                    *t += n;
+                }
+            }
+        }
     }
+
+    let mut pip = pipe();
+    pip.atomic();
+    for (c, t) in cat.iter().zip(total.iter()) {
+        pip.cmd("HMSET")
+            .arg(&format!("category:{}:set", c.id))
+            .arg(&[
+                ("topic_count", t[0]),
+                ("post_count", t[1]),
+                ("topic_count_new", t[2]),
+                ("post_count_new", t[3]),
+            ])
+            .ignore();
+    }
+
+    pip.query_async::<_, ()>(conn).await.map_err(Into::into)
 }
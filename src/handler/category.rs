@@ -1,23 +1,72 @@
 use std::future::Future;
 
-use tokio_postgres::types::ToSql;
+use redis::pipe;
+use tokio_postgres::{
+    types::{ToSql, Type},
+    Transaction,
+};
 
 use crate::handler::cache_update::CacheServiceAddr;
 use crate::handler::{
     cache::{MyRedisPool, CATEGORY_U8},
     cache_update::CacheFailedMessage,
     db::{MyPostgresPool, ParseRowStream},
+    tag::{remove_tags, topic_tags},
 };
 use crate::model::{
-    category::{Category, CategoryRequest},
+    category::{
+        Category, CategoryRemoveReport, CategoryRemoveRequest, CategoryRequest, RemoveMode,
+        Visibility,
+    },
     errors::ResError,
 };
 
-const GET_CATEGORY_ALL: &str = "SELECT * FROM categories";
-const GET_CATEGORY: &str = "SELECT * FROM categories WHERE id=ANY($1)";
+// moderators are collected from the moderator roles granted in category.
+pub(crate) const GET_CATEGORY_ALL: &str = "SELECT c.id, c.name, c.thumbnail, c.visibility, c.role,
+    ARRAY(SELECT ur.user_id FROM user_roles ur JOIN roles r ON r.id = ur.role_id
+        WHERE r.name = 'moderator' AND ur.category_id = c.id ORDER BY ur.user_id),
+    c.parent_id, c.position
+    FROM categories c";
+const GET_CATEGORY: &str = "SELECT c.id, c.name, c.thumbnail, c.visibility, c.role,
+    ARRAY(SELECT ur.user_id FROM user_roles ur JOIN roles r ON r.id = ur.role_id
+        WHERE r.name = 'moderator' AND ur.category_id = c.id ORDER BY ur.user_id),
+    c.parent_id, c.position
+    FROM categories c WHERE c.id=ANY($1)";
 const DEL_CATEGORY: &str = "DELETE FROM categories WHERE id=$1";
+
+// all topics and posts are moved. only the alive ones are returned as they are the ones in cache.
+const MOVE_CATEGORY_TOPICS: &str = "UPDATE topics SET category_id=$2 WHERE category_id=$1
+    RETURNING id, deleted_at IS NULL";
+const MOVE_CATEGORY_POSTS: &str = "UPDATE posts SET category_id=$2 WHERE category_id=$1
+    RETURNING id, deleted_at IS NULL
+    AND EXISTS (SELECT 1 FROM topics WHERE topics.id = posts.topic_id AND topics.deleted_at IS NULL)";
+
+const DELETE_CATEGORY_TOPICS: &str = "UPDATE topics SET deleted_at=CURRENT_TIMESTAMP, deleted_by=$2
+    WHERE category_id=$1 AND deleted_at IS NULL
+    RETURNING id";
+const SELECT_TOPICS_POST_IDS: &str =
+    "SELECT id FROM posts WHERE topic_id=ANY($1) AND deleted_at IS NULL";
 const INSERT_CATEGORY: &str =
-    "INSERT INTO categories (id, name, thumbnail) VALUES ($1, $2, $3) RETURNING *";
+    "INSERT INTO categories (id, name, thumbnail, visibility, role, parent_id, position)
+    VALUES ($1, $2, $3, $4, $5, $6, $7)";
+
+// new parent can't be the category itself or any of its subcategories.
+const SELECT_CATEGORY_CYCLE: &str = "WITH RECURSIVE up AS (
+    SELECT id, parent_id FROM categories WHERE id=$1
+    UNION
+    SELECT c.id, c.parent_id FROM categories c JOIN up ON c.id = up.parent_id
+) SELECT id FROM up WHERE id=$2";
+
+const MOVE_CATEGORY_CHILDREN: &str = "UPDATE categories
+    SET parent_id=(SELECT parent_id FROM categories WHERE id=$1)
+    WHERE parent_id=$1
+    RETURNING id";
+
+const DEL_CATEGORY_MODERATORS: &str = "DELETE FROM user_roles
+    WHERE category_id=$1 AND role_id=(SELECT id FROM roles WHERE name='moderator')";
+const INSERT_CATEGORY_MODERATORS: &str = "INSERT INTO user_roles (user_id, role_id, category_id)
+    SELECT uid, r.id, $2 FROM UNNEST($1::OID[]) AS uid, roles r WHERE r.name='moderator'
+    ON CONFLICT DO NOTHING";
 
 impl MyPostgresPool {
     pub(crate) async fn get_categories_all(&self) -> Result<Vec<Category>, ResError> {
@@ -49,25 +98,84 @@ impl MyPostgresPool {
     pub(crate) async fn add_category(&self, c: CategoryRequest) -> Result<Vec<Category>, ResError> {
         let name = c.name.as_ref().ok_or(ResError::BadRequest)?;
         let thumb = c.thumbnail.as_ref().ok_or(ResError::BadRequest)?;
+        let visibility = c.visibility.unwrap_or(Visibility::Public).as_u32();
+        let parent = c.parent_id.filter(|id| *id != 0);
+        let position = c.position.unwrap_or(0);
 
-        let pool = self.get().await?;
-        let (cli, _) = &*pool;
+        if let Some(role) = c.role.as_ref() {
+            self.check_role(role).await?;
+        }
+
+        let mut pool = self.get().await?;
+        let (cli, _) = &mut *pool;
+
+        let tx = cli.transaction().await?;
+
+        if let Some(parent) = parent {
+            let st = tx.prepare_typed(GET_CATEGORY, &[Type::OID_ARRAY]).await?;
+            if tx.query(&st, &[&[parent].as_ref()]).await?.is_empty() {
+                return Err(ResError::BadRequestExplained(
+                    "Parent category not found".into(),
+                ));
+            }
+        }
 
-        let st = cli.prepare_typed(INSERT_CATEGORY, &[]).await?;
+        let st = tx
+            .prepare_typed(
+                INSERT_CATEGORY,
+                &[
+                    Type::OID,
+                    Type::VARCHAR,
+                    Type::VARCHAR,
+                    Type::OID,
+                    Type::VARCHAR,
+                    Type::OID,
+                    Type::OID,
+                ],
+            )
+            .await?;
 
         let cid = crate::model::common::global().lock().next_cid();
-        let params: [&(dyn ToSql + Sync); 3] = [&cid, &name, &thumb];
+        tx.execute(
+            &st,
+            &[
+                &cid,
+                &name,
+                &thumb,
+                &visibility,
+                &c.role,
+                &parent,
+                &position,
+            ],
+        )
+        .await?;
 
-        cli.query_raw(&st, params.iter().map(|s| *s as _))
+        if let Some(m) = c.moderators.as_ref() {
+            set_moderators(&tx, cid, m).await?;
+        }
+
+        let st = tx.prepare_typed(GET_CATEGORY, &[Type::OID_ARRAY]).await?;
+        let params: [&(dyn ToSql + Sync); 1] = [&[cid].as_ref()];
+        let c = tx
+            .query_raw(&st, params.iter().map(|s| *s as _))
             .await?
             .parse_row()
-            .await
+            .await?;
+
+        tx.commit().await?;
+
+        Ok(c)
     }
 
     pub(crate) async fn update_category(
         &self,
         c: CategoryRequest,
     ) -> Result<Vec<Category>, ResError> {
+        let cid = c.id.ok_or(ResError::BadRequest)?;
+        let visibility = c.visibility.map(Visibility::as_u32);
+        // parent_id 0 is stored as null.
+        let parent = c.parent_id.map(|id| if id == 0 { None } else { Some(id) });
+
         let mut query = String::from("UPDATE categories SET");
         let mut params = Vec::new();
         let mut index = 1u8;
@@ -86,38 +194,215 @@ impl MyPostgresPool {
             params.push(s as &(dyn ToSql + Sync));
             index += 1;
         }
+        if let Some(s) = visibility.as_ref() {
+            query.push_str(" visibility=$");
+            query.push_str(index.to_string().as_str());
+            query.push_str(",");
+            params.push(s as &(dyn ToSql + Sync));
+            index += 1;
+        }
+        if let Some(s) = c.role.as_ref() {
+            self.check_role(s).await?;
+            query.push_str(" role=$");
+            query.push_str(index.to_string().as_str());
+            query.push_str(",");
+            params.push(s as &(dyn ToSql + Sync));
+            index += 1;
+        }
+        if let Some(s) = parent.as_ref() {
+            query.push_str(" parent_id=$");
+            query.push_str(index.to_string().as_str());
+            query.push_str(",");
+            params.push(s as &(dyn ToSql + Sync));
+            index += 1;
+        }
+        if let Some(s) = c.position.as_ref() {
+            query.push_str(" position=$");
+            query.push_str(index.to_string().as_str());
+            query.push_str(",");
+            params.push(s as &(dyn ToSql + Sync));
+            index += 1;
+        }
 
-        if query.ends_with(',') {
+        // moderators could be updated alone without touching the category row.
+        let should_update = query.ends_with(',');
+        if should_update {
             query.pop();
             query.push_str(" WHERE id=$");
             query.push_str(index.to_string().as_str());
-            params.push(c.id.as_ref().unwrap() as &(dyn ToSql + Sync));
-        } else {
+            params.push(&cid as &(dyn ToSql + Sync));
+        } else if c.moderators.is_none() {
             return Err(ResError::BadRequest);
         };
 
-        query.push_str(" RETURNING *");
+        let mut pool = self.get().await?;
+        let (cli, _) = &mut *pool;
 
-        let pool = self.get().await?;
-        let (cli, _) = &*pool;
+        let tx = cli.transaction().await?;
 
-        let st = cli.prepare_typed(query.as_str(), &[]).await?;
+        if let Some(Some(parent)) = parent {
+            let st = tx.prepare_typed(GET_CATEGORY, &[Type::OID_ARRAY]).await?;
+            if tx.query(&st, &[&[parent].as_ref()]).await?.is_empty() {
+                return Err(ResError::BadRequestExplained(
+                    "Parent category not found".into(),
+                ));
+            }
 
-        cli.query_raw(&st, params.iter().map(|s| *s as _))
+            let st = tx
+                .prepare_typed(SELECT_CATEGORY_CYCLE, &[Type::OID, Type::OID])
+                .await?;
+            if !tx.query(&st, &[&parent, &cid]).await?.is_empty() {
+                return Err(ResError::BadRequestExplained(
+                    "Category can't be moved into itself or its subcategories".into(),
+                ));
+            }
+        }
+
+        if should_update {
+            let st = tx.prepare_typed(query.as_str(), &[]).await?;
+            if tx.execute(&st, params.as_slice()).await? == 0 {
+                return Err(ResError::NotFound);
+            }
+        }
+
+        if let Some(m) = c.moderators.as_ref() {
+            set_moderators(&tx, cid, m).await?;
+        }
+
+        let st = tx.prepare_typed(GET_CATEGORY, &[Type::OID_ARRAY]).await?;
+        let params: [&(dyn ToSql + Sync); 1] = [&[cid].as_ref()];
+        let c: Vec<Category> = tx
+            .query_raw(&st, params.iter().map(|s| *s as _))
             .await?
             .parse_row()
-            .await
+            .await?;
+
+        if c.is_empty() {
+            return Err(ResError::NotFound);
+        }
+
+        tx.commit().await?;
+
+        Ok(c)
     }
 
-    pub async fn remove_category(&self, cid: u32) -> Result<(), ResError> {
-        let pool = self.get().await?;
-        let (cli, _) = &*pool;
+    // remove category and move its topics and posts to target category or soft delete the topics.
+    // uid is stored as deleted_by of the topics.
+    pub async fn remove_category(
+        &self,
+        cid: u32,
+        uid: u32,
+        req: CategoryRemoveRequest,
+    ) -> Result<CategoryRemoveReport, ResError> {
+        let mut pool = self.get().await?;
+        let (cli, _) = &mut *pool;
+
+        let tx = cli.transaction().await?;
+
+        let st = tx.prepare_typed(GET_CATEGORY, &[Type::OID_ARRAY]).await?;
+        let parent_id: Option<u32> = match tx.query(&st, &[&[cid].as_ref()]).await?.first() {
+            Some(r) => r.try_get(6)?,
+            None => return Err(ResError::NotFound),
+        };
+
+        let (target_id, topic_ids, post_ids) = match req.mode {
+            RemoveMode::Move => {
+                let target = req.target_id.ok_or(ResError::BadRequest)?;
+
+                if tx.query(&st, &[&[target].as_ref()]).await?.is_empty() {
+                    return Err(ResError::BadRequestExplained(
+                        "Target category not found".into(),
+                    ));
+                }
+
+                let st = tx
+                    .prepare_typed(MOVE_CATEGORY_TOPICS, &[Type::OID, Type::OID])
+                    .await?;
+                let topic_ids = alive_ids(tx.query(&st, &[&cid, &target]).await?)?;
+
+                let st = tx
+                    .prepare_typed(MOVE_CATEGORY_POSTS, &[Type::OID, Type::OID])
+                    .await?;
+                let post_ids = alive_ids(tx.query(&st, &[&cid, &target]).await?)?;
+
+                (Some(target), topic_ids, post_ids)
+            }
+            RemoveMode::Cascade => {
+                let st = tx
+                    .prepare_typed(DELETE_CATEGORY_TOPICS, &[Type::OID, Type::OID])
+                    .await?;
+                let topic_ids = tx
+                    .query(&st, &[&cid, &uid])
+                    .await?
+                    .iter()
+                    .map(|r| r.try_get(0))
+                    .collect::<Result<Vec<u32>, _>>()?;
+
+                let st = tx
+                    .prepare_typed(SELECT_TOPICS_POST_IDS, &[Type::OID_ARRAY])
+                    .await?;
+                let post_ids = tx
+                    .query(&st, &[&topic_ids])
+                    .await?
+                    .iter()
+                    .map(|r| r.try_get(0))
+                    .collect::<Result<Vec<u32>, _>>()?;
+
+                (None, topic_ids, post_ids)
+            }
+        };
+
+        let st = tx
+            .prepare_typed(MOVE_CATEGORY_CHILDREN, &[Type::OID])
+            .await?;
+        let child_ids = tx
+            .query(&st, &[&cid])
+            .await?
+            .iter()
+            .map(|r| r.try_get(0))
+            .collect::<Result<Vec<u32>, _>>()?;
+
+        let st = tx.prepare_typed(DEL_CATEGORY, &[Type::OID]).await?;
+        tx.execute(&st, &[&cid]).await?;
+
+        tx.commit().await?;
+
+        Ok(CategoryRemoveReport {
+            category_id: cid,
+            target_id,
+            parent_id,
+            child_ids,
+            topic_ids,
+            post_ids,
+        })
+    }
+}
 
-        let st = cli.prepare(DEL_CATEGORY).await?;
-        cli.execute(&st, &[&cid]).await?;
+// replace all moderators of category.
+async fn set_moderators(tx: &Transaction<'_>, cid: u32, uids: &[u32]) -> Result<(), ResError> {
+    let st = tx
+        .prepare_typed(DEL_CATEGORY_MODERATORS, &[Type::OID])
+        .await?;
+    tx.execute(&st, &[&cid]).await?;
 
-        Ok(())
+    if !uids.is_empty() {
+        let st = tx
+            .prepare_typed(INSERT_CATEGORY_MODERATORS, &[Type::OID_ARRAY, Type::OID])
+            .await?;
+        tx.execute(&st, &[&uids, &cid]).await?;
     }
+    Ok(())
+}
+
+// collect ids from rows of (id, is_alive)
+fn alive_ids(rows: Vec<tokio_postgres::Row>) -> Result<Vec<u32>, ResError> {
+    let mut ids = Vec::new();
+    for r in rows.iter() {
+        if r.try_get::<_, bool>(1)? {
+            ids.push(r.try_get(0)?);
+        }
+    }
+    Ok(ids)
 }
 
 impl MyRedisPool {
@@ -138,4 +423,152 @@ impl MyRedisPool {
             }
         };
     }
+
+    // remove all keys of category. moved topics and posts are merged into target category's lists
+    // and deleted ones are removed from category:all lists.
+    pub(crate) async fn remove_category(&self, r: &CategoryRemoveReport) -> Result<(), ResError> {
+        let cid = r.category_id;
+
+        let mut conn = self.get().await?.get_conn().clone();
+
+        let mut pip = pipe();
+        pip.atomic();
+
+        match r.target_id {
+            Some(target) => {
+                for key in [
+                    "topics_time",
+                    "topics_reply",
+                    "topics_reaction",
+                    "posts_time",
+                    "pinned",
+                ]
+                .iter()
+                {
+                    let target_key = format!("category:{}:{}", target, key);
+                    pip.cmd("ZUNIONSTORE")
+                        .arg(target_key.as_str())
+                        .arg(2)
+                        .arg(target_key.as_str())
+                        .arg(&format!("category:{}:{}", cid, key))
+                        .arg("AGGREGATE")
+                        .arg("MAX")
+                        .ignore();
+                }
+
+                pip.cmd("HINCRBY")
+                    .arg(&format!("category:{}:set", target))
+                    .arg("topic_count_own")
+                    .arg(r.topic_ids.len())
+                    .ignore()
+                    .cmd("HINCRBY")
+                    .arg(&format!("category:{}:set", target))
+                    .arg("post_count_own")
+                    .arg(r.post_ids.len())
+                    .ignore();
+
+                // category_id field of topic and post sets are outdated. they will be rebuilt from database when queried.
+                let keys = r
+                    .topic_ids
+                    .iter()
+                    .map(|tid| format!("topic:{}:set", tid))
+                    .chain(r.post_ids.iter().map(|pid| format!("post:{}:set", pid)))
+                    .collect::<Vec<String>>();
+                if !keys.is_empty() {
+                    pip.cmd("DEL").arg(keys).ignore();
+                }
+            }
+            None => {
+                if !r.topic_ids.is_empty() {
+                    pip.cmd("ZREM")
+                        .arg("category:all:topics_time")
+                        .arg(&r.topic_ids)
+                        .ignore()
+                        .cmd("ZREM")
+                        .arg("category:all:topics_reply")
+                        .arg(&r.topic_ids)
+                        .ignore()
+                        .cmd("ZREM")
+                        .arg("category:all:topics_reaction")
+                        .arg(&r.topic_ids)
+                        .ignore()
+                        .cmd("ZREM")
+                        .arg("category:all:pinned")
+                        .arg(&r.topic_ids)
+                        .ignore();
+                }
+
+                // topic:{tid}:tags is kept for restoring.
+                for tid in r.topic_ids.iter() {
+                    let tags = topic_tags(&mut conn, *tid).await?;
+                    remove_tags(&mut pip, *tid, &tags);
+                }
+
+                let mut keys = Vec::new();
+                for tid in r.topic_ids.iter() {
+                    pip.cmd("LREM")
+                        .arg("category:all:list_pop")
+                        .arg(0)
+                        .arg(*tid)
+                        .ignore()
+                        .cmd("LREM")
+                        .arg("category:all:list_hot")
+                        .arg(0)
+                        .arg(*tid)
+                        .ignore();
+                    keys.push(format!("topic:{}:set", tid));
+                    keys.push(format!("topic:{}:set_perm", tid));
+                    keys.push(format!("topic:{}:posts_reply", tid));
+                    keys.push(format!("topic:{}:posts_time_created", tid));
+                }
+                for pid in r.post_ids.iter() {
+                    keys.push(format!("post:{}:set", pid));
+                    keys.push(format!("post:{}:set_perm", pid));
+                }
+                if !keys.is_empty() {
+                    pip.cmd("DEL").arg(keys).ignore();
+                }
+            }
+        }
+
+        // subcategories are moved to the parent of removed category.
+        let parent = r.parent_id.map(|id| id.to_string()).unwrap_or_default();
+        for id in r.child_ids.iter() {
+            pip.cmd("HSET")
+                .arg(&format!("category:{}:set", id))
+                .arg("parent_id")
+                .arg(parent.as_str())
+                .ignore();
+        }
+
+        pip.cmd("LREM")
+            .arg("category_id:meta")
+            .arg(0)
+            .arg(cid)
+            .ignore()
+            .cmd("DEL")
+            .arg(&[
+                format!("category:{}:set", cid),
+                format!("category:{}:topics_time", cid),
+                format!("category:{}:topics_reply", cid),
+                format!("category:{}:list_pop", cid),
+                format!("category:{}:list_hot", cid),
+                format!("category:{}:posts_time", cid),
+                format!("category:{}:pinned", cid),
+                format!("category:{}:topics_reaction", cid),
+            ])
+            .ignore();
+
+        pip.query_async(&mut conn).await.map_err(Into::into)
+    }
+
+    pub(crate) async fn remove_category_send_fail(
+        &self,
+        r: CategoryRemoveReport,
+        addr: CacheServiceAddr,
+    ) {
+        if self.remove_category(&r).await.is_err() {
+            let _ = addr.send(CacheFailedMessage::FailedCategoryRemove(r)).await;
+        }
+    }
 }
@@ -11,13 +11,19 @@ use tokio_postgres_tang::{Builder, Pool, PoolRef, PostgresManager};
 use crate::model::{common::SelfUserId, db_schema::TryFromRow, errors::ResError};
 
 // frequent used statements that are construct on start.
-const SELECT_TOPIC: &str = "SELECT * FROM topics WHERE id=ANY($1)";
-const SELECT_POST: &str = "SELECT * FROM posts WHERE id=ANY($1)";
+// soft deleted topics and posts are never returned.
+const SELECT_TOPIC: &str = "SELECT * FROM topics WHERE id=ANY($1) AND deleted_at IS NULL";
+// posts of a deleted topic are treated as deleted.
+const SELECT_POST: &str = "SELECT p.* FROM posts p JOIN topics t ON t.id = p.topic_id
+    WHERE p.id=ANY($1) AND p.deleted_at IS NULL AND t.deleted_at IS NULL";
 const SELECT_USER: &str = "SELECT * FROM users WHERE id=ANY($1)";
-const INSERT_PUB_MSG: &str =
-    "INSERT INTO public_messages1 (talk_id, text, time) VALUES ($1, $2, $3)";
-const INSERT_PRV_MSG: &str =
-    "INSERT INTO private_messages1 (from_id, to_id, text, time) VALUES ($1, $2, $3, $4)";
+// messages are batch inserted with reserved ids. conflict means the batch is already inserted by a retry.
+const INSERT_PUB_MSG: &str = "INSERT INTO public_messages (id, talk_id, user_id, text, time)
+    SELECT * FROM UNNEST($1::BIGINT[], $2::OID[], $3::OID[], $4::VARCHAR[], $5::TIMESTAMP[])
+    ON CONFLICT (id) DO NOTHING";
+const INSERT_PRV_MSG: &str = "INSERT INTO private_messages (id, from_id, to_id, text, time)
+    SELECT * FROM UNNEST($1::BIGINT[], $2::OID[], $3::OID[], $4::VARCHAR[], $5::TIMESTAMP[])
+    ON CONFLICT (id) DO NOTHING";
 
 #[derive(Clone)]
 pub struct MyPostgresPool(Pool<PostgresManager<NoTls>>);
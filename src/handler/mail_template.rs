@@ -0,0 +1,180 @@
+use std::{fs, path::Path};
+
+use hashbrown::HashMap;
+
+use crate::model::errors::ResError;
+
+// locale used when user's locale has no template.
+pub(crate) const DEFAULT_LOCALE: &str = "en";
+
+const TEMPLATE_NAMES: &[&str] = &["activation", "password_reset", "error_report"];
+
+macro_rules! embed_template {
+    ($locale: literal, $name: literal) => {
+        (
+            $locale,
+            $name,
+            include_str!(concat!(
+                "../../templates/mail/",
+                $locale,
+                "/",
+                $name,
+                ".subject"
+            )),
+            include_str!(concat!(
+                "../../templates/mail/",
+                $locale,
+                "/",
+                $name,
+                ".html"
+            )),
+            include_str!(concat!(
+                "../../templates/mail/",
+                $locale,
+                "/",
+                $name,
+                ".txt"
+            )),
+        )
+    };
+}
+
+// default templates are embedded from ./templates/mail at compile time.
+// (locale, name, subject, html, text)
+const EMBEDDED_TEMPLATES: &[(&str, &str, &str, &str, &str)] = &[
+    embed_template!("en", "activation"),
+    embed_template!("en", "password_reset"),
+    embed_template!("en", "error_report"),
+    embed_template!("zh", "activation"),
+    embed_template!("zh", "password_reset"),
+];
+
+#[derive(Clone, Default)]
+pub(crate) struct MailTemplate {
+    subject: String,
+    html: String,
+    text: String,
+}
+
+pub(crate) struct RenderedMail {
+    pub subject: String,
+    pub html: String,
+    pub text: String,
+}
+
+// templates are keyed by (locale, name).
+pub(crate) struct MailTemplates(HashMap<(String, String), MailTemplate>);
+
+impl MailTemplates {
+    // load the embedded templates and override them with the files found in dir.
+    // the override layout is the same as ./templates/mail: {dir}/{locale}/{name}.subject|html|txt.
+    // a missing file falls back to the embedded one.
+    pub(crate) fn load(dir: Option<&str>) -> Self {
+        let mut templates = HashMap::new();
+
+        for (locale, name, subject, html, text) in EMBEDDED_TEMPLATES.iter() {
+            templates.insert(
+                (locale.to_string(), name.to_string()),
+                MailTemplate {
+                    subject: subject.trim().to_owned(),
+                    html: html.to_string(),
+                    text: text.to_string(),
+                },
+            );
+        }
+
+        let mut t = MailTemplates(templates);
+
+        if let Some(dir) = dir {
+            let entries = fs::read_dir(dir).unwrap_or_else(|e| {
+                panic!(
+                    "Failed to read mail template directory {}. Error is: {:?}",
+                    dir, e
+                )
+            });
+
+            for entry in entries.filter_map(Result::ok) {
+                let path = entry.path();
+                if !path.is_dir() {
+                    continue;
+                }
+                if let Some(locale) = path.file_name().and_then(|l| l.to_str()) {
+                    t.override_locale(locale, &path);
+                }
+            }
+        }
+
+        t
+    }
+
+    fn override_locale(&mut self, locale: &str, path: &Path) {
+        for name in TEMPLATE_NAMES.iter() {
+            let read = |ext: &str| fs::read_to_string(path.join(format!("{}.{}", name, ext))).ok();
+
+            let (subject, html, text) = (read("subject"), read("html"), read("txt"));
+            if subject.is_none() && html.is_none() && text.is_none() {
+                continue;
+            }
+
+            let base = self.get(locale, name).cloned().unwrap_or_default();
+
+            self.0.insert(
+                (locale.to_owned(), name.to_string()),
+                MailTemplate {
+                    subject: subject.map(|s| s.trim().to_owned()).unwrap_or(base.subject),
+                    html: html.unwrap_or(base.html),
+                    text: text.unwrap_or(base.text),
+                },
+            );
+        }
+    }
+
+    // find template of locale and fall back to default locale.
+    fn get(&self, locale: &str, name: &str) -> Option<&MailTemplate> {
+        self.0
+            .get(&(locale.to_owned(), name.to_owned()))
+            .or_else(|| self.0.get(&(DEFAULT_LOCALE.to_owned(), name.to_owned())))
+    }
+
+    // replace all {{key}} in template with value.
+    pub(crate) fn render(
+        &self,
+        locale: &str,
+        name: &str,
+        args: &[(&str, &str)],
+    ) -> Result<RenderedMail, ResError> {
+        let t = self.get(locale, name).ok_or(ResError::MailingError)?;
+
+        let replace = |s: &str, is_html: bool| {
+            args.iter().fold(s.to_owned(), |s, (k, v)| {
+                let key = format!("{{{{{}}}}}", k);
+                if is_html {
+                    s.replace(key.as_str(), escape_html(v).as_str())
+                } else {
+                    s.replace(key.as_str(), v)
+                }
+            })
+        };
+
+        Ok(RenderedMail {
+            subject: replace(t.subject.as_str(), false),
+            html: replace(t.html.as_str(), true),
+            text: replace(t.text.as_str(), false),
+        })
+    }
+}
+
+fn escape_html(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            _ => out.push(c),
+        }
+    }
+    out
+}
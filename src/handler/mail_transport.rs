@@ -0,0 +1,125 @@
+use std::{env, fs, io::Write, path::PathBuf, time::Duration};
+
+use lettre::{
+    smtp::{
+        authentication::{Credentials, Mechanism},
+        ConnectionReuseParameters,
+    },
+    SendableEmail, SmtpClient, SmtpTransport, Transport,
+};
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+
+use crate::model::errors::ResError;
+use crate::util::env::MailTransportKind;
+
+// captured mails of MemoryTransport in raw message form.
+static MAIL_BOX: Lazy<Mutex<Vec<String>>> = Lazy::new(|| Mutex::new(Vec::new()));
+
+// MailTransport is used by MailerService to deliver built emails.
+pub trait MailTransport: Send {
+    fn send_mail(&mut self, email: SendableEmail) -> Result<(), ResError>;
+}
+
+pub(crate) fn build_transport(kind: &MailTransportKind) -> Box<dyn MailTransport> {
+    match kind {
+        MailTransportKind::Smtp => {
+            let server = env::var("MAIL_SERVER").expect("Mail server must be set in .env");
+            let username =
+                env::var("MAIL_USERNAME").expect("Mail server credentials must be set  in .env");
+            let password =
+                env::var("MAIL_PASSWORD").expect("Mail server credentials must be set in .env");
+
+            Box::new(SmtpMailTransport::new(&server, username, password))
+        }
+        MailTransportKind::File(dir) => Box::new(FileMailTransport::new(dir.as_deref())),
+        MailTransportKind::Memory => Box::new(MemoryMailTransport),
+    }
+}
+
+pub(crate) struct SmtpMailTransport(SmtpTransport);
+
+impl SmtpMailTransport {
+    pub(crate) fn new(server: &str, username: String, password: String) -> Self {
+        let transport = SmtpClient::new_simple(server)
+            .unwrap_or_else(|e| panic!("Failed to establish SmtpClient. Error is: {:?}", e))
+            .timeout(Some(Duration::new(1, 0)))
+            .credentials(Credentials::new(username, password))
+            .smtp_utf8(false)
+            .authentication_mechanism(Mechanism::Plain)
+            .connection_reuse(ConnectionReuseParameters::ReuseUnlimited)
+            .transport();
+
+        SmtpMailTransport(transport)
+    }
+}
+
+impl MailTransport for SmtpMailTransport {
+    fn send_mail(&mut self, email: SendableEmail) -> Result<(), ResError> {
+        Ok(self.0.send(email).map(|_| ())?)
+    }
+}
+
+// write every mail to a .eml file in the given directory. print to stdout if no directory given.
+pub(crate) struct FileMailTransport {
+    dir: Option<PathBuf>,
+}
+
+impl FileMailTransport {
+    pub(crate) fn new(dir: Option<&str>) -> Self {
+        let dir = dir.map(PathBuf::from);
+
+        if let Some(dir) = dir.as_ref() {
+            fs::create_dir_all(dir).unwrap_or_else(|e| {
+                panic!(
+                    "Failed to create mail directory {:?}. Error is: {:?}",
+                    dir, e
+                )
+            });
+        }
+
+        FileMailTransport { dir }
+    }
+}
+
+impl MailTransport for FileMailTransport {
+    fn send_mail(&mut self, email: SendableEmail) -> Result<(), ResError> {
+        let id = email.message_id().to_string();
+        let message = email
+            .message_to_string()
+            .map_err(|_| ResError::MailingError)?;
+
+        match self.dir.as_ref() {
+            Some(dir) => {
+                let path = dir.join(format!("{}.eml", id));
+                fs::File::create(path)
+                    .and_then(|mut f| f.write_all(message.as_bytes()))
+                    .map_err(|_| ResError::MailingError)
+            }
+            None => {
+                println!("{}", message);
+                Ok(())
+            }
+        }
+    }
+}
+
+// keep mails in memory so their content can be inspected with take_captured_mails.
+pub(crate) struct MemoryMailTransport;
+
+impl MailTransport for MemoryMailTransport {
+    fn send_mail(&mut self, email: SendableEmail) -> Result<(), ResError> {
+        let message = email
+            .message_to_string()
+            .map_err(|_| ResError::MailingError)?;
+
+        MAIL_BOX.lock().push(message);
+
+        Ok(())
+    }
+}
+
+// return and clear all mails captured by MemoryMailTransport.
+pub fn take_captured_mails() -> Vec<String> {
+    std::mem::take(&mut *MAIL_BOX.lock())
+}
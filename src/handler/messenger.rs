@@ -1,21 +1,19 @@
 use std::{env, future::Future, time::Duration};
 
 use actix_send::prelude::*;
-use futures::FutureExt;
 use hashbrown::HashMap;
 use hyper::{Body, Client, Request};
 use hyper_tls::HttpsConnector;
-use lettre::{
-    smtp::{
-        authentication::{Credentials, Mechanism},
-        ConnectionReuseParameters,
-    },
-    SmtpClient, Transport,
-};
 use lettre_email::Email;
+use redis::cmd;
 
-use crate::handler::cache::MyRedisPool;
+use crate::handler::{
+    cache::MyRedisPool,
+    mail_template::{MailTemplates, DEFAULT_LOCALE},
+    mail_transport::build_transport,
+};
 use crate::model::{
+    cache_schema::HashMapBrown,
     common::dur,
     errors::{RepError, ResError},
     messenger::{Mail, Mailer, SmsMessage, Twilio},
@@ -25,7 +23,13 @@ use crate::util::env::Env;
 
 const REPORT_INTERVAL: Duration = dur(600_000);
 const MAIL_INTERVAL: Duration = dur(500);
+// mails failed to send are kept in mail_retry hash with their attempts and retried on interval.
+const MAIL_RETRY_INTERVAL: Duration = dur(60_000);
+const MAIL_RETRY_MAX: u32 = 5;
 const SMS_INTERVAL: Duration = dur(500);
+// user can request one activation mail resend and one password reset mail in this many seconds.
+const ACTIVATION_MAIL_GATE: usize = 120;
+const RESET_MAIL_GATE: usize = 300;
 
 // MailerService is an actor runs a interval and read from redis cache and send mails to users.
 // It would also receive admin message and send it immediately.
@@ -33,40 +37,38 @@ const SMS_INTERVAL: Duration = dur(500);
 struct MailerService {
     cache_pool: MyRedisPool,
     mailer: Option<Mailer>,
+    rep_addr: Option<ErrReportServiceAddr>,
 }
 
 pub struct AdminMailMsg(String);
 
+// ErrReportService is started after MailerService so its address is passed in afterwards.
+pub struct ErrReportAddrMsg(ErrReportServiceAddr);
+
 #[handler_v2]
 impl MailerService {
     async fn handle_msg(&mut self, msg: AdminMailMsg) {
         let _ = self.handle_mail_admin(msg.0.as_str());
     }
+
+    async fn handle_rep_addr(&mut self, msg: ErrReportAddrMsg) {
+        self.rep_addr = Some(msg.0);
+    }
 }
 
 impl MailerService {
-    fn generate_mailer(mut self) -> Self {
-        let mail_server = env::var("MAIL_SERVER").expect("Mail server must be set in .env");
-        let username =
-            env::var("MAIL_USERNAME").expect("Mail server credentials must be set  in .env");
-        let password =
-            env::var("MAIL_PASSWORD").expect("Mail server credentials must be set in .env");
-
-        let server_url = env::var("SERVER_URL").expect("Server url must be set in .env");
+    fn generate_mailer(mut self, env: &Env) -> Self {
+        let server_url =
+            env::var("SERVER_URL").unwrap_or_else(|_| format!("http://{}", env.addr()));
         let self_addr = env::var("SELF_MAIL_ADDR").unwrap_or_else(|_| "Pixel@Share".to_owned());
         let self_name = env::var("SELF_MAIL_ALIAS").unwrap_or_else(|_| "PixelShare".to_owned());
 
-        let mailer = SmtpClient::new_simple(&mail_server)
-            .unwrap_or_else(|e| panic!("Failed to establish SmtpClient. Error is: {:?}", e))
-            .timeout(Some(Duration::new(1, 0)))
-            .credentials(Credentials::new(username, password))
-            .smtp_utf8(false)
-            .authentication_mechanism(Mechanism::Plain)
-            .connection_reuse(ConnectionReuseParameters::ReuseUnlimited)
-            .transport();
+        let transport = build_transport(env.mail_transport());
+        let templates = MailTemplates::load(env.mail_template_dir());
 
         self.mailer = Some(Mailer {
-            mailer,
+            transport,
+            templates,
             server_url,
             self_addr,
             self_name,
@@ -75,10 +77,63 @@ impl MailerService {
         self
     }
 
+    // send queued mails until mail_queue is empty.
+    async fn drain_mail_queue(&mut self) {
+        loop {
+            match self.handle_mail_user().await {
+                Ok(()) => continue,
+                Err(ResError::NoCache) => break,
+                Err(e) => {
+                    self.send_err_rep(e);
+                    break;
+                }
+            }
+        }
+    }
+
     async fn handle_mail_user(&mut self) -> Result<(), ResError> {
         let s = self.cache_pool.get_queue("mail_queue").await?;
         let mail = serde_json::from_str::<Mail>(s.as_str())?;
-        self.send_mail(&mail)
+        if let Err(e) = self.send_mail(&mail) {
+            // the mail is already removed from queue so it goes to retry.
+            self.cache_pool.add_mail_retry(s.as_str()).await?;
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    // retry failed mails. mails still failing after MAIL_RETRY_MAX attempts are dropped.
+    async fn retry_failed_mails(&mut self) -> Result<(), ResError> {
+        let mails = self.cache_pool.get_mail_retry().await?;
+
+        for (s, attempts) in mails.into_iter() {
+            let r = serde_json::from_str::<Mail>(s.as_str())
+                .map_err(ResError::from)
+                .and_then(|mail| self.send_mail(&mail));
+
+            match r {
+                Ok(()) => self.cache_pool.remove_mail_retry(s.as_str()).await?,
+                Err(e) => {
+                    if attempts + 1 >= MAIL_RETRY_MAX {
+                        self.cache_pool.remove_mail_retry(s.as_str()).await?;
+                    } else {
+                        self.cache_pool.add_mail_retry(s.as_str()).await?;
+                    }
+                    self.send_err_rep(e);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn send_err_rep(&self, e: ResError) {
+        if let Some(addr) = self.rep_addr.as_ref() {
+            let addr = addr.clone();
+            actix_rt::spawn(async move {
+                let _ = addr.send(ErrReportMsg(e)).await;
+            })
+        }
     }
 
     fn handle_mail_admin(&mut self, rep: &str) -> Result<(), ResError> {
@@ -89,33 +144,34 @@ impl MailerService {
     fn send_mail(&mut self, mail: &Mail) -> Result<(), ResError> {
         let mailer = self.mailer.as_mut().unwrap();
 
-        let (to, subject, html, text) = match *mail {
-            Mail::Activation { to, uuid } => (
-                to,
-                "Activate your PixelShare account",
-                format!(
-                    "<p>Please click the link below </br> {}/activation/{} </p>",
-                    &mailer.server_url, uuid
-                ),
-                "Activation link",
-            ),
+        let (to, locale, name, arg) = match *mail {
+            Mail::Activation { to, uuid, locale } => (to, locale, "activation", ("uuid", uuid)),
+            Mail::PasswordReset { to, uuid, locale } => {
+                (to, locale, "password_reset", ("uuid", uuid))
+            }
             Mail::ErrorReport { report } => (
                 mailer.self_addr.as_str(),
-                "Error Report",
-                report.to_owned(),
-                "",
+                DEFAULT_LOCALE,
+                "error_report",
+                ("report", report),
             ),
         };
 
+        let rendered = mailer.templates.render(
+            locale,
+            name,
+            &[("server_url", mailer.server_url.as_str()), arg],
+        )?;
+
         let mail = Email::builder()
             .to(to)
             .from((mailer.self_addr.as_str(), mailer.self_name.as_str()))
-            .subject(subject)
-            .alternative(html.as_str(), text)
+            .subject(rendered.subject)
+            .alternative(rendered.html, rendered.text)
             .build()?
             .into();
 
-        Ok(mailer.mailer.send(mail).map(|_| ())?)
+        mailer.transport.send_mail(mail)
     }
 }
 
@@ -304,14 +360,17 @@ pub(crate) async fn init_message_services(
 ) -> Option<ErrReportServiceAddr> {
     let mailer_addr = if env.use_mail() {
         let cache_pool1 = cache_pool.clone();
+        let env = env.clone();
         let builder = MailerService::builder(move || {
             let cache_pool = cache_pool1.clone();
-            async {
+            let env = env.clone();
+            async move {
                 let actor = MailerService {
                     cache_pool,
                     mailer: None,
+                    rep_addr: None,
                 };
-                actor.generate_mailer()
+                actor.generate_mailer(&env)
             }
         });
 
@@ -319,8 +378,17 @@ pub(crate) async fn init_message_services(
 
         addr.run_interval(MAIL_INTERVAL, |mailer| {
             Box::pin(async move {
-                // ToDo: handle error.
-                let _ = mailer.handle_mail_user().await;
+                mailer.drain_mail_queue().await;
+            })
+        })
+        .await
+        .expect("Failed to start MailerService interval task");
+
+        addr.run_interval(MAIL_RETRY_INTERVAL, |mailer| {
+            Box::pin(async move {
+                if let Err(e) = mailer.retry_failed_mails().await {
+                    mailer.send_err_rep(e);
+                }
             })
         })
         .await
@@ -360,8 +428,9 @@ pub(crate) async fn init_message_services(
     };
 
     let err_rep_addr = if env.use_rep() {
+        let mailer_addr1 = mailer_addr.clone();
         let builder = ErrReportService::builder(move || {
-            let mailer_addr = mailer_addr.clone();
+            let mailer_addr = mailer_addr1.clone();
             let sms_addr = sms_addr.clone();
             async {
                 ErrReportService {
@@ -383,6 +452,10 @@ pub(crate) async fn init_message_services(
         .await
         .expect("Failed to start SMSService interval task");
 
+        if let Some(mailer_addr) = mailer_addr.as_ref() {
+            let _ = mailer_addr.send(ErrReportAddrMsg(addr.clone())).await;
+        }
+
         Some(addr)
     } else {
         None
@@ -392,26 +465,94 @@ pub(crate) async fn init_message_services(
 }
 
 impl MyRedisPool {
-    pub(crate) async fn add_activation_mail(&self, u: Vec<User>) {
-        if let Some(u) = u.first() {
-            let uuid = uuid::Uuid::new_v4().to_string();
-            let mail = Mail::new_activation(u.email.as_str(), uuid.as_str());
-
-            if let Ok(m) = serde_json::to_string(&mail) {
-                if let Ok(pool_ref) = self.get().await {
-                    let conn = (&*pool_ref).clone();
-                    actix_rt::spawn(
-                        Self::add_activation_mail_cache(conn, u.id, uuid, m).map(|_| ()),
-                    );
-                }
-            }
+    // enqueue activation mail for a newly registered user.
+    pub(crate) async fn add_activation_mail(&self, u: &User) -> Result<(), ResError> {
+        let uuid = uuid::Uuid::new_v4().to_string();
+        let mail = Mail::new_activation(u.email.as_str(), uuid.as_str(), u.locale.as_str());
+        let m = serde_json::to_string(&mail)?;
+
+        let conn = self.get().await?.get_conn().clone();
+        Self::add_activation_mail_cache(conn, u.id, uuid, m).await
+    }
+
+    pub(crate) async fn resend_activation_mail(&self, u: &User) -> Result<(), ResError> {
+        if u.privilege != 1 {
+            return Err(ResError::BadRequestExplained(
+                "User is already activated".into(),
+            ));
         }
+
+        if !self
+            .mail_gate(format!("activation_lock:{}", u.id), ACTIVATION_MAIL_GATE)
+            .await?
+        {
+            return Err(ResError::TooManyRequests);
+        }
+
+        self.add_activation_mail(u).await
     }
 
-    pub(crate) async fn remove_activation_uuid(&self, uuid: &str) {
-        if let Ok(pool_ref) = self.get().await {
-            let conn = (&*pool_ref).clone();
-            actix_rt::spawn(Self::del_cache(conn, uuid.to_owned()).map(|_| ()));
+    pub(crate) async fn add_password_reset_mail(&self, u: &User) -> Result<(), ResError> {
+        // silently ignore the request if user already got a reset mail recently.
+        if !self
+            .mail_gate(format!("reset_lock:{}", u.id), RESET_MAIL_GATE)
+            .await?
+        {
+            return Ok(());
         }
+
+        let uuid = uuid::Uuid::new_v4().to_string();
+        let mail = Mail::new_password_reset(u.email.as_str(), uuid.as_str(), u.locale.as_str());
+        let m = serde_json::to_string(&mail)?;
+
+        let conn = self.get().await?.get_conn().clone();
+        Self::add_mail_cache(conn, u.id, format!("reset:{}", uuid), m).await
+    }
+
+    // return false if the lock key still exists.
+    async fn mail_gate(&self, key: String, secs: usize) -> Result<bool, ResError> {
+        let mut conn = self.get().await?.get_conn().clone();
+
+        let gate = cmd("SET")
+            .arg(key.as_str())
+            .arg(1)
+            .arg("EX")
+            .arg(secs)
+            .arg("NX")
+            .query_async::<_, Option<String>>(&mut conn)
+            .await?;
+
+        Ok(gate.is_some())
+    }
+
+    // mail_retry hash maps failed mail string to its failed attempts.
+    async fn add_mail_retry(&self, mail: &str) -> Result<(), ResError> {
+        let mut conn = self.get().await?.get_conn().clone();
+        cmd("HINCRBY")
+            .arg("mail_retry")
+            .arg(mail)
+            .arg(1)
+            .query_async::<_, u32>(&mut conn)
+            .await?;
+        Ok(())
+    }
+
+    async fn get_mail_retry(&self) -> Result<HashMap<String, u32>, ResError> {
+        let mut conn = self.get().await?.get_conn().clone();
+        let HashMapBrown(hm) = cmd("HGETALL")
+            .arg("mail_retry")
+            .query_async::<_, HashMapBrown<String, u32>>(&mut conn)
+            .await?;
+        Ok(hm)
+    }
+
+    async fn remove_mail_retry(&self, mail: &str) -> Result<(), ResError> {
+        let mut conn = self.get().await?.get_conn().clone();
+        cmd("HDEL")
+            .arg("mail_retry")
+            .arg(mail)
+            .query_async::<_, u32>(&mut conn)
+            .await?;
+        Ok(())
     }
 }
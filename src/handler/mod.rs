@@ -5,10 +5,19 @@ pub mod cache_update;
 pub mod category;
 pub mod data;
 pub mod db;
+pub mod mail_template;
+pub mod mail_transport;
 pub mod messenger;
+pub mod permission;
+pub mod pin;
 pub mod post;
 pub mod psn;
+pub mod reaction;
+pub mod read;
+pub mod search;
 pub mod stream;
+pub mod tag;
 pub mod talk;
+pub mod talk_fanout;
 pub mod topic;
 pub mod user;
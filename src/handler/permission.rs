@@ -0,0 +1,222 @@
+use std::future::Future;
+use std::pin::Pin;
+
+use actix_web::{dev, FromRequest, HttpRequest};
+use tokio_postgres::types::{ToSql, Type};
+
+use crate::handler::{auth::UserJwt, data::DataRc, db::MyPostgresPool};
+use crate::model::{
+    category::{Category, Visibility},
+    errors::ResError,
+    permission::{Permission, PermissionSet, RoleRequest, Scope, UserRole},
+};
+
+// permissions of all roles granted to user globally. scope clause is appended to include the category roles.
+const SELECT_PERMISSIONS: &str = "SELECT DISTINCT rp.permission FROM user_roles ur
+    JOIN role_permissions rp ON rp.role_id = ur.role_id
+    WHERE ur.user_id=$1 AND (ur.category_id IS NULL";
+
+const INSERT_USER_ROLE: &str = "INSERT INTO user_roles (user_id, role_id, category_id)
+    SELECT $1, id, $3 FROM roles WHERE name=$2
+    ON CONFLICT DO NOTHING";
+
+const DELETE_USER_ROLE: &str = "DELETE FROM user_roles
+    WHERE user_id=$1 AND role_id=(SELECT id FROM roles WHERE name=$2)
+    AND category_id IS NOT DISTINCT FROM $3";
+
+const SELECT_USER_ROLES: &str = "SELECT ur.user_id, r.name, ur.category_id FROM user_roles ur
+    JOIN roles r ON r.id = ur.role_id
+    WHERE ur.user_id=$1
+    ORDER BY r.id";
+
+const SELECT_ROLE: &str = "SELECT id FROM roles WHERE name=$1";
+
+// jwt extractor with a permission resolver.
+// handlers declare it when they need permissions and check them by name instead of comparing privilege levels.
+pub struct UserPerm {
+    pub jwt: UserJwt,
+    db_pool: DataRc<MyPostgresPool>,
+}
+
+impl FromRequest for UserPerm {
+    type Error = ResError;
+    type Future = Pin<Box<dyn Future<Output = Result<Self, Self::Error>>>>;
+    type Config = ();
+
+    fn from_request(req: &HttpRequest, payload: &mut dev::Payload) -> Self::Future {
+        let jwt = UserJwt::from_request(req, payload);
+        let db_pool = req.app_data::<DataRc<MyPostgresPool>>().cloned();
+
+        Box::pin(async move {
+            let jwt = jwt.await?;
+            let db_pool = db_pool.ok_or(ResError::InternalServerError)?;
+            Ok(UserPerm { jwt, db_pool })
+        })
+    }
+}
+
+impl UserPerm {
+    // blocked and inactive users have no permission no matter what roles they have.
+    pub async fn resolve(&self, scope: Scope) -> Result<PermissionSet, ResError> {
+        if self.jwt.check_privilege().is_err() {
+            return Ok(PermissionSet::default());
+        }
+        self.db_pool.get_permissions(self.jwt.user_id, scope).await
+    }
+
+    pub async fn require(&self, p: Permission, scope: Scope) -> Result<(), ResError> {
+        self.resolve(scope).await?.require(p)
+    }
+}
+
+// roles and global permissions of the caller used to check category visibility.
+// anonymous, blocked and inactive callers could only see public categories.
+pub struct Viewer {
+    active: bool,
+    roles: Vec<UserRole>,
+    perms: PermissionSet,
+}
+
+impl Viewer {
+    pub fn can_view(&self, c: &Category) -> bool {
+        if c.visibility == Visibility::Public {
+            return true;
+        }
+        if !self.active {
+            return false;
+        }
+        // moderators and other roles granted in the category always see it.
+        if self.perms.has(Permission::CategoryManage)
+            || self.roles.iter().any(|r| r.category_id == Some(c.id))
+        {
+            return true;
+        }
+        match c.visibility {
+            Visibility::Role => match c.role.as_ref() {
+                Some(role) => self
+                    .roles
+                    .iter()
+                    .any(|r| r.category_id.is_none() && &r.role == role),
+                None => false,
+            },
+            _ => true,
+        }
+    }
+}
+
+impl MyPostgresPool {
+    pub(crate) async fn get_viewer(&self, jwt: Option<&UserJwt>) -> Result<Viewer, ResError> {
+        match jwt {
+            Some(jwt) if jwt.check_privilege().is_ok() => Ok(Viewer {
+                active: true,
+                roles: self.get_user_roles(jwt.user_id).await?,
+                perms: self.get_permissions(jwt.user_id, Scope::Global).await?,
+            }),
+            _ => Ok(Viewer {
+                active: false,
+                roles: vec![],
+                perms: PermissionSet::default(),
+            }),
+        }
+    }
+
+    pub(crate) async fn get_permissions(
+        &self,
+        uid: u32,
+        scope: Scope,
+    ) -> Result<PermissionSet, ResError> {
+        let mut query = String::from(SELECT_PERMISSIONS);
+        let id;
+        let mut params: Vec<&(dyn ToSql + Sync)> = vec![&uid];
+
+        match scope {
+            Scope::Global => query.push(')'),
+            Scope::Category(cid) => {
+                id = cid;
+                query.push_str(" OR ur.category_id=$2)");
+                params.push(&id);
+            }
+            Scope::Topic(tid) => {
+                id = tid;
+                query.push_str(" OR ur.category_id=(SELECT category_id FROM topics WHERE id=$2))");
+                params.push(&id);
+            }
+            Scope::Post(pid) => {
+                id = pid;
+                query.push_str(" OR ur.category_id=(SELECT category_id FROM posts WHERE id=$2))");
+                params.push(&id);
+            }
+        }
+
+        let pool = self.get().await?;
+        let (cli, _) = &*pool;
+
+        let st = cli.prepare(query.as_str()).await?;
+        let mut perms = Vec::new();
+        for row in cli.query(&st, &params).await?.iter() {
+            let p: &str = row.try_get(0)?;
+            // unknown permissions in database are ignored.
+            if let Some(p) = Permission::from_name(p) {
+                perms.push(p);
+            }
+        }
+
+        Ok(PermissionSet(perms))
+    }
+
+    pub(crate) async fn grant_role(&self, req: &RoleRequest) -> Result<Vec<UserRole>, ResError> {
+        self.check_role(req.role.as_str()).await?;
+        self.execute_role(INSERT_USER_ROLE, req).await?;
+        self.get_user_roles(req.user_id).await
+    }
+
+    pub(crate) async fn revoke_role(&self, req: &RoleRequest) -> Result<Vec<UserRole>, ResError> {
+        self.check_role(req.role.as_str()).await?;
+        self.execute_role(DELETE_USER_ROLE, req).await?;
+        self.get_user_roles(req.user_id).await
+    }
+
+    pub(crate) async fn get_user_roles(&self, uid: u32) -> Result<Vec<UserRole>, ResError> {
+        let pool = self.get().await?;
+        let (cli, _) = &*pool;
+
+        let st = cli.prepare_typed(SELECT_USER_ROLES, &[Type::OID]).await?;
+        cli.query(&st, &[&uid])
+            .await?
+            .iter()
+            .map(|r| {
+                Ok::<_, ResError>(UserRole {
+                    user_id: r.try_get(0)?,
+                    role: r.try_get(1)?,
+                    category_id: r.try_get(2)?,
+                })
+            })
+            .collect()
+    }
+
+    pub(crate) async fn check_role(&self, role: &str) -> Result<(), ResError> {
+        let pool = self.get().await?;
+        let (cli, _) = &*pool;
+
+        let st = cli.prepare_typed(SELECT_ROLE, &[Type::VARCHAR]).await?;
+        if cli.query(&st, &[&role]).await?.is_empty() {
+            return Err(ResError::BadRequestExplained(format!(
+                "Role {} not found",
+                role
+            )));
+        }
+        Ok(())
+    }
+
+    async fn execute_role(&self, query: &str, req: &RoleRequest) -> Result<(), ResError> {
+        let pool = self.get().await?;
+        let (cli, _) = &*pool;
+
+        let st = cli
+            .prepare_typed(query, &[Type::OID, Type::VARCHAR, Type::OID])
+            .await?;
+        cli.execute(&st, &[&req.user_id, &req.role, &req.category_id])
+            .await?;
+        Ok(())
+    }
+}
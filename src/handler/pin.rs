@@ -0,0 +1,209 @@
+use chrono::Utc;
+use redis::{aio::MultiplexedConnection, pipe, Pipeline};
+use tokio_postgres::types::{ToSql, Type};
+
+use crate::handler::{
+    cache::{MyRedisPool, TOPIC_U8},
+    cache_update::{CacheFailedMessage, CacheServiceAddr},
+    db::{MyPostgresPool, ParseRowStream},
+};
+use crate::model::{
+    errors::ResError,
+    pin::{PinRequest, TopicPin},
+    topic::Topic,
+};
+
+const INSERT_TOPIC_PIN: &str = "INSERT INTO topic_pins (topic_id, is_global, pinned_by, expires_at)
+    VALUES ($1, $2, $3, $4)
+    ON CONFLICT (topic_id, is_global) DO UPDATE SET
+    pinned_by=EXCLUDED.pinned_by, pinned_at=CURRENT_TIMESTAMP, expires_at=EXCLUDED.expires_at";
+const DEL_TOPIC_PIN: &str = "DELETE FROM topic_pins WHERE topic_id=$1 AND is_global=$2";
+// expired pins are left in table and they are ignored when loading.
+pub(crate) const SELECT_TOPIC_PINS: &str =
+    "SELECT p.topic_id, t.category_id, p.is_global, p.expires_at FROM topic_pins p
+    JOIN topics t ON t.id = p.topic_id
+    WHERE t.deleted_at IS NULL AND (p.expires_at IS NULL OR p.expires_at > CURRENT_TIMESTAMP)";
+
+impl MyPostgresPool {
+    pub(crate) async fn set_topic_pin(
+        &self,
+        t: &Topic,
+        req: &PinRequest,
+        uid: u32,
+    ) -> Result<TopicPin, ResError> {
+        let pool = self.get().await?;
+        let (cli, _) = &*pool;
+
+        let is_global = req.scope.is_global();
+
+        if req.is_pinned {
+            let st = cli
+                .prepare_typed(
+                    INSERT_TOPIC_PIN,
+                    &[Type::OID, Type::BOOL, Type::OID, Type::TIMESTAMP],
+                )
+                .await?;
+            cli.execute(&st, &[&t.id, &is_global, &uid, &req.expires_at])
+                .await?;
+        } else {
+            let st = cli
+                .prepare_typed(DEL_TOPIC_PIN, &[Type::OID, Type::BOOL])
+                .await?;
+            cli.execute(&st, &[&t.id, &is_global]).await?;
+        }
+
+        Ok(TopicPin {
+            topic_id: t.id,
+            category_id: t.category_id,
+            is_global,
+            is_pinned: req.is_pinned,
+            expires_at: req.expires_at.filter(|_| req.is_pinned),
+        })
+    }
+
+    // alive pins of topic.
+    pub(crate) async fn get_topic_pins(&self, tid: u32) -> Result<Vec<TopicPin>, ResError> {
+        let pool = self.get().await?;
+        let (cli, _) = &*pool;
+
+        let query = format!("{} AND p.topic_id=$1", SELECT_TOPIC_PINS);
+        let st = cli.prepare_typed(query.as_str(), &[Type::OID]).await?;
+        let params: [&(dyn ToSql + Sync); 1] = [&tid];
+
+        cli.query_raw(&st, params.iter().map(|s| *s as _))
+            .await?
+            .parse_row()
+            .await
+    }
+}
+
+impl MyRedisPool {
+    pub(crate) async fn update_topic_pin(&self, p: &TopicPin) -> Result<(), ResError> {
+        let mut conn = self.get().await?.get_conn().clone();
+
+        let mut pip = pipe();
+        pip.atomic();
+        if p.is_pinned {
+            add_pins(&mut pip, std::slice::from_ref(p));
+        } else {
+            pip.cmd("ZREM").arg(p.list_key()).arg(p.topic_id).ignore();
+        }
+
+        pip.query_async(&mut conn).await.map_err(Into::into)
+    }
+
+    // replace all pins of topic with the given ones.
+    pub(crate) async fn set_topic_pins(
+        &self,
+        t: &Topic,
+        pins: &[TopicPin],
+    ) -> Result<(), ResError> {
+        let mut conn = self.get().await?.get_conn().clone();
+
+        let mut pip = pipe();
+        pip.atomic();
+        remove_pins(&mut pip, t);
+        add_pins(&mut pip, pins);
+
+        pip.query_async(&mut conn).await.map_err(Into::into)
+    }
+
+    pub(crate) async fn update_topic_pin_send_fail(&self, p: TopicPin, addr: CacheServiceAddr) {
+        if self.update_topic_pin(&p).await.is_err() {
+            let _ = addr
+                .send(CacheFailedMessage::FailedTopicPin(p.topic_id))
+                .await;
+        }
+    }
+}
+
+pub(crate) fn add_pins(pip: &mut Pipeline, pins: &[TopicPin]) {
+    for p in pins.iter() {
+        pip.cmd("ZADD")
+            .arg(p.list_key())
+            .arg(p.score())
+            .arg(p.topic_id)
+            .ignore();
+    }
+}
+
+pub(crate) fn remove_pins(pip: &mut Pipeline, t: &Topic) {
+    pip.cmd("ZREM")
+        .arg(&format!("category:{}:pinned", t.category_id))
+        .arg(t.id)
+        .ignore()
+        .cmd("ZREM")
+        .arg("category:all:pinned")
+        .arg(t.id)
+        .ignore();
+}
+
+// global pins come first and category pins follow. pins with no expire time are on top.
+async fn pinned_ids(
+    conn: &mut MultiplexedConnection,
+    cid: Option<u32>,
+) -> Result<Vec<u32>, ResError> {
+    let now = format!("({}", Utc::now().naive_utc().timestamp_millis());
+
+    let mut pip = pipe();
+    pip.cmd("ZREVRANGEBYSCORE")
+        .arg("category:all:pinned")
+        .arg("+inf")
+        .arg(now.as_str());
+    if let Some(cid) = cid {
+        pip.cmd("ZREVRANGEBYSCORE")
+            .arg(&format!("category:{}:pinned", cid))
+            .arg("+inf")
+            .arg(now.as_str());
+    }
+
+    let lists = pip.query_async::<_, Vec<Vec<u32>>>(conn).await?;
+
+    let mut ids = Vec::new();
+    for id in lists.into_iter().flatten() {
+        if !ids.contains(&id) {
+            ids.push(id);
+        }
+    }
+    Ok(ids)
+}
+
+// prepend pinned topics to the first page. pinned topics are removed from the page so they are not shown twice
+// and the following pages are not shifted.
+pub(crate) async fn with_pinned(
+    conn: &mut MultiplexedConnection,
+    cid: Option<u32>,
+    page: usize,
+    ids: Result<Vec<u32>, ResError>,
+) -> Result<(Vec<Topic>, Vec<u32>), ResError> {
+    let mut pinned = if page == 1 {
+        pinned_ids(conn, cid).await?
+    } else {
+        Vec::new()
+    };
+
+    let ids = match ids {
+        Ok(ids) => ids,
+        Err(ResError::NoContent) if !pinned.is_empty() => Vec::new(),
+        Err(e) => return Err(e),
+    };
+
+    for id in ids.into_iter() {
+        if !pinned.contains(&id) {
+            pinned.push(id);
+        }
+    }
+
+    MyRedisPool::from_redis_with_perm_uids(conn, pinned, TOPIC_U8).await
+}
+
+// helper function for startup.
+pub(crate) async fn build_pins_cache(
+    conn: &mut MultiplexedConnection,
+    pins: Vec<TopicPin>,
+) -> Result<(), ResError> {
+    let mut pip = pipe();
+    pip.atomic();
+    add_pins(&mut pip, &pins);
+    pip.query_async(conn).await.map_err(Into::into)
+}
@@ -1,6 +1,6 @@
 use std::future::Future;
 
-use chrono::Utc;
+use chrono::{NaiveDateTime, Utc};
 use tokio_postgres::types::{ToSql, Type};
 
 use crate::handler::{
@@ -11,6 +11,7 @@ use crate::handler::{
 use crate::model::{
     errors::ResError,
     post::{Post, PostRequest},
+    revision::PostRevision,
 };
 
 const INSERT_POST: &str =
@@ -29,7 +30,101 @@ const INSERT_POST_TYPES: &[Type; 8] = &[
     Type::TIMESTAMP,
 ];
 
+// page size of the top level posts in a reply tree.
+const THREAD_LIMIT: i64 = 20;
+// default depth, max depth and max total posts of a reply tree.
+const THREAD_DEPTH: u32 = 3;
+const THREAD_MAX_DEPTH: u32 = 8;
+const THREAD_MAX_POSTS: i64 = 500;
+
+// walk the reply tree from a page of posts replied to $2(or top level posts when $2 is null).
+// path is used to return the ids in depth first order. posts of invisible topic are not returned.
+const THREAD_IDS: &str = "WITH RECURSIVE thread AS (
+    (SELECT id, 1 AS depth, ARRAY[id] AS path FROM posts
+    WHERE topic_id=$1 AND post_id IS NOT DISTINCT FROM $2 AND deleted_at IS NULL
+    AND EXISTS (SELECT 1 FROM topics WHERE id=$1 AND is_visible)
+    ORDER BY id LIMIT $3 OFFSET $4)
+    UNION ALL
+    SELECT p.id, t.depth + 1, t.path || p.id FROM posts p
+    JOIN thread t ON p.post_id = t.id
+    WHERE t.depth < $5 AND p.deleted_at IS NULL
+)
+SELECT id FROM thread ORDER BY path LIMIT $6";
+
+const THREAD_IDS_TYPES: &[Type; 6] = &[
+    Type::OID,
+    Type::OID,
+    Type::INT8,
+    Type::INT8,
+    Type::INT4,
+    Type::INT8,
+];
+
+const DELETE_POST: &str =
+    "UPDATE posts SET deleted_at=CURRENT_TIMESTAMP, deleted_by=$1 WHERE id=$2 AND deleted_at IS NULL";
+
+// post of a deleted topic can't be restored.
+const RESTORE_POST: &str = "UPDATE posts SET deleted_at=NULL, deleted_by=NULL
+    WHERE id=$1 AND deleted_at IS NOT NULL
+    AND topic_id IN (SELECT id FROM topics WHERE deleted_at IS NULL)
+    RETURNING *";
+
+const SELECT_DELETED_POST: &str = "SELECT * FROM posts WHERE id=$1 AND deleted_at IS NOT NULL";
+
+// keep the content before update as a revision. the row is locked until the update is committed.
+const INSERT_POST_REVISION: &str = "INSERT INTO post_revisions (post_id, post_content, edited_by)
+    SELECT id, post_content, $1 FROM posts WHERE id=$2 AND deleted_at IS NULL
+    AND topic_id IN (SELECT id FROM topics WHERE deleted_at IS NULL)";
+
+const SELECT_POST_REVISIONS: &str =
+    "SELECT * FROM post_revisions WHERE post_id=$1 ORDER BY id DESC";
+
+// category of post and if it's alive in a visible topic. used to check access to revisions.
+const SELECT_POST_REVISION_TARGET: &str = "SELECT p.category_id,
+    t.is_visible AND p.deleted_at IS NULL AND t.deleted_at IS NULL
+    FROM posts p JOIN topics t ON t.id = p.topic_id WHERE p.id=$1";
+
+const SELECT_POST_REVISION: &str = "SELECT * FROM post_revisions WHERE id=ANY($1)";
+
+// reply count and last reply time of a post. deleted replies are not counted.
+const POST_STAT: &str =
+    "SELECT COUNT(id), MAX(created_at) FROM posts WHERE post_id=$1 AND deleted_at IS NULL";
+
 impl MyPostgresPool {
+    // return post ids of a reply tree in depth first order.
+    pub(crate) async fn get_thread_ids(
+        &self,
+        tid: u32,
+        root: Option<u32>,
+        page: usize,
+        depth: Option<u32>,
+    ) -> Result<Vec<u32>, ResError> {
+        let offset = (page.max(1) as i64 - 1) * THREAD_LIMIT;
+        let depth = depth.unwrap_or(THREAD_DEPTH).max(1).min(THREAD_MAX_DEPTH) as i32;
+
+        let pool = self.get().await?;
+        let (cli, _) = &*pool;
+
+        let st = cli.prepare_typed(THREAD_IDS, THREAD_IDS_TYPES).await?;
+        let params: [&(dyn ToSql + Sync); 6] = [
+            &tid,
+            &root,
+            &THREAD_LIMIT,
+            &offset,
+            &depth,
+            &THREAD_MAX_POSTS,
+        ];
+
+        let ids = cli
+            .query(&st, &params)
+            .await?
+            .iter()
+            .map(|r| r.try_get(0))
+            .collect::<Result<Vec<u32>, _>>()?;
+
+        Ok(ids)
+    }
+
     pub async fn add_post(&self, p: PostRequest) -> Result<Vec<Post>, ResError> {
         let uid = p.user_id.as_ref().ok_or(ResError::BadRequest)?;
         let tid = p.topic_id.as_ref().ok_or(ResError::BadRequest)?;
@@ -51,18 +146,12 @@ impl MyPostgresPool {
             .await
     }
 
-    pub async fn update_post(&self, p: PostRequest) -> Result<Vec<Post>, ResError> {
+    // editor is the user id of who made the update and is stored with the revision.
+    pub async fn update_post(&self, p: PostRequest, editor: u32) -> Result<Vec<Post>, ResError> {
         let mut query = String::from("UPDATE posts SET");
         let mut params = Vec::new();
         let mut index = 1u8;
 
-        if let Some(s) = p.topic_id.as_ref() {
-            query.push_str(" topic_id=$");
-            query.push_str(index.to_string().as_str());
-            query.push_str(",");
-            params.push(s as &(dyn ToSql + Sync));
-            index += 1;
-        }
         if let Some(s) = p.post_id.as_ref() {
             query.push_str(" post_id=$");
             query.push_str(index.to_string().as_str());
@@ -89,7 +178,9 @@ impl MyPostgresPool {
             return Err(ResError::BadRequest);
         }
 
-        query.push_str(" updated_at=DEFAULT WHERE id=$");
+        query.push_str(" updated_at=DEFAULT WHERE deleted_at IS NULL");
+        query
+            .push_str(" AND topic_id IN (SELECT id FROM topics WHERE deleted_at IS NULL) AND id=$");
         query.push_str(index.to_string().as_str());
         params.push(p.id.as_ref().unwrap() as &(dyn ToSql + Sync));
         index += 1;
@@ -101,6 +192,99 @@ impl MyPostgresPool {
         }
         query.push_str(" RETURNING *");
 
+        let mut pool = self.get().await?;
+        let (cli, _) = &mut *pool;
+
+        let tx = cli.transaction().await?;
+
+        // only content change is stored as revision.
+        if p.post_content.is_some() {
+            let mut rev = String::from(INSERT_POST_REVISION);
+            let mut rev_params: Vec<&(dyn ToSql + Sync)> = vec![&editor, p.id.as_ref().unwrap()];
+            if let Some(s) = p.user_id.as_ref() {
+                rev.push_str(" AND user_id=$3");
+                rev_params.push(s);
+            }
+            rev.push_str(" FOR UPDATE");
+
+            let st = tx.prepare_typed(rev.as_str(), &[]).await?;
+            tx.execute(&st, &rev_params).await?;
+        }
+
+        let st = tx.prepare_typed(query.as_str(), &[]).await?;
+        let p = tx
+            .query_raw(&st, params.iter().map(|s| *s as _))
+            .await?
+            .parse_row()
+            .await?;
+
+        tx.commit().await?;
+
+        Ok(p)
+    }
+
+    pub(crate) async fn get_post_revisions(&self, pid: u32) -> Result<Vec<PostRevision>, ResError> {
+        let pool = self.get().await?;
+        let (cli, _) = &*pool;
+
+        let st = cli
+            .prepare_typed(SELECT_POST_REVISIONS, &[Type::OID])
+            .await?;
+        cli.query_raw(&st, [&pid as &(dyn ToSql + Sync)].iter().map(|s| *s as _))
+            .await?
+            .parse_row()
+            .await
+    }
+
+    pub(crate) async fn get_post_revision_target(&self, pid: u32) -> Result<(u32, bool), ResError> {
+        let pool = self.get().await?;
+        let (cli, _) = &*pool;
+
+        let st = cli
+            .prepare_typed(SELECT_POST_REVISION_TARGET, &[Type::OID])
+            .await?;
+        let row = cli
+            .query(&st, &[&pid])
+            .await?
+            .into_iter()
+            .next()
+            .ok_or(ResError::NotFound)?;
+
+        Ok((row.try_get(0)?, row.try_get(1)?))
+    }
+
+    pub(crate) async fn get_post_revisions_by_id(
+        &self,
+        ids: &[i32],
+    ) -> Result<Vec<PostRevision>, ResError> {
+        let pool = self.get().await?;
+        let (cli, _) = &*pool;
+
+        let st = cli
+            .prepare_typed(SELECT_POST_REVISION, &[Type::INT4_ARRAY])
+            .await?;
+        cli.query_raw(&st, [&ids as &(dyn ToSql + Sync)].iter().map(|s| *s as _))
+            .await?
+            .parse_row()
+            .await
+    }
+
+    // soft delete a post. uid is used to make sure only the author can delete the post.
+    pub(crate) async fn delete_post(
+        &self,
+        pid: u32,
+        deleted_by: u32,
+        uid: Option<u32>,
+    ) -> Result<Vec<Post>, ResError> {
+        let mut query = String::from(DELETE_POST);
+        let mut params: Vec<&(dyn ToSql + Sync)> = vec![&deleted_by, &pid];
+
+        if let Some(uid) = uid.as_ref() {
+            query.push_str(" AND user_id=$3");
+            params.push(uid);
+        }
+        query.push_str(" RETURNING *");
+
         let pool = self.get().await?;
         let (cli, _) = &*pool;
 
@@ -111,6 +295,43 @@ impl MyPostgresPool {
             .await
     }
 
+    pub(crate) async fn restore_post(&self, pid: u32) -> Result<Vec<Post>, ResError> {
+        let pool = self.get().await?;
+        let (cli, _) = &*pool;
+
+        let st = cli.prepare_typed(RESTORE_POST, &[Type::OID]).await?;
+        cli.query_raw(&st, [&pid as &(dyn ToSql + Sync)].iter().map(|s| *s as _))
+            .await?
+            .parse_row()
+            .await
+    }
+
+    pub(crate) async fn get_deleted_post(&self, pid: u32) -> Result<Vec<Post>, ResError> {
+        let pool = self.get().await?;
+        let (cli, _) = &*pool;
+
+        let st = cli.prepare_typed(SELECT_DELETED_POST, &[Type::OID]).await?;
+        cli.query_raw(&st, [&pid as &(dyn ToSql + Sync)].iter().map(|s| *s as _))
+            .await?
+            .parse_row()
+            .await
+    }
+
+    // return reply_count and last_reply_time of a post.
+    pub(crate) async fn get_post_stat(
+        &self,
+        pid: u32,
+    ) -> Result<(u32, Option<NaiveDateTime>), ResError> {
+        let pool = self.get().await?;
+        let (cli, _) = &*pool;
+
+        let st = cli.prepare_typed(POST_STAT, &[Type::OID]).await?;
+        let row = cli.query_one(&st, &[&pid]).await?;
+
+        let count: i64 = row.try_get(0)?;
+        Ok((count as u32, row.try_get(1)?))
+    }
+
     pub(crate) async fn get_posts(&self, pids: &[u32]) -> Result<(Vec<Post>, Vec<u32>), ResError> {
         let pool = self.get().await?;
         let (cli, sts) = &*pool;
@@ -184,4 +405,37 @@ impl MyRedisPool {
             }
         }
     }
+
+    pub(crate) async fn remove_post_send_fail(&self, p: Vec<Post>, addr: CacheServiceAddr) {
+        if let Some(p) = p.first() {
+            if self.remove_post(p).await.is_err() {
+                let _ = addr.send(CacheFailedMessage::FailedPostDelete(p.id)).await;
+            }
+        }
+    }
+
+    // reply stat of post is queried from database as the cache of it is removed when deleting.
+    pub(crate) async fn restore_post_with_stat(
+        &self,
+        db_pool: &MyPostgresPool,
+        p: &Post,
+    ) -> Result<(), ResError> {
+        let (count, last_reply_time) = db_pool.get_post_stat(p.id).await?;
+        let reactions = db_pool.get_post_reaction_counts(p.id).await?;
+        self.restore_post(p, count, last_reply_time).await?;
+        self.set_reaction_counts(&reactions).await
+    }
+
+    pub(crate) async fn restore_post_send_fail(
+        &self,
+        db_pool: &MyPostgresPool,
+        p: Vec<Post>,
+        addr: CacheServiceAddr,
+    ) {
+        if let Some(p) = p.first() {
+            if self.restore_post_with_stat(db_pool, p).await.is_err() {
+                let _ = addr.send(CacheFailedMessage::FailedPostRestore(p.id)).await;
+            }
+        }
+    }
 }
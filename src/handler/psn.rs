@@ -394,14 +394,6 @@ impl PSNRequest {
         }
     }
 
-    pub(crate) fn check_privilege(self, privilege: u32) -> Result<Self, ResError> {
-        if privilege < 9 {
-            Err(ResError::Unauthorized)
-        } else {
-            Ok(self)
-        }
-    }
-
     pub(crate) fn attach_user_id(self, uid: u32) -> Self {
         if let PSNRequest::Activation {
             online_id, code, ..
@@ -0,0 +1,284 @@
+use redis::{aio::MultiplexedConnection, pipe, Pipeline};
+use tokio_postgres::{
+    types::{ToSql, Type},
+    Client,
+};
+
+use crate::handler::{
+    cache::MyRedisPool,
+    cache_update::{CacheFailedMessage, CacheServiceAddr},
+    db::MyPostgresPool,
+};
+use crate::model::{
+    errors::ResError,
+    reaction::{ReactionCounts, ReactionReport, ReactionRequest, ReactionTarget},
+};
+
+const SELECT_TOPIC_TARGET: &str =
+    "SELECT id, category_id FROM topics WHERE id=$1 AND deleted_at IS NULL";
+const SELECT_POST_TARGET: &str = "SELECT p.topic_id, p.category_id FROM posts p
+    JOIN topics t ON t.id = p.topic_id
+    WHERE p.id=$1 AND p.deleted_at IS NULL AND t.deleted_at IS NULL";
+
+const INSERT_TOPIC_REACTION: &str =
+    "INSERT INTO topic_reactions (topic_id, user_id, reaction) VALUES ($1, $2, $3)
+    ON CONFLICT DO NOTHING";
+const DEL_TOPIC_REACTION: &str =
+    "DELETE FROM topic_reactions WHERE topic_id=$1 AND user_id=$2 AND reaction=$3";
+const INSERT_POST_REACTION: &str =
+    "INSERT INTO post_reactions (post_id, user_id, reaction) VALUES ($1, $2, $3)
+    ON CONFLICT DO NOTHING";
+const DEL_POST_REACTION: &str =
+    "DELETE FROM post_reactions WHERE post_id=$1 AND user_id=$2 AND reaction=$3";
+
+// reactions of deleted topics and posts are kept so they are restored along with them.
+const TOPIC_REACTION_COUNTS: &str =
+    "SELECT r.topic_id, t.category_id, r.reaction, COUNT(*) FROM topic_reactions r
+    JOIN topics t ON t.id = r.topic_id
+    WHERE t.deleted_at IS NULL";
+const POST_REACTION_COUNTS: &str = "SELECT r.post_id, r.reaction, COUNT(*) FROM post_reactions r
+    JOIN posts p ON p.id = r.post_id
+    JOIN topics t ON t.id = p.topic_id
+    WHERE p.deleted_at IS NULL AND t.deleted_at IS NULL";
+
+impl MyPostgresPool {
+    pub(crate) async fn get_reaction_target(
+        &self,
+        req: &ReactionRequest,
+    ) -> Result<ReactionTarget, ResError> {
+        let pool = self.get().await?;
+        let (cli, _) = &*pool;
+
+        let (query, id) = match (req.topic_id, req.post_id) {
+            (Some(tid), None) => (SELECT_TOPIC_TARGET, tid),
+            (None, Some(pid)) => (SELECT_POST_TARGET, pid),
+            _ => return Err(ResError::BadRequest),
+        };
+
+        let st = cli.prepare_typed(query, &[Type::OID]).await?;
+        let row = cli
+            .query(&st, &[&id])
+            .await?
+            .into_iter()
+            .next()
+            .ok_or(ResError::NotFound)?;
+
+        Ok(ReactionTarget {
+            topic_id: row.try_get(0)?,
+            post_id: req.post_id,
+            category_id: row.try_get(1)?,
+        })
+    }
+
+    pub(crate) async fn set_reaction(
+        &self,
+        target: ReactionTarget,
+        uid: u32,
+        req: ReactionRequest,
+    ) -> Result<ReactionReport, ResError> {
+        let pool = self.get().await?;
+        let (cli, _) = &*pool;
+
+        let (query, id) = match (target.post_id, req.is_reacted) {
+            (None, true) => (INSERT_TOPIC_REACTION, target.topic_id),
+            (None, false) => (DEL_TOPIC_REACTION, target.topic_id),
+            (Some(pid), true) => (INSERT_POST_REACTION, pid),
+            (Some(pid), false) => (DEL_POST_REACTION, pid),
+        };
+
+        let st = cli
+            .prepare_typed(query, &[Type::OID, Type::OID, Type::VARCHAR])
+            .await?;
+        let changed = cli.execute(&st, &[&id, &uid, &req.reaction]).await? as i64;
+
+        Ok(ReactionReport {
+            topic_id: target.topic_id,
+            post_id: target.post_id,
+            category_id: target.category_id,
+            reaction: req.reaction,
+            incr: if req.is_reacted { changed } else { -changed },
+        })
+    }
+
+    // reaction counts of topic and its posts.
+    pub(crate) async fn get_reaction_counts(
+        &self,
+        tid: Option<u32>,
+    ) -> Result<ReactionCounts, ResError> {
+        let pool = self.get().await?;
+        let (cli, _) = &*pool;
+        reaction_counts(cli, tid).await
+    }
+
+    pub(crate) async fn get_post_reaction_counts(
+        &self,
+        pid: u32,
+    ) -> Result<ReactionCounts, ResError> {
+        let pool = self.get().await?;
+        let (cli, _) = &*pool;
+
+        let query = format!(
+            "{} AND p.id=$1 GROUP BY r.post_id, r.reaction",
+            POST_REACTION_COUNTS
+        );
+        let st = cli.prepare_typed(query.as_str(), &[Type::OID]).await?;
+        let posts = post_counts(cli.query(&st, &[&pid]).await?)?;
+
+        Ok(ReactionCounts {
+            topics: Vec::new(),
+            posts,
+        })
+    }
+}
+
+// all reaction counts when tid is None. used by startup too.
+pub(crate) async fn reaction_counts(
+    cli: &Client,
+    tid: Option<u32>,
+) -> Result<ReactionCounts, ResError> {
+    let (filter_topic, filter_post) = match tid {
+        Some(_) => (" AND t.id=$1", " AND p.topic_id=$1"),
+        None => ("", ""),
+    };
+    let params: Vec<&(dyn ToSql + Sync)> = match tid.as_ref() {
+        Some(tid) => vec![tid],
+        None => vec![],
+    };
+
+    let query = format!(
+        "{}{} GROUP BY r.topic_id, t.category_id, r.reaction",
+        TOPIC_REACTION_COUNTS, filter_topic
+    );
+    let st = cli.prepare(query.as_str()).await?;
+    let topics = cli
+        .query(&st, &params)
+        .await?
+        .iter()
+        .map(|r| {
+            let count: i64 = r.try_get(3)?;
+            Ok::<_, ResError>((r.try_get(0)?, r.try_get(1)?, r.try_get(2)?, count as u32))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let query = format!(
+        "{}{} GROUP BY r.post_id, r.reaction",
+        POST_REACTION_COUNTS, filter_post
+    );
+    let st = cli.prepare(query.as_str()).await?;
+    let posts = post_counts(cli.query(&st, &params).await?)?;
+
+    Ok(ReactionCounts { topics, posts })
+}
+
+fn post_counts(rows: Vec<tokio_postgres::Row>) -> Result<Vec<(u32, String, u32)>, ResError> {
+    rows.iter()
+        .map(|r| {
+            let count: i64 = r.try_get(2)?;
+            Ok((r.try_get(0)?, r.try_get(1)?, count as u32))
+        })
+        .collect()
+}
+
+impl MyRedisPool {
+    pub(crate) async fn update_reaction(&self, r: &ReactionReport) -> Result<(), ResError> {
+        if r.incr == 0 {
+            return Ok(());
+        }
+
+        let mut conn = self.get().await?.get_conn().clone();
+
+        let mut pip = pipe();
+        pip.atomic();
+
+        let field = format!("reaction:{}", r.reaction);
+        match r.post_id {
+            Some(pid) => {
+                pip.cmd("HINCRBY")
+                    .arg(&format!("post:{}:set_perm", pid))
+                    .arg(field.as_str())
+                    .arg(r.incr)
+                    .ignore();
+            }
+            None => {
+                pip.cmd("HINCRBY")
+                    .arg(&format!("topic:{}:set_perm", r.topic_id))
+                    .arg(field.as_str())
+                    .arg(r.incr)
+                    .ignore();
+                for c in [r.category_id.to_string().as_str(), "all"].iter() {
+                    pip.cmd("ZINCRBY")
+                        .arg(&format!("category:{}:topics_reaction", c))
+                        .arg(r.incr)
+                        .arg(r.topic_id)
+                        .ignore();
+                }
+            }
+        }
+
+        pip.query_async(&mut conn).await.map_err(Into::into)
+    }
+
+    pub(crate) async fn set_reaction_counts(&self, c: &ReactionCounts) -> Result<(), ResError> {
+        if c.topics.is_empty() && c.posts.is_empty() {
+            return Ok(());
+        }
+        let mut conn = self.get().await?.get_conn().clone();
+        build_reactions_cache(&mut conn, c).await
+    }
+
+    pub(crate) async fn update_reaction_send_fail(
+        &self,
+        r: ReactionReport,
+        addr: CacheServiceAddr,
+    ) {
+        if self.update_reaction(&r).await.is_err() {
+            let _ = addr.send(CacheFailedMessage::FailedReaction(r)).await;
+        }
+    }
+}
+
+// counts are set instead of increased so the function could be called again when rebuilding.
+fn set_counts(pip: &mut Pipeline, c: &ReactionCounts) {
+    let mut totals: Vec<(u32, u32, u32)> = Vec::new();
+
+    for (tid, cid, name, count) in c.topics.iter() {
+        pip.cmd("HSET")
+            .arg(&format!("topic:{}:set_perm", tid))
+            .arg(&format!("reaction:{}", name))
+            .arg(*count)
+            .ignore();
+        match totals.iter_mut().find(|(id, _, _)| id == tid) {
+            Some(t) => t.2 += *count,
+            None => totals.push((*tid, *cid, *count)),
+        }
+    }
+
+    for (tid, cid, total) in totals.into_iter() {
+        for c in [cid.to_string().as_str(), "all"].iter() {
+            pip.cmd("ZADD")
+                .arg(&format!("category:{}:topics_reaction", c))
+                .arg(total)
+                .arg(tid)
+                .ignore();
+        }
+    }
+
+    for (pid, name, count) in c.posts.iter() {
+        pip.cmd("HSET")
+            .arg(&format!("post:{}:set_perm", pid))
+            .arg(&format!("reaction:{}", name))
+            .arg(*count)
+            .ignore();
+    }
+}
+
+// helper function for startup.
+pub(crate) async fn build_reactions_cache(
+    conn: &mut MultiplexedConnection,
+    c: &ReactionCounts,
+) -> Result<(), ResError> {
+    let mut pip = pipe();
+    pip.atomic();
+    set_counts(&mut pip, c);
+    pip.query_async(conn).await.map_err(Into::into)
+}
@@ -0,0 +1,379 @@
+use chrono::{NaiveDateTime, Utc};
+use redis::{aio::MultiplexedConnection, pipe, Pipeline};
+use tokio_postgres::types::Type;
+
+use crate::handler::{cache::MyRedisPool, db::MyPostgresPool};
+use crate::model::{
+    errors::ResError,
+    read::{CategoryRead, TopicRead, TopicUnread},
+    topic::Topic,
+};
+
+// read states changed since last persist. members are topic:{uid}:{tid} and category:{uid}:{cid}.
+const DIRTY_READS: &str = "reads:dirty";
+// max read states persisted in one interval.
+const PERSIST_LIMIT: usize = 1000;
+
+const UPSERT_TOPIC_READ: &str =
+    "INSERT INTO topic_reads (user_id, topic_id, last_read_post_id, read_count, read_at)
+    VALUES ($1, $2, $3, $4, $5)
+    ON CONFLICT (user_id, topic_id) DO UPDATE SET
+    last_read_post_id=EXCLUDED.last_read_post_id, read_count=EXCLUDED.read_count, read_at=EXCLUDED.read_at";
+const UPSERT_CATEGORY_READ: &str = "INSERT INTO category_reads (user_id, category_id, read_at)
+    VALUES ($1, $2, $3)
+    ON CONFLICT (user_id, category_id) DO UPDATE SET read_at=EXCLUDED.read_at";
+pub(crate) const SELECT_TOPIC_READS: &str =
+    "SELECT user_id, topic_id, last_read_post_id, read_count, read_at FROM topic_reads";
+pub(crate) const SELECT_CATEGORY_READS: &str =
+    "SELECT user_id, category_id, read_at FROM category_reads";
+
+impl MyPostgresPool {
+    pub(crate) async fn save_reads(
+        &self,
+        t: &[TopicRead],
+        c: &[CategoryRead],
+    ) -> Result<(), ResError> {
+        let mut pool = self.get().await?;
+        let (cli, _) = &mut *pool;
+        let tx = cli.transaction().await?;
+
+        if !t.is_empty() {
+            let st = tx
+                .prepare_typed(
+                    UPSERT_TOPIC_READ,
+                    &[Type::OID, Type::OID, Type::OID, Type::OID, Type::TIMESTAMP],
+                )
+                .await?;
+            for r in t.iter() {
+                tx.execute(
+                    &st,
+                    &[
+                        &r.user_id,
+                        &r.topic_id,
+                        &r.last_read_post_id,
+                        &r.read_count,
+                        &r.read_at,
+                    ],
+                )
+                .await?;
+            }
+        }
+
+        if !c.is_empty() {
+            let st = tx
+                .prepare_typed(
+                    UPSERT_CATEGORY_READ,
+                    &[Type::OID, Type::OID, Type::TIMESTAMP],
+                )
+                .await?;
+            for r in c.iter() {
+                tx.execute(&st, &[&r.user_id, &r.category_id, &r.read_at])
+                    .await?;
+            }
+        }
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+}
+
+impl MyRedisPool {
+    // mark topic read up to the latest post user has seen. read_at is the created time of the post
+    // (or the topic when no post is seen) and read state never goes back.
+    pub(crate) async fn mark_topic_read(
+        &self,
+        uid: u32,
+        tid: u32,
+        last_read_post_id: Option<u32>,
+        read_at: NaiveDateTime,
+    ) -> Result<(), ResError> {
+        let mut conn = self.get().await?.get_conn().clone();
+
+        let (old, read_count) = pipe()
+            .cmd("HGET")
+            .arg(&format!("user:{}:reads", uid))
+            .arg(TopicRead::field(tid))
+            .cmd("ZCOUNT")
+            .arg(&format!("topic:{}:posts_time_created", tid))
+            .arg("-inf")
+            .arg(read_at.timestamp_millis())
+            .query_async::<_, (Option<String>, u32)>(&mut conn)
+            .await?;
+
+        if let Some(old) = old.and_then(|v| TopicRead::from_value(uid, tid, v.as_str())) {
+            if old.read_at >= read_at {
+                return Ok(());
+            }
+        }
+
+        let r = TopicRead {
+            user_id: uid,
+            topic_id: tid,
+            last_read_post_id,
+            read_count,
+            read_at,
+        };
+
+        let mut pip = pipe();
+        pip.atomic();
+        add_topic_reads(&mut pip, std::slice::from_ref(&r));
+        pip.cmd("SADD")
+            .arg(DIRTY_READS)
+            .arg(&format!("topic:{}:{}", uid, tid))
+            .ignore();
+
+        pip.query_async(&mut conn).await.map_err(Into::into)
+    }
+
+    // topics of categories with no reply from now on are read.
+    pub(crate) async fn mark_categories_read(
+        &self,
+        uid: u32,
+        cids: &[u32],
+    ) -> Result<(), ResError> {
+        let now = Utc::now().naive_utc();
+        let r = cids
+            .iter()
+            .map(|cid| CategoryRead {
+                user_id: uid,
+                category_id: *cid,
+                read_at: now,
+            })
+            .collect::<Vec<_>>();
+
+        let mut conn = self.get().await?.get_conn().clone();
+
+        let mut pip = pipe();
+        pip.atomic();
+        add_category_reads(&mut pip, &r);
+        for cid in cids.iter() {
+            pip.cmd("SADD")
+                .arg(DIRTY_READS)
+                .arg(&format!("category:{}:{}", uid, cid))
+                .ignore();
+        }
+
+        pip.query_async(&mut conn).await.map_err(Into::into)
+    }
+
+    // unread_count and first_unread_post_id of topics in the same order.
+    // a topic is read when its last_reply_time is not after the read time of the topic or its category.
+    // the unread count is the reply_count diff for topic read. it's counted from topic's posts for category read.
+    pub(crate) async fn get_unread(
+        &self,
+        uid: u32,
+        t: &[Topic],
+    ) -> Result<Vec<TopicUnread>, ResError> {
+        let mut res = vec![TopicUnread::default(); t.len()];
+
+        if t.is_empty() {
+            return Ok(res);
+        }
+
+        let mut conn = self.get().await?.get_conn().clone();
+
+        let key = format!("user:{}:reads", uid);
+        let mut pip = pipe();
+        pip.cmd("HMGET").arg(key.as_str());
+        for t in t.iter() {
+            pip.arg(TopicRead::field(t.id));
+        }
+        pip.cmd("HMGET").arg(key.as_str());
+        for t in t.iter() {
+            pip.arg(CategoryRead::field(t.category_id));
+        }
+
+        let (tr, cr): (Vec<Option<String>>, Vec<Option<String>>) =
+            pip.query_async(&mut conn).await?;
+
+        // (index of topic, read time in milliseconds, unread count when it's known)
+        let mut unread = Vec::new();
+
+        for (i, (t, (tr, cr))) in t.iter().zip(tr.iter().zip(cr.iter())).enumerate() {
+            let count = match t.reply_count {
+                Some(c) => c,
+                None => continue,
+            };
+            let last = t.last_reply_time.unwrap_or(t.created_at).timestamp_millis();
+
+            let tr = tr
+                .as_ref()
+                .and_then(|v| TopicRead::from_value(uid, t.id, v));
+            let cr = cr
+                .as_ref()
+                .and_then(|v| CategoryRead::from_value(uid, t.category_id, v));
+
+            let (read_at, diff) = match (tr, cr) {
+                (Some(tr), Some(cr)) if cr.read_at > tr.read_at => {
+                    (Some(cr.read_at.timestamp_millis()), None)
+                }
+                (Some(tr), _) => (
+                    Some(tr.read_at.timestamp_millis()),
+                    Some(count.saturating_sub(tr.read_count)),
+                ),
+                (None, Some(cr)) => (Some(cr.read_at.timestamp_millis()), None),
+                (None, None) => (None, Some(count)),
+            };
+
+            match (read_at, diff) {
+                (Some(read_at), _) if last <= read_at => res[i].unread_count = Some(0),
+                (None, Some(0)) => res[i].unread_count = Some(0),
+                // there is at least one reply after read time even if some posts are deleted in between.
+                (Some(read_at), Some(diff)) => unread.push((i, Some(read_at), Some(diff.max(1)))),
+                (read_at, diff) => unread.push((i, read_at, diff)),
+            }
+        }
+
+        if unread.is_empty() {
+            return Ok(res);
+        }
+
+        let mut pip = pipe();
+        for (i, read_at, _) in unread.iter() {
+            pip.cmd("ZRANGEBYSCORE")
+                .arg(&format!("topic:{}:posts_time_created", t[*i].id))
+                .arg(min_score(*read_at))
+                .arg("+inf")
+                .arg("LIMIT")
+                .arg(0)
+                .arg(1);
+        }
+        let first: Vec<Vec<u32>> = pip.query_async(&mut conn).await?;
+
+        let mut pip = pipe();
+        for (i, read_at, _) in unread.iter().filter(|(_, _, diff)| diff.is_none()) {
+            pip.cmd("ZCOUNT")
+                .arg(&format!("topic:{}:posts_time_created", t[*i].id))
+                .arg(min_score(*read_at))
+                .arg("+inf");
+        }
+        let mut counts = if unread.iter().any(|(_, _, diff)| diff.is_none()) {
+            pip.query_async::<_, Vec<u32>>(&mut conn).await?
+        } else {
+            Vec::new()
+        }
+        .into_iter();
+
+        for ((i, _, diff), first) in unread.into_iter().zip(first.into_iter()) {
+            let r = &mut res[i];
+            r.unread_count = diff.or_else(|| counts.next());
+            r.first_unread_post_id = first.first().copied();
+        }
+
+        Ok(res)
+    }
+
+    // take the dirty read states out of cache. the keys are returned so they could be added back when persisting failed.
+    pub(crate) async fn take_dirty_reads(
+        &self,
+    ) -> Result<(Vec<String>, Vec<TopicRead>, Vec<CategoryRead>), ResError> {
+        let mut conn = self.get().await?.get_conn().clone();
+
+        let keys = redis::cmd("SPOP")
+            .arg(DIRTY_READS)
+            .arg(PERSIST_LIMIT)
+            .query_async::<_, Vec<String>>(&mut conn)
+            .await?;
+
+        if keys.is_empty() {
+            return Ok((keys, Vec::new(), Vec::new()));
+        }
+
+        let parsed = keys.iter().map(|k| parse_dirty_key(k)).collect::<Vec<_>>();
+
+        let mut pip = pipe();
+        for (is_topic, uid, id) in parsed.iter().flatten() {
+            let field = if *is_topic {
+                TopicRead::field(*id)
+            } else {
+                CategoryRead::field(*id)
+            };
+            pip.cmd("HGET")
+                .arg(&format!("user:{}:reads", uid))
+                .arg(field);
+        }
+        let values: Vec<Option<String>> = pip.query_async(&mut conn).await?;
+
+        let mut t = Vec::new();
+        let mut c = Vec::new();
+        for ((is_topic, uid, id), v) in parsed.into_iter().flatten().zip(values.into_iter()) {
+            if let Some(v) = v {
+                if is_topic {
+                    t.extend(TopicRead::from_value(uid, id, v.as_str()));
+                } else {
+                    c.extend(CategoryRead::from_value(uid, id, v.as_str()));
+                }
+            }
+        }
+
+        Ok((keys, t, c))
+    }
+
+    pub(crate) async fn add_dirty_reads(&self, keys: &[String]) -> Result<(), ResError> {
+        if keys.is_empty() {
+            return Ok(());
+        }
+        let mut conn = self.get().await?.get_conn().clone();
+        redis::cmd("SADD")
+            .arg(DIRTY_READS)
+            .arg(keys)
+            .query_async(&mut conn)
+            .await
+            .map_err(Into::into)
+    }
+}
+
+// dirty key is parsed to (is_topic, user_id, topic_id or category_id).
+fn parse_dirty_key(k: &str) -> Option<(bool, u32, u32)> {
+    let mut k = k.split(':');
+    let is_topic = match k.next()? {
+        "topic" => true,
+        "category" => false,
+        _ => return None,
+    };
+    let uid = k.next()?.parse::<u32>().ok()?;
+    let id = k.next()?.parse::<u32>().ok()?;
+    Some((is_topic, uid, id))
+}
+
+// posts created after read time are unread. all posts are unread when topic is never read.
+fn min_score(read_at: Option<i64>) -> String {
+    match read_at {
+        Some(t) => format!("({}", t),
+        None => "-inf".to_owned(),
+    }
+}
+
+fn add_topic_reads(pip: &mut Pipeline, r: &[TopicRead]) {
+    for r in r.iter() {
+        pip.cmd("HSET")
+            .arg(&format!("user:{}:reads", r.user_id))
+            .arg(TopicRead::field(r.topic_id))
+            .arg(r.value())
+            .ignore();
+    }
+}
+
+fn add_category_reads(pip: &mut Pipeline, r: &[CategoryRead]) {
+    for r in r.iter() {
+        pip.cmd("HSET")
+            .arg(&format!("user:{}:reads", r.user_id))
+            .arg(CategoryRead::field(r.category_id))
+            .arg(r.value())
+            .ignore();
+    }
+}
+
+// helper function for startup.
+pub(crate) async fn build_reads_cache(
+    conn: &mut MultiplexedConnection,
+    t: &[TopicRead],
+    c: &[CategoryRead],
+) -> Result<(), ResError> {
+    let mut pip = pipe();
+    pip.atomic();
+    add_topic_reads(&mut pip, t);
+    add_category_reads(&mut pip, c);
+    pip.query_async(conn).await.map_err(Into::into)
+}
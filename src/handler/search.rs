@@ -0,0 +1,92 @@
+use tokio_postgres::types::ToSql;
+
+use crate::handler::db::{MyPostgresPool, ParseRowStream};
+use crate::model::{
+    errors::ResError,
+    search::{SearchHit, SearchOrder, SearchQuery},
+};
+
+const SEARCH_LIMIT: usize = 20;
+const SEARCH_PAGE_MAX: usize = 500;
+
+// $1 is the search text. topics are matched on title and body and posts on post_content.
+// the union is wrapped so filters and order can be applied to both kinds of hits.
+// user text is html escaped before highlighting so the only tags in title and snippet are <b></b>.
+const SEARCH_HITS: &str = "SELECT * FROM (
+SELECT t.id AS topic_id, NULL::OID AS post_id, t.category_id, t.user_id,
+ts_headline('simple', replace(replace(replace(t.title, '&', '&amp;'), '<', '&lt;'), '>', '&gt;'), q, 'HighlightAll=TRUE') AS title,
+ts_headline('simple', replace(replace(replace(t.body, '&', '&amp;'), '<', '&lt;'), '>', '&gt;'), q, 'MaxFragments=2') AS snippet,
+ts_rank(t.search_vector, q) AS rank, t.created_at
+FROM topics t CROSS JOIN plainto_tsquery('simple', $1) q
+WHERE t.search_vector @@ q AND t.is_visible AND t.deleted_at IS NULL
+UNION ALL
+SELECT p.topic_id, p.id, p.category_id, p.user_id, replace(replace(replace(t.title, '&', '&amp;'), '<', '&lt;'), '>', '&gt;'),
+ts_headline('simple', replace(replace(replace(p.post_content, '&', '&amp;'), '<', '&lt;'), '>', '&gt;'), q, 'MaxFragments=2'),
+ts_rank(p.search_vector, q), p.created_at
+FROM posts p JOIN topics t ON t.id = p.topic_id CROSS JOIN plainto_tsquery('simple', $1) q
+WHERE p.search_vector @@ q AND t.is_visible AND p.deleted_at IS NULL AND t.deleted_at IS NULL
+) hits";
+
+impl MyPostgresPool {
+    // hidden is the categories caller can't see.
+    pub(crate) async fn search(
+        &self,
+        req: &SearchQuery,
+        hidden: &[u32],
+    ) -> Result<Vec<SearchHit>, ResError> {
+        let mut query = String::from(SEARCH_HITS);
+        let mut params = vec![&req.q as &(dyn ToSql + Sync)];
+        let mut index = 2u8;
+
+        if !hidden.is_empty() {
+            query.push_str(" WHERE category_id <> ALL($2)");
+            params.push(&hidden as &(dyn ToSql + Sync));
+            index += 1;
+        }
+
+        if let Some(s) = req.category_id.as_ref() {
+            query.push_str(if index == 2 { " WHERE" } else { " AND" });
+            query.push_str(" category_id=$");
+            query.push_str(index.to_string().as_str());
+            params.push(s as &(dyn ToSql + Sync));
+            index += 1;
+        }
+        if let Some(s) = req.user_id.as_ref() {
+            query.push_str(if index == 2 { " WHERE" } else { " AND" });
+            query.push_str(" user_id=$");
+            query.push_str(index.to_string().as_str());
+            params.push(s as &(dyn ToSql + Sync));
+            index += 1;
+        }
+        if let Some(s) = req.from.as_ref() {
+            query.push_str(if index == 2 { " WHERE" } else { " AND" });
+            query.push_str(" created_at>=$");
+            query.push_str(index.to_string().as_str());
+            params.push(s as &(dyn ToSql + Sync));
+            index += 1;
+        }
+        if let Some(s) = req.to.as_ref() {
+            query.push_str(if index == 2 { " WHERE" } else { " AND" });
+            query.push_str(" created_at<=$");
+            query.push_str(index.to_string().as_str());
+            params.push(s as &(dyn ToSql + Sync));
+        }
+
+        match req.order {
+            Some(SearchOrder::Recency) => query.push_str(" ORDER BY created_at DESC"),
+            _ => query.push_str(" ORDER BY rank DESC, created_at DESC"),
+        }
+
+        let offset = (req.page.unwrap_or(1).max(1).min(SEARCH_PAGE_MAX) - 1) * SEARCH_LIMIT;
+        query.push_str(format!(" LIMIT {} OFFSET {}", SEARCH_LIMIT, offset).as_str());
+
+        let pool = self.get().await?;
+        let (cli, _) = &*pool;
+
+        let st = cli.prepare_typed(query.as_str(), &[]).await?;
+        cli.query_raw(&st, params.iter().map(|s| *s as _))
+            .await?
+            .parse_row()
+            .await
+    }
+}
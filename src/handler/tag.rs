@@ -0,0 +1,293 @@
+use redis::{aio::MultiplexedConnection, cmd, pipe, Pipeline};
+use tokio_postgres::types::Type;
+
+use crate::handler::{
+    cache::{MyRedisPool, TOPIC_U8},
+    cache_update::{CacheFailedMessage, CacheServiceAddr},
+    db::MyPostgresPool,
+};
+use crate::model::{
+    errors::ResError,
+    tag::{TopicTagRequest, TopicTags},
+    topic::Topic,
+};
+
+const SELECT_TOPIC_TAGS: &str = "SELECT tag FROM topic_tags WHERE topic_id=$1 ORDER BY tag";
+const DEL_TOPIC_TAGS: &str = "DELETE FROM topic_tags WHERE topic_id=$1";
+const INSERT_TOPIC_TAGS: &str = "INSERT INTO topic_tags (topic_id, tag)
+    SELECT $1, tag FROM UNNEST($2::VARCHAR[]) AS tag
+    ON CONFLICT DO NOTHING";
+// tags of soft deleted topics are kept so they are restored along with the topic.
+const SELECT_TOPIC_EXIST: &str = "SELECT id FROM topics WHERE id=$1";
+
+impl MyPostgresPool {
+    pub(crate) async fn get_topic_tags(&self, tid: u32) -> Result<TopicTags, ResError> {
+        let pool = self.get().await?;
+        let (cli, _) = &*pool;
+
+        let st = cli.prepare_typed(SELECT_TOPIC_TAGS, &[Type::OID]).await?;
+        let tags = cli
+            .query(&st, &[&tid])
+            .await?
+            .iter()
+            .map(|r| r.try_get(0))
+            .collect::<Result<Vec<String>, _>>()?;
+
+        Ok(TopicTags {
+            topic_id: tid,
+            tags,
+        })
+    }
+
+    // replace all tags of topic. return the old tags so the cache could be updated.
+    pub(crate) async fn set_topic_tags(
+        &self,
+        req: &TopicTagRequest,
+    ) -> Result<(Vec<String>, TopicTags), ResError> {
+        let old = self.get_topic_tags(req.topic_id).await?.tags;
+
+        let mut pool = self.get().await?;
+        let (cli, _) = &mut *pool;
+
+        let tx = cli.transaction().await?;
+
+        let st = tx.prepare_typed(SELECT_TOPIC_EXIST, &[Type::OID]).await?;
+        if tx.query(&st, &[&req.topic_id]).await?.is_empty() {
+            return Err(ResError::NotFound);
+        }
+
+        let st = tx.prepare_typed(DEL_TOPIC_TAGS, &[Type::OID]).await?;
+        tx.execute(&st, &[&req.topic_id]).await?;
+
+        if !req.tags.is_empty() {
+            let st = tx
+                .prepare_typed(INSERT_TOPIC_TAGS, &[Type::OID, Type::VARCHAR_ARRAY])
+                .await?;
+            tx.execute(&st, &[&req.topic_id, &req.tags]).await?;
+        }
+
+        tx.commit().await?;
+
+        let mut tags = req.tags.clone();
+        tags.sort();
+
+        Ok((
+            old,
+            TopicTags {
+                topic_id: req.topic_id,
+                tags,
+            },
+        ))
+    }
+}
+
+impl MyRedisPool {
+    // topics of hidden categories are left out before paging.
+    async fn get_topics_by_tag(
+        &self,
+        key: &str,
+        page: usize,
+        hidden: &[u32],
+    ) -> Result<(Vec<Topic>, Vec<u32>), ResError> {
+        if hidden.is_empty() {
+            return self
+                .get_cache_with_uids_from_zrevrange(key, page, TOPIC_U8)
+                .await;
+        }
+
+        let mut conn = self.get().await?.get_conn().clone();
+        let ids = Self::visible_topic_ids(&mut conn, key, false, hidden, page).await?;
+        Self::from_redis_with_perm_uids(&mut conn, ids, TOPIC_U8).await
+    }
+
+    pub(crate) async fn get_topics_by_tag_late(
+        &self,
+        tag: &str,
+        page: usize,
+        hidden: &[u32],
+    ) -> Result<(Vec<Topic>, Vec<u32>), ResError> {
+        let key = format!("tag:{}:topics_time", tag);
+        self.get_topics_by_tag(key.as_str(), page, hidden).await
+    }
+
+    pub(crate) async fn get_topics_by_tag_pop(
+        &self,
+        tag: &str,
+        page: usize,
+        hidden: &[u32],
+    ) -> Result<(Vec<Topic>, Vec<u32>), ResError> {
+        let key = format!("tag:{}:topics_reply", tag);
+        self.get_topics_by_tag(key.as_str(), page, hidden).await
+    }
+
+    // move topic between tag sorted sets. the scores are copied from category:all lists
+    // and a topic not in the lists(soft deleted) is only recorded in topic:{tid}:tags.
+    pub(crate) async fn update_topic_tags(
+        &self,
+        old: &[String],
+        t: &TopicTags,
+    ) -> Result<(), ResError> {
+        let tid = t.topic_id;
+        let mut conn = self.get().await?.get_conn().clone();
+
+        let (time, reply) = pipe()
+            .cmd("ZSCORE")
+            .arg("category:all:topics_time")
+            .arg(tid)
+            .cmd("ZSCORE")
+            .arg("category:all:topics_reply")
+            .arg(tid)
+            .query_async::<_, (Option<f64>, Option<f64>)>(&mut conn)
+            .await?;
+
+        let mut pip = pipe();
+        pip.atomic();
+
+        let key = format!("topic:{}:tags", tid);
+        pip.cmd("DEL").arg(key.as_str()).ignore();
+        if !t.tags.is_empty() {
+            pip.cmd("SADD").arg(key.as_str()).arg(&t.tags).ignore();
+        }
+
+        let removed = old
+            .iter()
+            .filter(|tag| !t.tags.contains(tag))
+            .cloned()
+            .collect::<Vec<String>>();
+        remove_tags(&mut pip, tid, &removed);
+
+        if let (Some(time), Some(reply)) = (time, reply) {
+            add_tags(&mut pip, tid, &t.tags, time, reply);
+        }
+
+        pip.query_async(&mut conn).await.map_err(Into::into)
+    }
+
+    pub(crate) async fn get_topic_tags(&self, tid: u32) -> Result<Vec<String>, ResError> {
+        let mut conn = self.get().await?.get_conn().clone();
+        topic_tags(&mut conn, tid).await
+    }
+
+    pub(crate) async fn update_topic_tags_send_fail(
+        &self,
+        old: Vec<String>,
+        t: TopicTags,
+        addr: CacheServiceAddr,
+    ) {
+        if self.update_topic_tags(&old, &t).await.is_err() {
+            let _ = addr
+                .send(CacheFailedMessage::FailedTopicTags(t.topic_id))
+                .await;
+        }
+    }
+}
+
+pub(crate) async fn topic_tags(
+    conn: &mut MultiplexedConnection,
+    tid: u32,
+) -> Result<Vec<String>, ResError> {
+    cmd("SMEMBERS")
+        .arg(&format!("topic:{}:tags", tid))
+        .query_async(conn)
+        .await
+        .map_err(Into::into)
+}
+
+pub(crate) fn add_tags(pip: &mut Pipeline, tid: u32, tags: &[String], time: f64, reply: f64) {
+    for tag in tags.iter() {
+        pip.cmd("ZADD")
+            .arg(&format!("tag:{}:topics_time", tag))
+            .arg(time)
+            .arg(tid)
+            .ignore()
+            .cmd("ZADD")
+            .arg(&format!("tag:{}:topics_reply", tag))
+            .arg(reply)
+            .arg(tid)
+            .ignore();
+    }
+}
+
+pub(crate) fn remove_tags(pip: &mut Pipeline, tid: u32, tags: &[String]) {
+    for tag in tags.iter() {
+        pip.cmd("ZREM")
+            .arg(&format!("tag:{}:topics_time", tag))
+            .arg(tid)
+            .ignore()
+            .cmd("ZREM")
+            .arg(&format!("tag:{}:topics_reply", tag))
+            .arg(tid)
+            .ignore();
+    }
+}
+
+// update scores of topic when its posts are added or removed.
+// use XX so a topic already removed from the lists is not added back.
+pub(crate) fn reply_tags(
+    pip: &mut Pipeline,
+    tid: u32,
+    tags: &[String],
+    time: Option<i64>,
+    incr: i64,
+) {
+    for tag in tags.iter() {
+        if let Some(time) = time {
+            pip.cmd("ZADD")
+                .arg(&format!("tag:{}:topics_time", tag))
+                .arg("XX")
+                .arg(time)
+                .arg(tid)
+                .ignore();
+        }
+        pip.cmd("ZADD")
+            .arg(&format!("tag:{}:topics_reply", tag))
+            .arg("XX")
+            .arg("INCR")
+            .arg(incr)
+            .arg(tid)
+            .ignore();
+    }
+}
+
+// helper function for startup. tags are (topic_id, tag) of all alive topics.
+// tag sorted sets are rebuilt by intersecting the tag's topics with category:all lists to get the scores.
+pub(crate) async fn build_tags_cache(
+    conn: &mut MultiplexedConnection,
+    tags: Vec<(u32, String)>,
+) -> Result<(), ResError> {
+    let mut pip = pipe();
+    pip.atomic();
+
+    let mut names: Vec<&str> = Vec::new();
+    for (tid, tag) in tags.iter() {
+        pip.cmd("SADD")
+            .arg(&format!("topic:{}:tags", tid))
+            .arg(tag.as_str())
+            .ignore()
+            .cmd("SADD")
+            .arg(&format!("tag:{}:topics", tag))
+            .arg(*tid)
+            .ignore();
+        if !names.contains(&tag.as_str()) {
+            names.push(tag.as_str());
+        }
+    }
+
+    for tag in names.into_iter() {
+        let set_key = format!("tag:{}:topics", tag);
+        for key in ["topics_time", "topics_reply"].iter() {
+            pip.cmd("ZINTERSTORE")
+                .arg(&format!("tag:{}:{}", tag, key))
+                .arg(2)
+                .arg(set_key.as_str())
+                .arg(&format!("category:all:{}", key))
+                .arg("WEIGHTS")
+                .arg(0)
+                .arg(1)
+                .ignore();
+        }
+        pip.cmd("DEL").arg(set_key.as_str()).ignore();
+    }
+
+    pip.query_async(conn).await.map_err(Into::into)
+}
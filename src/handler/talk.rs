@@ -1,20 +1,28 @@
+use std::collections::VecDeque;
+use std::time::Duration;
+
 use actix::Addr;
 use actix_send::prelude::*;
-use chrono::{NaiveDateTime, Utc};
+use chrono::Utc;
 use hashbrown::HashMap;
 use parking_lot::{RwLockReadGuard, RwLockWriteGuard};
-use redis::cmd;
-use tokio_postgres::types::ToSql;
+use redis::{cmd, Script};
+use tokio_postgres::types::{ToSql, Type};
 
 use crate::handler::{
     cache::MyRedisPool,
     db::{GetStatement, MyPostgresPool, ParseRowStream},
+    messenger::{ErrReportMsg, ErrReportServiceAddr},
+    talk_fanout::{FanoutMessage, NODE_ID},
 };
 use crate::model::{
-    actors::WsChatSession,
-    common::{GlobalSessions, GlobalTalks},
+    actors::{SessionAddr, WsChatSession},
+    common::{dur, GlobalSessions, GlobalTalks},
     errors::ResError,
-    talk::{PrivateMessage, PublicMessage, Relation, SendMessage, SessionMessage, Talk},
+    talk::{
+        MessageDeleted, MessageEdited, PrivateMessage, PublicMessage, Relation, SendMessage,
+        SessionMessage, Talk,
+    },
 };
 
 // statements that are not constructed on pool start.
@@ -26,12 +34,58 @@ const INSERT_ADMIN: &str =
 const REMOVE_ADMIN: &str =
     "UPDATE talks SET admin=array_remove(admin, $1) WHERE id=$2 AND owner=$3";
 const REMOVE_USER: &str = "UPDATE talks SET users=array_remove(users, $1) WHERE id=$2";
-const GET_PUB_MSG: &str =
-    "SELECT * FROM public_messages1 WHERE talk_id = $1 AND time <= $2 ORDER BY time DESC LIMIT 999";
-const GET_PRV_MSG: &str =
-    "SELECT * FROM private_messages1 WHERE to_id = $1 AND time <= $2 ORDER BY time DESC LIMIT 999";
+// deleted messages are never returned. cursor and limit conditions are added by history_query.
+const SELECT_PUB_MSG: &str =
+    "SELECT id, talk_id, user_id, time, text, edited_at FROM public_messages
+    WHERE talk_id = $1 AND deleted_at IS NULL";
+// messages of both directions between the two users.
+const SELECT_PRV_MSG: &str =
+    "SELECT id, from_id, to_id, time, text, edited_at FROM private_messages
+    WHERE LEAST(from_id, to_id) = LEAST($1, $2) AND GREATEST(from_id, to_id) = GREATEST($1, $2)
+    AND deleted_at IS NULL";
+const HISTORY_LIMIT: i64 = 50;
+const HISTORY_LIMIT_MAX: i64 = 200;
+// only sender could edit message.
+const UPDATE_PUB_MSG: &str = "UPDATE public_messages SET text=$1, edited_at=$2
+    WHERE id=$3 AND talk_id=$4 AND user_id=$5 AND deleted_at IS NULL RETURNING id";
+const UPDATE_PRV_MSG: &str = "UPDATE private_messages SET text=$1, edited_at=$2
+    WHERE id=$3 AND from_id=$4 AND deleted_at IS NULL RETURNING to_id";
+// public message could be deleted by sender or talk's owner and admins. $5 is true when the user is talk's owner or admin.
+const DEL_PUB_MSG: &str = "UPDATE public_messages SET deleted_at=$1
+    WHERE id=$2 AND talk_id=$3 AND (user_id=$4 OR $5) AND deleted_at IS NULL RETURNING id";
+const DEL_PRV_MSG: &str = "UPDATE private_messages SET deleted_at=$1
+    WHERE id=$2 AND from_id=$3 AND deleted_at IS NULL RETURNING to_id";
+// message ids are reserved in blocks so messages could be broadcast before they are inserted.
+const RESERVE_PUB_MSG_ID: &str =
+    "SELECT nextval('public_messages_id_seq') FROM generate_series(1, $1)";
+const RESERVE_PRV_MSG_ID: &str =
+    "SELECT nextval('private_messages_id_seq') FROM generate_series(1, $1)";
+const MSG_ID_BLOCK: i64 = 100;
+// buffered messages are flushed when they reach the size or on interval.
+const FLUSH_SIZE: usize = 100;
+const FLUSH_INTERVAL: Duration = dur(1000);
+// the first failed flush is reported and so is every FLUSH_REPORT_EVERY retries after it.
+const FLUSH_REPORT_EVERY: usize = 5;
+// buffer is dropped after failing to flush for the times. new messages are refused when buffer is full.
+const FLUSH_RETRY_MAX: usize = 30;
+const BUFFER_MAX: usize = 1000;
 const GET_FRIENDS: &str = "SELECT friends FROM relations WHERE id = $1";
 const INSERT_USER: &str = "UPDATE talks SET users=array_append(users, $1) WHERE id= $2";
+// change the connection count of a node in user:{uid}:conns and return the total count of all live nodes.
+// counts of nodes without talk:node:{node_id} key are removed as the nodes are gone.
+const INCR_CONNS: &str = "
+    local n = redis.call('HINCRBY', KEYS[1], ARGV[1], ARGV[2])
+    if n <= 0 then redis.call('HDEL', KEYS[1], ARGV[1]) end
+    local total = 0
+    local conns = redis.call('HGETALL', KEYS[1])
+    for i = 1, #conns, 2 do
+        if conns[i] == ARGV[1] or redis.call('EXISTS', 'talk:node:' .. conns[i]) == 1 then
+            total = total + tonumber(conns[i + 1])
+        else
+            redis.call('HDEL', KEYS[1], conns[i])
+        end
+    end
+    return total";
 
 // talk service actor handle communication to web socket sessions actors
 #[actor]
@@ -40,6 +94,13 @@ pub struct TalkService {
     sessions: GlobalSessions,
     db_pool: MyPostgresPool,
     cache_pool: MyRedisPool,
+    rep_addr: Option<ErrReportServiceAddr>,
+    // messages are broadcast on arrival and buffered here with sender's conn_id for batch insert.
+    pub_msgs: Vec<(PublicMessage, u32)>,
+    prv_msgs: Vec<(PrivateMessage, u32)>,
+    pub_ids: VecDeque<i64>,
+    prv_ids: VecDeque<i64>,
+    flush_fails: usize,
 }
 
 pub type TalkServiceAddr = Address<TalkService>;
@@ -49,12 +110,14 @@ pub(crate) async fn init_talk_service(
     cache_pool: MyRedisPool,
     talks: GlobalTalks,
     sessions: GlobalSessions,
+    rep_addr: Option<ErrReportServiceAddr>,
 ) -> Result<TalkServiceAddr, ()> {
     let builder = TalkService::builder(move || {
         let db_pool = db_pool.clone();
         let cache_pool = cache_pool.clone();
         let talks = talks.clone();
         let sessions = sessions.clone();
+        let rep_addr = rep_addr.clone();
 
         async {
             TalkService {
@@ -62,11 +125,23 @@ pub(crate) async fn init_talk_service(
                 sessions,
                 db_pool,
                 cache_pool,
+                rep_addr,
+                pub_msgs: Vec::new(),
+                prv_msgs: Vec::new(),
+                pub_ids: VecDeque::new(),
+                prv_ids: VecDeque::new(),
+                flush_fails: 0,
             }
         }
     });
 
-    let addr = builder.start().await;
+    let addr: TalkServiceAddr = builder.start().await;
+
+    addr.run_interval(FLUSH_INTERVAL, |service| {
+        Box::pin(async move { service.flush_messages_rep().await })
+    })
+    .await
+    .map_err(|_| ())?;
 
     Ok(addr)
 }
@@ -79,6 +154,7 @@ pub struct AuthRequest {
 
 pub struct DisconnectRequest {
     pub session_id: u32,
+    pub conn_id: u32,
 }
 
 // pass Some(talk_id) in json for public message, pass None for private message
@@ -88,10 +164,12 @@ pub struct TextMessageRequest {
     pub talk_id: Option<u32>,
     pub user_id: Option<u32>,
     pub session_id: Option<u32>,
+    pub conn_id: Option<u32>,
 }
 
 pub struct ConnectRequest {
     pub session_id: u32,
+    pub conn_id: u32,
     pub online_status: u32,
     pub addr: Addr<WsChatSession>,
 }
@@ -99,6 +177,7 @@ pub struct ConnectRequest {
 #[derive(Deserialize, Clone)]
 pub struct CreateTalkRequest {
     pub session_id: Option<u32>,
+    pub conn_id: Option<u32>,
     pub name: String,
     pub description: String,
     pub owner: u32,
@@ -107,37 +186,63 @@ pub struct CreateTalkRequest {
 #[derive(Deserialize)]
 pub struct JoinTalkRequest {
     pub session_id: Option<u32>,
+    pub conn_id: Option<u32>,
     pub talk_id: u32,
 }
 
 #[derive(Deserialize)]
 pub struct TalkByIdRequest {
     pub session_id: Option<u32>,
+    pub conn_id: Option<u32>,
     pub talk_id: u32,
 }
 
 #[derive(Deserialize)]
 pub struct UsersByIdRequest {
     pub session_id: Option<u32>,
+    pub conn_id: Option<u32>,
     user_id: Vec<u32>,
 }
 
 #[derive(Deserialize)]
 pub struct UserRelationRequest {
     pub session_id: Option<u32>,
+    pub conn_id: Option<u32>,
 }
 
-// pass talk id for talk public messages. pass none for private history message.
+// pass talk id for talk public messages. pass user id of the peer for private conversation.
+// cursor is a message id and the latest messages are returned when there is no cursor.
 #[derive(Deserialize)]
 pub struct GetHistory {
-    pub time: String,
     pub talk_id: Option<u32>,
+    pub user_id: Option<u32>,
+    pub cursor: Option<i64>,
+    #[serde(default)]
+    pub mode: HistoryMode,
+    pub limit: Option<i64>,
     pub session_id: Option<u32>,
+    pub conn_id: Option<u32>,
+}
+
+// messages before or after the cursor. around returns both sides and the cursor message itself.
+#[derive(Deserialize, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum HistoryMode {
+    Before,
+    After,
+    Around,
+}
+
+impl Default for HistoryMode {
+    fn default() -> Self {
+        HistoryMode::Before
+    }
 }
 
 #[derive(Deserialize)]
 pub struct RemoveUserRequest {
     pub session_id: Option<u32>,
+    pub conn_id: Option<u32>,
     user_id: u32,
     talk_id: u32,
 }
@@ -148,14 +253,34 @@ pub struct Admin {
     pub remove: Option<u32>,
     pub talk_id: u32,
     pub session_id: Option<u32>,
+    pub conn_id: Option<u32>,
 }
 
 #[derive(Deserialize)]
 pub struct DeleteTalkRequest {
     pub session_id: Option<u32>,
+    pub conn_id: Option<u32>,
     pub talk_id: u32,
 }
 
+// pass Some(talk_id) for public message and None for private message.
+#[derive(Deserialize)]
+pub struct EditMessageRequest {
+    pub session_id: Option<u32>,
+    pub conn_id: Option<u32>,
+    pub id: i64,
+    pub talk_id: Option<u32>,
+    pub text: String,
+}
+
+#[derive(Deserialize)]
+pub struct DeleteMessageRequest {
+    pub session_id: Option<u32>,
+    pub conn_id: Option<u32>,
+    pub id: i64,
+    pub talk_id: Option<u32>,
+}
+
 #[handler_v2]
 impl TalkService {
     #[on_start]
@@ -163,61 +288,88 @@ impl TalkService {
         println!("talk service actor have started");
     }
 
+    // buffered messages are not kept when actor is stopping.
+    #[on_stop]
+    async fn on_stop(&mut self) {
+        if let Err(e) = self.flush_messages().await {
+            self.send_err_rep(e);
+        }
+    }
+
     async fn handle_disconnect(&mut self, msg: DisconnectRequest) {
         let sid = msg.session_id;
 
-        if let Err(e) = self.sessions.remove_session_hm(sid) {
-            self.sessions.send_error(sid, &e);
+        // session could be removed already as heartbeat timeout and stopping both send disconnect request.
+        let status = match self.sessions.remove_session_hm(sid, msg.conn_id) {
+            Ok(status) => status,
+            Err(_) => return,
         };
 
-        // we set user's online status in redis to 0 when user's websocket session disconnecting
-        if let Err(e) = self.cache_pool.set_online_status(sid, 0, true).await {
-            self.sessions.send_error(sid, &e);
+        // we set user's online status in redis to 0 when user's last websocket session of all nodes disconnecting.
+        // otherwise the status is aggregated from the remaining local sessions or left to the other nodes.
+        let r = match self.cache_pool.incr_online_conns(sid, -1).await {
+            Ok(0) => self.cache_pool.set_online_status(sid, 0, true).await,
+            Ok(_) => match status {
+                Some(status) => self.cache_pool.set_online_status(sid, status, false).await,
+                None => Ok(()),
+            },
+            Err(e) => Err(e),
+        };
+        // the connection is gone so the error is reported instead.
+        if let Err(e) = r {
+            self.send_err_rep(e);
         };
     }
 
     async fn handle_txt(&mut self, msg: TextMessageRequest) {
-        // ToDo: batch insert messages to database.
         let sid = msg.session_id.unwrap();
+        let cid = msg.conn_id.unwrap();
 
         // the double layer async/await is to handle ResError more easily. We stringify the error and send them to websocket session actor.
         let r = async {
-            let now = Utc::now().naive_utc();
+            check_message_text(&msg.text)?;
 
-            let pool = self.db_pool.get().await?;
-            let (cli, sts) = &*pool;
+            if self.pub_msgs.len() + self.prv_msgs.len() >= BUFFER_MAX {
+                return Err(ResError::TooManyRequests);
+            }
 
-            if let Some(tid) = msg.talk_id {
-                let st = sts.get_statement("insert_pub_msg")?;
-                cli.execute(st, &[&tid, &msg.text, &now]).await?;
+            let time = Utc::now().naive_utc();
 
-                drop(pool);
+            if let Some(talk_id) = msg.talk_id {
+                if !self.talks.get_talk_hm(talk_id)?.users.contains(&sid) {
+                    return Err(ResError::Unauthorized);
+                }
 
-                let s = SendMessage::PublicMessage(&[PublicMessage {
+                let m = PublicMessage {
+                    id: self.next_msg_id(true).await?,
+                    talk_id,
+                    user_id: sid,
+                    time,
                     text: msg.text,
-                    time: now,
-                    talk_id: msg.talk_id.unwrap(),
-                }])
-                .stringify();
+                    edited_at: None,
+                };
+
+                let s = SendMessage::PublicMessage(std::slice::from_ref(&m)).stringify();
+                self.pub_msgs.push((m, cid));
 
-                self.send_message_many(tid, s.as_str())
+                self.send_message_many(talk_id, s.as_str()).await
             } else {
                 let uid = msg.user_id.ok_or(ResError::BadRequest)?;
 
-                let st = sts.get_statement("insert_prv_msg")?;
-                cli.execute(st, &[&msg.session_id.unwrap(), &uid, &msg.text, &now])
-                    .await?;
-
-                drop(pool);
-
-                let s = SendMessage::PrivateMessage(&[PrivateMessage {
-                    user_id: msg.user_id.unwrap(),
+                let m = PrivateMessage {
+                    id: self.next_msg_id(false).await?,
+                    user_id: sid,
+                    to_id: uid,
+                    time,
                     text: msg.text,
-                    time: now,
-                }])
-                .stringify();
+                    edited_at: None,
+                };
+
+                let s = SendMessage::PrivateMessage(std::slice::from_ref(&m)).stringify();
+                self.prv_msgs.push((m, cid));
 
-                self.sessions.send_message(sid, s.as_str());
+                // private message goes to both sender and receiver.
+                self.publish_message(vec![sid, uid], s.as_str()).await;
 
                 Ok(())
             }
@@ -225,22 +377,34 @@ impl TalkService {
         .await;
 
         if let Err(e) = r {
-            self.sessions.send_error(sid, &e);
+            self.sessions.send_error(sid, cid, &e);
         };
+
+        if self.pub_msgs.len() + self.prv_msgs.len() >= FLUSH_SIZE {
+            self.flush_messages_rep().await;
+        }
     }
 
     async fn handle_connect(&mut self, msg: ConnectRequest) {
         let sid = msg.session_id;
 
-        let status = msg.online_status;
         let addr = msg.addr;
 
-        if let Err(e) = self.cache_pool.set_online_status(sid, status, true).await {
-            self.sessions.send_error(sid, &e);
+        let status = self.sessions.insert_session_hm(
+            sid,
+            SessionAddr {
+                conn_id: msg.conn_id,
+                online_status: msg.online_status,
+                addr: addr.clone(),
+            },
+        );
+
+        let r = match self.cache_pool.incr_online_conns(sid, 1).await {
+            Ok(_) => self.cache_pool.set_online_status(sid, status, true).await,
+            Err(e) => Err(e),
         };
-
-        if let Err(e) = self.sessions.insert_session_hm(sid, addr.clone()) {
-            self.sessions.send_error(sid, &e);
+        if let Err(e) = r {
+            self.sessions.send_error(sid, msg.conn_id, &e);
         };
 
         addr.do_send(SessionMessage(
@@ -250,6 +414,7 @@ impl TalkService {
 
     async fn handle_create(&mut self, msg: CreateTalkRequest) {
         let sid = msg.session_id.unwrap();
+        let cid = msg.conn_id.unwrap();
 
         let r = async {
             let admins = vec![msg.owner];
@@ -287,19 +452,20 @@ impl TalkService {
             drop(pool);
 
             let s = SendMessage::Talks(&t).stringify();
-            self.talks.insert_talk_hm(t)?;
-            self.sessions.send_message(msg.owner, s.as_str());
+            self.update_talks(t).await;
+            self.sessions.send_reply(sid, cid, s.as_str());
             Ok(())
         }
         .await;
 
         if let Err(e) = r {
-            self.sessions.send_error(sid, &e);
+            self.sessions.send_error(sid, cid, &e);
         };
     }
 
     async fn handle_join(&mut self, msg: JoinTalkRequest) {
         let sid = msg.session_id.unwrap();
+        let cid = msg.conn_id.unwrap();
         let r = async {
             let tid = msg.talk_id;
 
@@ -322,20 +488,21 @@ impl TalkService {
             drop(pool);
 
             let s = SendMessage::Talks(&t).stringify();
-            self.talks.insert_talk_hm(t)?;
-            self.sessions.send_message(sid, s.as_str());
+            self.update_talks(t).await;
+            self.sessions.send_reply(sid, cid, s.as_str());
 
             Ok(())
         }
         .await;
 
         if let Err(e) = r {
-            self.sessions.send_error(sid, &e);
+            self.sessions.send_error(sid, cid, &e);
         };
     }
 
     async fn handle_talk_by_id(&mut self, msg: TalkByIdRequest) {
         let sid = msg.session_id.unwrap();
+        let cid = msg.conn_id.unwrap();
 
         let r = async {
             let talks = self.talks.get_talks_hm()?;
@@ -350,33 +517,35 @@ impl TalkService {
             };
 
             let s = SendMessage::Talks(&t).stringify();
-            self.sessions.send_message(sid, s.as_str());
+            self.sessions.send_reply(sid, cid, s.as_str());
 
             Ok(())
         }
         .await;
 
         if let Err(e) = r {
-            self.sessions.send_error(sid, &e);
+            self.sessions.send_error(sid, cid, &e);
         }
     }
 
     async fn handle_user_by_id(&mut self, msg: UsersByIdRequest) {
         let sid = msg.session_id.unwrap();
+        let cid = msg.conn_id.unwrap();
 
         match self.cache_pool.get_users(msg.user_id).await {
             Ok(u) => {
                 let s = SendMessage::Users(&u).stringify();
-                self.sessions.send_message(sid, s.as_str());
+                self.sessions.send_reply(sid, cid, s.as_str());
             }
             Err(e) => {
-                self.sessions.send_error(sid, &e);
+                self.sessions.send_error(sid, cid, &e);
             }
         }
     }
 
     async fn handle_relation(&mut self, msg: UserRelationRequest) {
         let sid = msg.session_id.unwrap();
+        let cid = msg.conn_id.unwrap();
 
         let r = async {
             let pool = self.db_pool.get().await?;
@@ -395,29 +564,51 @@ impl TalkService {
             drop(pool);
 
             let s = SendMessage::Friends(&r.friends).stringify();
-            self.sessions.send_message(sid, s.as_str());
+            self.sessions.send_reply(sid, cid, s.as_str());
 
             Ok(())
         };
 
         if let Err(e) = r.await {
-            self.sessions.send_error(sid, &e);
+            self.sessions.send_error(sid, cid, &e);
         }
     }
 
     async fn handle_history(&mut self, msg: GetHistory) {
         let sid = msg.session_id.unwrap();
+        let cid = msg.conn_id.unwrap();
 
         let f = async {
-            let time = NaiveDateTime::parse_from_str(&msg.time, "%Y-%m-%d %H:%M:%S%.f")?;
+            let limit = msg
+                .limit
+                .unwrap_or(HISTORY_LIMIT)
+                .max(1)
+                .min(HISTORY_LIMIT_MAX);
+
+            // buffered messages are inserted first so they are included in history.
+            self.flush_messages().await?;
+
+            // no cursor means paging from the latest message or from the first message for after mode.
+            let (cursor, mode) = match (msg.cursor, msg.mode) {
+                (Some(c), mode) => (c, mode),
+                (None, HistoryMode::After) => (0, HistoryMode::After),
+                (None, _) => (std::i64::MAX, HistoryMode::Before),
+            };
 
             let pool = self.db_pool.get().await?;
             let (cli, _) = &*pool;
 
             let s = match msg.talk_id {
                 Some(tid) => {
-                    let st = cli.prepare(GET_PUB_MSG).await?;
-                    let params: [&(dyn ToSql + Sync); 2] = [&tid, &time];
+                    if !self.talks.get_talk_hm(tid)?.users.contains(&sid) {
+                        return Err(ResError::Unauthorized);
+                    }
+
+                    let query = history_query(SELECT_PUB_MSG, 1, mode);
+                    let st = cli
+                        .prepare_typed(query.as_str(), &[Type::OID, Type::INT8, Type::INT8])
+                        .await?;
+                    let params: [&(dyn ToSql + Sync); 3] = [&tid, &cursor, &limit];
                     let msg = cli
                         .query_raw(&st, params.iter().map(|s| *s as _))
                         .await?
@@ -429,8 +620,16 @@ impl TalkService {
                     SendMessage::PublicMessage(&msg).stringify()
                 }
                 None => {
-                    let st = cli.prepare(GET_PRV_MSG).await?;
-                    let params: [&(dyn ToSql + Sync); 2] = [&sid, &time];
+                    let uid = msg.user_id.ok_or(ResError::BadRequest)?;
+
+                    let query = history_query(SELECT_PRV_MSG, 2, mode);
+                    let st = cli
+                        .prepare_typed(
+                            query.as_str(),
+                            &[Type::OID, Type::OID, Type::INT8, Type::INT8],
+                        )
+                        .await?;
+                    let params: [&(dyn ToSql + Sync); 4] = [&sid, &uid, &cursor, &limit];
                     let msg = cli
                         .query_raw(&st, params.iter().map(|s| *s as _))
                         .await?
@@ -443,17 +642,18 @@ impl TalkService {
                 }
             };
 
-            self.sessions.send_message(sid, s.as_str());
+            self.sessions.send_reply(sid, cid, s.as_str());
             Ok(())
         };
 
         if let Err(e) = f.await {
-            self.sessions.send_error(sid, &e);
+            self.sessions.send_error(sid, cid, &e);
         }
     }
 
     async fn handle_remove(&mut self, msg: RemoveUserRequest) {
         let sid = msg.session_id.unwrap();
+        let cid = msg.conn_id.unwrap();
 
         let r = async {
             let tid = msg.talk_id;
@@ -488,20 +688,21 @@ impl TalkService {
             drop(pool);
 
             let s = SendMessage::Talks(&t).stringify();
-            self.talks.insert_talk_hm(t)?;
-            self.sessions.send_message(sid, s.as_str());
+            self.update_talks(t).await;
+            self.sessions.send_reply(sid, cid, s.as_str());
 
             Ok(())
         }
         .await;
 
         if let Err(e) = r {
-            self.sessions.send_error(sid, &e);
+            self.sessions.send_error(sid, cid, &e);
         };
     }
 
     async fn handle_admin(&mut self, msg: Admin) {
         let sid = msg.session_id.unwrap();
+        let cid = msg.conn_id.unwrap();
 
         let r = async {
             let tid = msg.talk_id;
@@ -528,31 +729,50 @@ impl TalkService {
             drop(pool);
 
             let s = SendMessage::Talks(&t).stringify();
-            self.talks.insert_talk_hm(t)?;
-            self.sessions.send_message(sid, s.as_str());
+            self.update_talks(t).await;
+            self.sessions.send_reply(sid, cid, s.as_str());
 
             Ok(())
         }
         .await;
 
         if let Err(e) = r {
-            self.sessions.send_error(sid, &e);
+            self.sessions.send_error(sid, cid, &e);
         };
     }
 
     async fn handle_delete(&mut self, msg: DeleteTalkRequest) {
         let sid = msg.session_id.unwrap();
+        let cid = msg.conn_id.unwrap();
 
         let tid = msg.talk_id;
 
-        if let Err(e) = self._handle_delete(sid, tid).await {
-            self.sessions.send_error(sid, &e);
+        if let Err(e) = self._handle_delete(sid, cid, tid).await {
+            self.sessions.send_error(sid, cid, &e);
+        };
+    }
+
+    async fn handle_edit_msg(&mut self, msg: EditMessageRequest) {
+        let sid = msg.session_id.unwrap();
+        let cid = msg.conn_id.unwrap();
+
+        if let Err(e) = self._handle_edit_msg(sid, msg).await {
+            self.sessions.send_error(sid, cid, &e);
+        };
+    }
+
+    async fn handle_delete_msg(&mut self, msg: DeleteMessageRequest) {
+        let sid = msg.session_id.unwrap();
+        let cid = msg.conn_id.unwrap();
+
+        if let Err(e) = self._handle_delete_msg(sid, msg).await {
+            self.sessions.send_error(sid, cid, &e);
         };
     }
 }
 
 impl TalkService {
-    async fn _handle_delete(&mut self, sid: u32, tid: u32) -> Result<(), ResError> {
+    async fn _handle_delete(&mut self, sid: u32, cid: u32, tid: u32) -> Result<(), ResError> {
         let pool = self.db_pool.get().await?;
         let (cli, _) = &*pool;
 
@@ -562,69 +782,397 @@ impl TalkService {
         drop(pool);
 
         self.talks.remove_talk_hm(tid)?;
+        let _ = self
+            .cache_pool
+            .publish_fanout(&FanoutMessage::Talks(vec![tid]))
+            .await;
         let s = SendMessage::Success("Delete Talk Success").stringify();
-        self.sessions.send_message(sid, s.as_str());
+        self.sessions.send_reply(sid, cid, s.as_str());
 
         Ok(())
     }
 
-    // helper function to send message to multiple sessions.
-    fn send_message_many(&self, tid: u32, msg: &str) -> Result<(), ResError> {
-        let t = self.talks.get_talk_hm(tid)?;
+    // edit event goes to all users of talk for public message and both sender and receiver for private message.
+    async fn _handle_edit_msg(
+        &mut self,
+        sid: u32,
+        msg: EditMessageRequest,
+    ) -> Result<(), ResError> {
+        check_message_text(&msg.text)?;
+
+        // the message could still be in buffer.
+        self.flush_messages().await?;
+
+        let m = MessageEdited {
+            id: msg.id,
+            talk_id: msg.talk_id,
+            text: msg.text,
+            edited_at: Utc::now().naive_utc(),
+        };
+
+        let pool = self.db_pool.get().await?;
+        let (cli, _) = &*pool;
+
+        let row = match m.talk_id {
+            Some(tid) => {
+                let st = cli.prepare(UPDATE_PUB_MSG).await?;
+                cli.query(&st, &[&m.text, &m.edited_at, &m.id, &tid, &sid])
+                    .await?
+            }
+            None => {
+                let st = cli.prepare(UPDATE_PRV_MSG).await?;
+                cli.query(&st, &[&m.text, &m.edited_at, &m.id, &sid])
+                    .await?
+            }
+        }
+        .into_iter()
+        .next()
+        .ok_or(ResError::NotFound)?;
+
+        drop(pool);
+
+        let s = SendMessage::MessageEdited(&m).stringify();
+
+        match m.talk_id {
+            Some(tid) => self.send_message_many(tid, s.as_str()).await,
+            None => {
+                let to_id: u32 = row.try_get(0)?;
+                self.publish_message(vec![sid, to_id], s.as_str()).await;
+                Ok(())
+            }
+        }
+    }
+
+    async fn _handle_delete_msg(
+        &mut self,
+        sid: u32,
+        msg: DeleteMessageRequest,
+    ) -> Result<(), ResError> {
+        self.flush_messages().await?;
+
+        let m = MessageDeleted {
+            id: msg.id,
+            talk_id: msg.talk_id,
+            deleted_at: Utc::now().naive_utc(),
+        };
+
+        let pool = self.db_pool.get().await?;
+        let (cli, _) = &*pool;
+
+        let row = match m.talk_id {
+            Some(tid) => {
+                let t = self.talks.get_talk_hm(tid)?;
+                let is_admin = t.owner == sid || t.admin.contains(&sid);
+
+                let st = cli.prepare(DEL_PUB_MSG).await?;
+                cli.query(&st, &[&m.deleted_at, &m.id, &tid, &sid, &is_admin])
+                    .await?
+            }
+            None => {
+                let st = cli.prepare(DEL_PRV_MSG).await?;
+                cli.query(&st, &[&m.deleted_at, &m.id, &sid]).await?
+            }
+        }
+        .into_iter()
+        .next()
+        .ok_or(ResError::NotFound)?;
+
+        drop(pool);
+
+        let s = SendMessage::MessageDeleted(&m).stringify();
+
+        match m.talk_id {
+            Some(tid) => self.send_message_many(tid, s.as_str()).await,
+            None => {
+                let to_id: u32 = row.try_get(0)?;
+                self.publish_message(vec![sid, to_id], s.as_str()).await;
+                Ok(())
+            }
+        }
+    }
+
+    // take a reserved message id. ids are reserved from sequence in blocks when running out.
+    async fn next_msg_id(&mut self, is_public: bool) -> Result<i64, ResError> {
+        let ids = if is_public {
+            &mut self.pub_ids
+        } else {
+            &mut self.prv_ids
+        };
+
+        if ids.is_empty() {
+            let query = if is_public {
+                RESERVE_PUB_MSG_ID
+            } else {
+                RESERVE_PRV_MSG_ID
+            };
+
+            let pool = self.db_pool.get().await?;
+            let (cli, _) = &*pool;
+
+            let st = cli.prepare_typed(query, &[Type::INT8]).await?;
+            for row in cli.query(&st, &[&MSG_ID_BLOCK]).await?.into_iter() {
+                ids.push_back(row.try_get(0)?);
+            }
+        }
+
+        ids.pop_front().ok_or(ResError::InternalServerError)
+    }
 
-        for u in t.users.iter() {
-            self.sessions.send_message(*u, msg);
+    // insert buffered messages. a buffer is only cleared on success so failed messages are retried on next flush.
+    async fn flush_messages(&mut self) -> Result<(), ResError> {
+        if self.pub_msgs.is_empty() && self.prv_msgs.is_empty() {
+            return Ok(());
         }
+
+        let pool = self.db_pool.get().await?;
+        let (cli, sts) = &*pool;
+
+        if !self.pub_msgs.is_empty() {
+            let len = self.pub_msgs.len();
+            let mut ids = Vec::with_capacity(len);
+            let mut talk_ids = Vec::with_capacity(len);
+            let mut user_ids = Vec::with_capacity(len);
+            let mut texts = Vec::with_capacity(len);
+            let mut times = Vec::with_capacity(len);
+
+            for (m, _) in self.pub_msgs.iter() {
+                ids.push(m.id);
+                talk_ids.push(m.talk_id);
+                user_ids.push(m.user_id);
+                texts.push(m.text.as_str());
+                times.push(m.time);
+            }
+
+            let st = sts.get_statement("insert_pub_msg")?;
+            cli.execute(st, &[&ids, &talk_ids, &user_ids, &texts, &times])
+                .await?;
+
+            self.pub_msgs.clear();
+        }
+
+        if !self.prv_msgs.is_empty() {
+            let len = self.prv_msgs.len();
+            let mut ids = Vec::with_capacity(len);
+            let mut from_ids = Vec::with_capacity(len);
+            let mut to_ids = Vec::with_capacity(len);
+            let mut texts = Vec::with_capacity(len);
+            let mut times = Vec::with_capacity(len);
+
+            for (m, _) in self.prv_msgs.iter() {
+                ids.push(m.id);
+                from_ids.push(m.user_id);
+                to_ids.push(m.to_id);
+                texts.push(m.text.as_str());
+                times.push(m.time);
+            }
+
+            let st = sts.get_statement("insert_prv_msg")?;
+            cli.execute(st, &[&ids, &from_ids, &to_ids, &texts, &times])
+                .await?;
+
+            self.prv_msgs.clear();
+        }
+
         Ok(())
     }
+
+    // failed flush is reported on the first failure and on every FLUSH_REPORT_EVERY retries after it.
+    // buffer still failing after FLUSH_RETRY_MAX retries is dropped and the senders are told.
+    async fn flush_messages_rep(&mut self) {
+        let e = match self.flush_messages().await {
+            Ok(()) => {
+                self.flush_fails = 0;
+                return;
+            }
+            Err(e) => e,
+        };
+
+        self.flush_fails += 1;
+        let fails = self.flush_fails;
+
+        if fails >= FLUSH_RETRY_MAX {
+            self.flush_fails = 0;
+            self.send_err_rep(e);
+            self.drop_messages();
+        } else if fails % FLUSH_REPORT_EVERY == 1 {
+            self.send_err_rep(e);
+        }
+    }
+
+    // dropped messages are already broadcast but they are not stored.
+    fn drop_messages(&mut self) {
+        let e = ResError::InternalServerErrorExplained("Message Not Saved".to_owned());
+        for (m, cid) in self.pub_msgs.drain(..) {
+            self.sessions.send_error(m.user_id, cid, &e);
+        }
+        for (m, cid) in self.prv_msgs.drain(..) {
+            self.sessions.send_error(m.user_id, cid, &e);
+        }
+    }
+
+    fn send_err_rep(&self, e: ResError) {
+        if let Some(addr) = self.rep_addr.as_ref() {
+            let addr = addr.clone();
+            actix_rt::spawn(async move {
+                let _ = addr.send(ErrReportMsg(e)).await;
+            })
+        }
+    }
+
+    // helper function to send message to all users of talk.
+    async fn send_message_many(&self, tid: u32, msg: &str) -> Result<(), ResError> {
+        let t = self.talks.get_talk_hm(tid)?;
+        self.publish_message(t.users, msg).await;
+        Ok(())
+    }
+
+    // events(messages, edits and deletes) are published to all nodes and they deliver it to their local sessions.
+    // it's delivered to the local sessions directly when publishing failed.
+    async fn publish_message(&self, session_ids: Vec<u32>, msg: &str) {
+        let m = FanoutMessage::Sessions {
+            session_ids,
+            msg: msg.to_owned(),
+        };
+        if self.cache_pool.publish_fanout(&m).await.is_err() {
+            if let FanoutMessage::Sessions { session_ids, .. } = m {
+                for sid in session_ids.into_iter() {
+                    self.sessions.send_message(sid, msg);
+                }
+            }
+        }
+    }
+
+    // talks are updated locally and the other nodes reload them from database.
+    // database is already changed so a failed publish is not treated as an error of the request.
+    async fn update_talks(&self, t: Vec<Talk>) {
+        let ids = t.iter().map(|t| t.id).collect();
+        self.talks.insert_talk_hm(t);
+        let _ = self
+            .cache_pool
+            .publish_fanout(&FanoutMessage::Talks(ids))
+            .await;
+    }
 }
 
-// lock global sessions and read write session id and/or associate session addr(WebSocket session actor's address) and send string messages.
+// lock global sessions and read write session id and/or associate session addrs(WebSocket session actors' addresses) and send string messages.
+// messages to a session id are sent to all local connections of the user.
 impl GlobalSessions {
-    fn send_message(&self, sid: u32, msg: &str) {
-        match self.get_session_hm(sid) {
-            Ok(addr) => addr.do_send(SessionMessage(msg.to_owned())),
-            Err(e) => self.send_error(sid, &e),
-        };
+    pub(crate) fn send_message(&self, sid: u32, msg: &str) {
+        if let Ok(addrs) = self.get_sessions_hm(sid) {
+            for addr in addrs.iter() {
+                addr.do_send(SessionMessage(msg.to_owned()));
+            }
+        }
     }
 
-    fn send_error(&self, sid: u32, e: &ResError) {
-        if let Ok(addr) = self.get_session_hm(sid) {
-            addr.do_send(SessionMessage(
-                SendMessage::Error(e.to_string().as_str()).stringify(),
-            ));
+    // replies and errors of a request only go to the connection sending it.
+    fn send_reply(&self, sid: u32, conn_id: u32, msg: &str) {
+        if let Ok(addr) = self.get_session_hm(sid, conn_id) {
+            addr.do_send(SessionMessage(msg.to_owned()));
         }
     }
 
-    fn get_session_hm(&self, sid: u32) -> Result<Addr<WsChatSession>, ResError> {
-        self.read_sessions(move |s| s.get(&sid).cloned().ok_or(ResError::NotFound))
+    fn send_error(&self, sid: u32, conn_id: u32, e: &ResError) {
+        let msg = SendMessage::Error(e.to_string().as_str()).stringify();
+        self.send_reply(sid, conn_id, msg.as_str());
+    }
+
+    fn get_session_hm(&self, sid: u32, conn_id: u32) -> Result<Addr<WsChatSession>, ResError> {
+        self.read_sessions(move |s| {
+            s.get(&sid)
+                .and_then(|s| s.iter().find(|s| s.conn_id == conn_id))
+                .map(|s| s.addr.clone())
+                .ok_or(ResError::NotFound)
+        })
+    }
+
+    fn get_sessions_hm(&self, sid: u32) -> Result<Vec<Addr<WsChatSession>>, ResError> {
+        self.read_sessions(move |s| {
+            s.get(&sid)
+                .map(|s| s.iter().map(|s| s.addr.clone()).collect())
+                .ok_or(ResError::NotFound)
+        })
     }
 
-    fn insert_session_hm(&self, sid: u32, addr: Addr<WsChatSession>) -> Result<(), ResError> {
-        self.write_sessions(move |mut s| s.insert(sid, addr).map(|_| ()).ok_or(ResError::NotFound))
+    // return the aggregated online status of user's sessions after inserting.
+    fn insert_session_hm(&self, sid: u32, session: SessionAddr) -> u32 {
+        self.write_sessions(move |mut s| {
+            let sessions = s.entry(sid).or_insert_with(Vec::new);
+            sessions.retain(|s| s.conn_id != session.conn_id);
+            sessions.push(session);
+            online_status(sessions)
+        })
     }
 
-    fn remove_session_hm(&self, sid: u32) -> Result<(), ResError> {
-        self.write_sessions(move |mut s| s.remove(&sid).map(|_| ()).ok_or(ResError::NotFound))
+    // return the aggregated online status of user's remaining sessions. None if it's the last one.
+    fn remove_session_hm(&self, sid: u32, conn_id: u32) -> Result<Option<u32>, ResError> {
+        self.write_sessions(move |mut s| {
+            let sessions = s.get_mut(&sid).ok_or(ResError::NotFound)?;
+            let len = sessions.len();
+            sessions.retain(|s| s.conn_id != conn_id);
+            if sessions.len() == len {
+                return Err(ResError::NotFound);
+            }
+            if sessions.is_empty() {
+                s.remove(&sid);
+                Ok(None)
+            } else {
+                Ok(Some(online_status(sessions)))
+            }
+        })
     }
 
     fn read_sessions<F, T>(&self, f: F) -> Result<T, ResError>
     where
-        F: FnOnce(RwLockReadGuard<HashMap<u32, Addr<WsChatSession>>>) -> Result<T, ResError>,
+        F: FnOnce(RwLockReadGuard<HashMap<u32, Vec<SessionAddr>>>) -> Result<T, ResError>,
     {
         let r = self.0.read();
         f(r)
     }
 
-    fn write_sessions<F>(&self, f: F) -> Result<(), ResError>
+    fn write_sessions<F, T>(&self, f: F) -> T
     where
-        F: FnOnce(RwLockWriteGuard<HashMap<u32, Addr<WsChatSession>>>) -> Result<(), ResError>,
+        F: FnOnce(RwLockWriteGuard<HashMap<u32, Vec<SessionAddr>>>) -> T,
     {
         let r = self.0.write();
         f(r)
     }
 }
 
+// page of messages in ascending id order. the base query has n params and the cursor and limit params follow.
+fn history_query(base: &str, n: usize, mode: HistoryMode) -> String {
+    let (c, l) = (n + 1, n + 2);
+    match mode {
+        HistoryMode::Before => format!(
+            "SELECT * FROM ({} AND id < ${} ORDER BY id DESC LIMIT ${}) m ORDER BY id",
+            base, c, l
+        ),
+        HistoryMode::After => format!("{} AND id > ${} ORDER BY id LIMIT ${}", base, c, l),
+        HistoryMode::Around => format!(
+            "SELECT * FROM (({} AND id < ${} ORDER BY id DESC LIMIT ${} / 2)
+            UNION ALL ({} AND id >= ${} ORDER BY id LIMIT ${} - ${} / 2)) m ORDER BY id",
+            base, c, l, base, c, l, l
+        ),
+    }
+}
+
+// message text is stored as VARCHAR(1024).
+fn check_message_text(text: &str) -> Result<(), ResError> {
+    let len = text.chars().count();
+    if len == 0 || len > 1024 {
+        Err(ResError::BadRequestExplained(
+            "Message must be 1 to 1024 characters".to_owned(),
+        ))
+    } else {
+        Ok(())
+    }
+}
+
+// user is shown with the most active status of all its sessions.
+fn online_status(sessions: &[SessionAddr]) -> u32 {
+    sessions.iter().map(|s| s.online_status).max().unwrap_or(0)
+}
+
 // lock the global talks and read/write the inner HashMap<talk_id, Talk>;
 impl GlobalTalks {
     fn get_talk_hm(&self, talk_id: u32) -> Result<Talk, ResError> {
@@ -635,18 +1183,15 @@ impl GlobalTalks {
         self.read_talks(move |t| Ok(t.clone()))
     }
 
-    fn insert_talk_hm(&self, talks: Vec<Talk>) -> Result<(), ResError> {
+    pub(crate) fn insert_talk_hm(&self, talks: Vec<Talk>) {
         self.write_talks(move |mut t| {
             for talk in talks.into_iter() {
-                t.insert(talk.id, talk)
-                    .map(|_| ())
-                    .ok_or(ResError::NotFound)?;
+                t.insert(talk.id, talk);
             }
-            Ok(())
         })
     }
 
-    fn remove_talk_hm(&self, tid: u32) -> Result<(), ResError> {
+    pub(crate) fn remove_talk_hm(&self, tid: u32) -> Result<(), ResError> {
         self.write_talks(move |mut t| t.remove(&tid).map(|_| ()).ok_or(ResError::NotFound))
     }
 
@@ -658,9 +1203,9 @@ impl GlobalTalks {
         f(r)
     }
 
-    fn write_talks<F>(&self, f: F) -> Result<(), ResError>
+    fn write_talks<F, T>(&self, f: F) -> T
     where
-        F: FnOnce(RwLockWriteGuard<HashMap<u32, Talk>>) -> Result<(), ResError>,
+        F: FnOnce(RwLockWriteGuard<HashMap<u32, Talk>>) -> T,
     {
         let r = self.0.write();
         f(r)
@@ -668,6 +1213,19 @@ impl GlobalTalks {
 }
 
 impl MyRedisPool {
+    // user's websocket connections are counted per node as a node only knows its own connections.
+    async fn incr_online_conns(&self, uid: u32, n: i64) -> Result<i64, ResError> {
+        let mut conn = self.get().await?.get_conn().clone();
+
+        Script::new(INCR_CONNS)
+            .key(&format!("user:{}:conns", uid))
+            .arg(NODE_ID.as_str())
+            .arg(n)
+            .invoke_async(&mut conn)
+            .await
+            .map_err(Into::into)
+    }
+
     // we set user's online status in redis cache when user connect with websocket.
     async fn set_online_status(
         &self,
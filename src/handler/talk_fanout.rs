@@ -0,0 +1,167 @@
+use std::time::Duration;
+
+use futures::StreamExt;
+use once_cell::sync::Lazy;
+use redis::cmd;
+use tokio_postgres::types::{ToSql, Type};
+
+use crate::handler::{
+    cache::MyRedisPool,
+    db::{MyPostgresPool, ParseRowStream},
+    messenger::{ErrReportMsg, ErrReportServiceAddr},
+};
+use crate::model::{
+    common::{dur, GlobalSessions, GlobalTalks},
+    errors::ResError,
+    talk::Talk,
+};
+
+// every node subscribes to the channel and delivers the messages to its local websocket sessions.
+const FANOUT_CHANNEL: &str = "talk:fanout";
+const RESUBSCRIBE_DELAY: Duration = dur(3000);
+
+// id of current node. it's used to count user's websocket connections per node.
+pub(crate) static NODE_ID: Lazy<String> = Lazy::new(|| uuid::Uuid::new_v4().to_string());
+// live node refreshes its talk:node:{node_id} key. the key expires when the node is gone.
+const NODE_TTL: usize = 30;
+const NODE_HEARTBEAT: Duration = dur(10_000);
+
+const SELECT_TALKS: &str = "SELECT * FROM talks WHERE id = ANY($1)";
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "type", content = "content")]
+pub(crate) enum FanoutMessage {
+    // string message to all sessions of users.
+    Sessions { session_ids: Vec<u32>, msg: String },
+    // talks are changed and they are reloaded from database. talks not found are removed.
+    Talks(Vec<u32>),
+}
+
+impl MyRedisPool {
+    pub(crate) async fn publish_fanout(&self, msg: &FanoutMessage) -> Result<(), ResError> {
+        let msg = serde_json::to_string(msg)?;
+
+        let mut conn = self.get().await?.get_conn().clone();
+
+        cmd("PUBLISH")
+            .arg(FANOUT_CHANNEL)
+            .arg(msg)
+            .query_async::<_, ()>(&mut conn)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn node_heartbeat(&self) -> Result<(), ResError> {
+        let mut conn = self.get().await?.get_conn().clone();
+
+        cmd("SET")
+            .arg(&format!("talk:node:{}", NODE_ID.as_str()))
+            .arg(1)
+            .arg("EX")
+            .arg(NODE_TTL)
+            .query_async::<_, ()>(&mut conn)
+            .await?;
+
+        Ok(())
+    }
+}
+
+impl MyPostgresPool {
+    async fn get_talks(&self, ids: &[u32]) -> Result<Vec<Talk>, ResError> {
+        let pool = self.get().await?;
+        let (cli, _) = &*pool;
+
+        let st = cli.prepare_typed(SELECT_TALKS, &[Type::OID_ARRAY]).await?;
+        let params: [&(dyn ToSql + Sync); 1] = [&ids];
+
+        cli.query_raw(&st, params.iter().map(|s| *s as _))
+            .await?
+            .parse_row()
+            .await
+    }
+}
+
+// start the subscriber of current node. it's started once for all TalkService actors as they share the global talks and sessions.
+pub(crate) fn init_talk_fanout(
+    redis_url: &str,
+    db_pool: MyPostgresPool,
+    cache_pool: MyRedisPool,
+    talks: GlobalTalks,
+    sessions: GlobalSessions,
+    rep_addr: Option<ErrReportServiceAddr>,
+) {
+    let redis_url = redis_url.to_owned();
+    let rep_addr1 = rep_addr.clone();
+
+    actix_rt::spawn(async move {
+        loop {
+            if let Err(e) = cache_pool.node_heartbeat().await {
+                if let Some(addr) = rep_addr1.as_ref() {
+                    let _ = addr.send(ErrReportMsg(e)).await;
+                }
+            }
+            actix_rt::time::delay_for(NODE_HEARTBEAT).await;
+        }
+    });
+
+    actix_rt::spawn(async move {
+        // messages published while resubscribing are lost.
+        loop {
+            if let Err(e) = subscribe(redis_url.as_str(), &db_pool, &talks, &sessions).await {
+                if let Some(addr) = rep_addr.as_ref() {
+                    let _ = addr.send(ErrReportMsg(e)).await;
+                }
+            }
+            actix_rt::time::delay_for(RESUBSCRIBE_DELAY).await;
+        }
+    });
+}
+
+async fn subscribe(
+    redis_url: &str,
+    db_pool: &MyPostgresPool,
+    talks: &GlobalTalks,
+    sessions: &GlobalSessions,
+) -> Result<(), ResError> {
+    let conn = redis::Client::open(redis_url)?
+        .get_async_connection()
+        .await?;
+
+    let mut pubsub = conn.into_pubsub();
+    pubsub.subscribe(FANOUT_CHANNEL).await?;
+
+    let mut stream = pubsub.on_message();
+
+    while let Some(msg) = stream.next().await {
+        let msg = match msg
+            .get_payload::<String>()
+            .map_err(ResError::from)
+            .and_then(|m| serde_json::from_str::<FanoutMessage>(&m).map_err(Into::into))
+        {
+            Ok(msg) => msg,
+            Err(_) => continue,
+        };
+
+        match msg {
+            FanoutMessage::Sessions { session_ids, msg } => {
+                for sid in session_ids.into_iter() {
+                    sessions.send_message(sid, msg.as_str());
+                }
+            }
+            FanoutMessage::Talks(ids) => {
+                // a failed reload leaves the local talks stale until they are changed again.
+                if let Ok(t) = db_pool.get_talks(&ids).await {
+                    for id in ids.iter().filter(|id| !t.iter().any(|t| t.id == **id)) {
+                        let _ = talks.remove_talk_hm(*id);
+                    }
+                    talks.insert_talk_hm(t);
+                }
+            }
+        }
+    }
+
+    Err(ResError::RedisErrorExplained(
+        "talk fanout subscription closed".to_owned(),
+    ))
+}
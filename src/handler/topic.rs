@@ -1,17 +1,25 @@
 use std::future::Future;
 
-use chrono::Utc;
-use tokio_postgres::types::{ToSql, Type};
+use chrono::{NaiveDateTime, Utc};
+use tokio_postgres::{
+    types::{ToSql, Type},
+    Transaction,
+};
 
 use crate::handler::{
     cache::MyRedisPool,
-    cache::TOPIC_U8,
+    cache::{LIMIT, TOPIC_U8},
     cache_update::{CacheFailedMessage, CacheServiceAddr},
     db::{GetStatement, MyPostgresPool, ParseRowStream},
+    pin::with_pinned,
 };
 use crate::model::{
     errors::ResError,
-    topic::{Topic, TopicRequest},
+    revision::TopicRevision,
+    topic::{
+        Topic, TopicMergeRequest, TopicMoveReport, TopicMoveRequest, TopicRequest,
+        TopicSplitRequest,
+    },
 };
 
 const INSERT_TOPIC: &str =
@@ -30,6 +38,54 @@ const INSERT_TOPIC_TYPES: &[Type; 8] = &[
     Type::TIMESTAMP,
 ];
 
+const DELETE_TOPIC: &str =
+    "UPDATE topics SET deleted_at=CURRENT_TIMESTAMP, deleted_by=$1 WHERE id=$2 AND deleted_at IS NULL";
+
+// topic of a removed category can't be restored.
+const RESTORE_TOPIC: &str = "UPDATE topics SET deleted_at=NULL, deleted_by=NULL
+    WHERE id=$1 AND deleted_at IS NOT NULL
+    AND EXISTS (SELECT 1 FROM categories WHERE categories.id = topics.category_id)
+    RETURNING *";
+
+const SELECT_DELETED_TOPIC: &str = "SELECT * FROM topics WHERE id=$1 AND deleted_at IS NOT NULL";
+
+// keep the content before update as a revision. the row is locked until the update is committed.
+const INSERT_TOPIC_REVISION: &str =
+    "INSERT INTO topic_revisions (topic_id, title, body, thumbnail, edited_by)
+    SELECT id, title, body, thumbnail, $1 FROM topics WHERE id=$2 AND deleted_at IS NULL";
+
+const SELECT_TOPIC_REVISIONS: &str =
+    "SELECT * FROM topic_revisions WHERE topic_id=$1 ORDER BY id DESC";
+
+// category of topic and if it's visible and not deleted. used to check access to revisions.
+const SELECT_TOPIC_REVISION_TARGET: &str =
+    "SELECT category_id, is_visible AND deleted_at IS NULL FROM topics WHERE id=$1";
+
+const SELECT_TOPIC_REVISION: &str = "SELECT * FROM topic_revisions WHERE id=ANY($1)";
+
+// reply count and last reply time of all alive posts in a topic.
+const TOPIC_POSTS_STAT: &str =
+    "SELECT p.id, COUNT(r.id), MAX(r.created_at), p.created_at FROM posts p
+    LEFT JOIN posts r ON r.post_id = p.id AND r.deleted_at IS NULL
+    WHERE p.topic_id=$1 AND p.deleted_at IS NULL
+    GROUP BY p.id";
+
+// topics are locked until the move, merge or split is committed.
+const SELECT_TOPIC_LOCK: &str =
+    "SELECT * FROM topics WHERE id=$1 AND deleted_at IS NULL FOR UPDATE";
+const SELECT_TOPIC_POST_IDS: &str = "SELECT id FROM posts WHERE topic_id=$1 AND deleted_at IS NULL";
+const SELECT_CATEGORY_EXIST: &str = "SELECT id FROM categories WHERE id=$1";
+const MOVE_TOPIC: &str = "UPDATE topics SET category_id=$2 WHERE id=$1 RETURNING *";
+// deleted posts are moved too so they are in the right place when restored.
+const MOVE_TOPIC_POSTS: &str = "UPDATE posts SET topic_id=$2, category_id=$3 WHERE topic_id=$1";
+const MOVE_POSTS: &str = "UPDATE posts SET topic_id=$2, category_id=$3 WHERE id=ANY($1)";
+const DEL_TOPIC_PINS: &str = "DELETE FROM topic_pins WHERE topic_id=$1";
+const SELECT_FIRST_POST: &str = "SELECT user_id, created_at FROM posts
+    WHERE id=ANY($1) ORDER BY created_at, id LIMIT 1";
+// replies between split posts and the posts left behind are detached.
+const DETACH_SPLIT_REPLIES: &str = "UPDATE posts SET post_id=NULL
+    WHERE (topic_id=$1 OR topic_id=$2) AND (id=ANY($3)) <> (post_id=ANY($3))";
+
 impl MyPostgresPool {
     pub(crate) async fn add_topic(&self, t: &TopicRequest) -> Result<Vec<Topic>, ResError> {
         let uid = t.user_id.as_ref().ok_or(ResError::BadRequest)?;
@@ -54,7 +110,12 @@ impl MyPostgresPool {
     }
 
     //ToDo: add query for moving topic to other table.
-    pub(crate) async fn update_topic(&self, t: &TopicRequest) -> Result<Vec<Topic>, ResError> {
+    // editor is the user id of who made the update and is stored with the revision.
+    pub(crate) async fn update_topic(
+        &self,
+        t: &TopicRequest,
+        editor: u32,
+    ) -> Result<Vec<Topic>, ResError> {
         let mut query = String::from("UPDATE topics SET");
         let mut params = Vec::new();
         let mut index = 1u8;
@@ -99,7 +160,7 @@ impl MyPostgresPool {
             return Err(ResError::BadRequest);
         }
 
-        query.push_str(" updated_at=DEFAULT WHERE id=$");
+        query.push_str(" updated_at=DEFAULT WHERE deleted_at IS NULL AND id=$");
         query.push_str(index.to_string().as_str());
         params.push(t.id.as_ref().unwrap() as &(dyn ToSql + Sync));
         index += 1;
@@ -111,6 +172,105 @@ impl MyPostgresPool {
         }
         query.push_str(" RETURNING *");
 
+        let mut pool = self.get().await?;
+        let (cli, _) = &mut *pool;
+
+        let tx = cli.transaction().await?;
+
+        // only content change is stored as revision.
+        if t.title.is_some() || t.body.is_some() || t.thumbnail.is_some() {
+            let mut rev = String::from(INSERT_TOPIC_REVISION);
+            let mut rev_params: Vec<&(dyn ToSql + Sync)> = vec![&editor, t.id.as_ref().unwrap()];
+            if let Some(s) = t.user_id.as_ref() {
+                rev.push_str(" AND user_id=$3");
+                rev_params.push(s);
+            }
+            rev.push_str(" FOR UPDATE");
+
+            let st = tx.prepare_typed(rev.as_str(), &[]).await?;
+            tx.execute(&st, &rev_params).await?;
+        }
+
+        let st = tx.prepare_typed(query.as_str(), &[]).await?;
+        let t = tx
+            .query_raw(&st, params.iter().map(|s| *s as _))
+            .await?
+            .parse_row()
+            .await?;
+
+        tx.commit().await?;
+
+        Ok(t)
+    }
+
+    pub(crate) async fn get_topic_revisions(
+        &self,
+        tid: u32,
+    ) -> Result<Vec<TopicRevision>, ResError> {
+        let pool = self.get().await?;
+        let (cli, _) = &*pool;
+
+        let st = cli
+            .prepare_typed(SELECT_TOPIC_REVISIONS, &[Type::OID])
+            .await?;
+        cli.query_raw(&st, [&tid as &(dyn ToSql + Sync)].iter().map(|s| *s as _))
+            .await?
+            .parse_row()
+            .await
+    }
+
+    pub(crate) async fn get_topic_revision_target(
+        &self,
+        tid: u32,
+    ) -> Result<(u32, bool), ResError> {
+        let pool = self.get().await?;
+        let (cli, _) = &*pool;
+
+        let st = cli
+            .prepare_typed(SELECT_TOPIC_REVISION_TARGET, &[Type::OID])
+            .await?;
+        let row = cli
+            .query(&st, &[&tid])
+            .await?
+            .into_iter()
+            .next()
+            .ok_or(ResError::NotFound)?;
+
+        Ok((row.try_get(0)?, row.try_get(1)?))
+    }
+
+    pub(crate) async fn get_topic_revisions_by_id(
+        &self,
+        ids: &[i32],
+    ) -> Result<Vec<TopicRevision>, ResError> {
+        let pool = self.get().await?;
+        let (cli, _) = &*pool;
+
+        let st = cli
+            .prepare_typed(SELECT_TOPIC_REVISION, &[Type::INT4_ARRAY])
+            .await?;
+        cli.query_raw(&st, [&ids as &(dyn ToSql + Sync)].iter().map(|s| *s as _))
+            .await?
+            .parse_row()
+            .await
+    }
+
+    // soft delete a topic. uid is used to make sure only the author can delete the topic.
+    pub(crate) async fn delete_topic(
+        &self,
+        tid: u32,
+        deleted_by: u32,
+        uid: Option<u32>,
+    ) -> Result<Vec<Topic>, ResError> {
+        let mut query = String::from(DELETE_TOPIC);
+        let mut params: Vec<&(dyn ToSql + Sync)> = vec![&deleted_by, &tid];
+
+        if let Some(uid) = uid.as_ref() {
+            query.push_str(" AND user_id=$3");
+            params.push(uid);
+        }
+        query.push_str(" RETURNING *");
+
         let pool = self.get().await?;
         let (cli, _) = &*pool;
 
@@ -121,6 +281,56 @@ impl MyPostgresPool {
             .await
     }
 
+    pub(crate) async fn restore_topic(&self, tid: u32) -> Result<Vec<Topic>, ResError> {
+        let pool = self.get().await?;
+        let (cli, _) = &*pool;
+
+        let st = cli.prepare_typed(RESTORE_TOPIC, &[Type::OID]).await?;
+        cli.query_raw(&st, [&tid as &(dyn ToSql + Sync)].iter().map(|s| *s as _))
+            .await?
+            .parse_row()
+            .await
+    }
+
+    pub(crate) async fn get_deleted_topic(&self, tid: u32) -> Result<Vec<Topic>, ResError> {
+        let pool = self.get().await?;
+        let (cli, _) = &*pool;
+
+        let st = cli
+            .prepare_typed(SELECT_DELETED_TOPIC, &[Type::OID])
+            .await?;
+        cli.query_raw(&st, [&tid as &(dyn ToSql + Sync)].iter().map(|s| *s as _))
+            .await?
+            .parse_row()
+            .await
+    }
+
+    // return (tid, pid, reply_count, last_reply_time, created_at) of alive posts in a topic.
+    pub(crate) async fn get_topic_posts_stat(
+        &self,
+        tid: u32,
+    ) -> Result<Vec<(u32, u32, Option<u32>, Option<NaiveDateTime>, NaiveDateTime)>, ResError> {
+        let pool = self.get().await?;
+        let (cli, _) = &*pool;
+
+        let st = cli.prepare_typed(TOPIC_POSTS_STAT, &[Type::OID]).await?;
+
+        cli.query(&st, &[&tid])
+            .await?
+            .iter()
+            .map(|r| {
+                let count: i64 = r.try_get(1)?;
+                Ok::<_, ResError>((
+                    tid,
+                    r.try_get(0)?,
+                    Some(count as u32),
+                    r.try_get(2)?,
+                    r.try_get(3)?,
+                ))
+            })
+            .collect()
+    }
+
     pub(crate) async fn get_topics(
         &self,
         tids: &[u32],
@@ -141,28 +351,263 @@ impl MyPostgresPool {
 
         Ok((t, uids))
     }
+
+    pub(crate) async fn move_topic(
+        &self,
+        req: &TopicMoveRequest,
+    ) -> Result<TopicMoveReport, ResError> {
+        let mut pool = self.get().await?;
+        let (cli, _) = &mut *pool;
+
+        let tx = cli.transaction().await?;
+
+        let (t, pids) = lock_topic(&tx, req.topic_id).await?;
+        if t.category_id == req.category_id {
+            return Err(ResError::BadRequest);
+        }
+        check_category(&tx, req.category_id).await?;
+
+        let st = tx
+            .prepare_typed(MOVE_TOPIC, &[Type::OID, Type::OID])
+            .await?;
+        let params: [&(dyn ToSql + Sync); 2] = [&t.id, &req.category_id];
+        let topics = tx
+            .query_raw(&st, params.iter().map(|s| *s as _))
+            .await?
+            .parse_row()
+            .await?;
+
+        let st = tx
+            .prepare_typed(MOVE_TOPIC_POSTS, &[Type::OID, Type::OID, Type::OID])
+            .await?;
+        tx.execute(&st, &[&t.id, &t.id, &req.category_id]).await?;
+
+        tx.commit().await?;
+
+        Ok(TopicMoveReport {
+            removed: vec![(t, pids)],
+            topics,
+        })
+    }
+
+    pub(crate) async fn merge_topic(
+        &self,
+        req: &TopicMergeRequest,
+        deleted_by: u32,
+    ) -> Result<TopicMoveReport, ResError> {
+        let mut pool = self.get().await?;
+        let (cli, _) = &mut *pool;
+
+        let tx = cli.transaction().await?;
+
+        // lock in the order of id to avoid dead lock with a merge in the opposite direction.
+        let (t, target) = if req.topic_id < req.target_id {
+            let t = lock_topic(&tx, req.topic_id).await?;
+            (t, lock_topic(&tx, req.target_id).await?)
+        } else {
+            let target = lock_topic(&tx, req.target_id).await?;
+            (lock_topic(&tx, req.topic_id).await?, target)
+        };
+
+        let st = tx
+            .prepare_typed(MOVE_TOPIC_POSTS, &[Type::OID, Type::OID, Type::OID])
+            .await?;
+        tx.execute(&st, &[&t.0.id, &target.0.id, &target.0.category_id])
+            .await?;
+
+        let st = tx
+            .prepare_typed(DELETE_TOPIC, &[Type::OID, Type::OID])
+            .await?;
+        tx.execute(&st, &[&deleted_by, &t.0.id]).await?;
+
+        let st = tx.prepare_typed(DEL_TOPIC_PINS, &[Type::OID]).await?;
+        tx.execute(&st, &[&t.0.id]).await?;
+
+        tx.commit().await?;
+
+        let topics = vec![target.0.clone()];
+
+        Ok(TopicMoveReport {
+            removed: vec![t, target],
+            topics,
+        })
+    }
+
+    pub(crate) async fn split_topic(
+        &self,
+        req: &TopicSplitRequest,
+    ) -> Result<TopicMoveReport, ResError> {
+        let mut pool = self.get().await?;
+        let (cli, _) = &mut *pool;
+
+        let tx = cli.transaction().await?;
+
+        let (t, pids) = lock_topic(&tx, req.topic_id).await?;
+        if req.post_ids.iter().any(|pid| !pids.contains(pid)) {
+            return Err(ResError::BadRequest);
+        }
+
+        let cid = match req.category_id {
+            Some(cid) if cid != t.category_id => {
+                check_category(&tx, cid).await?;
+                cid
+            }
+            _ => t.category_id,
+        };
+
+        let st = tx
+            .prepare_typed(SELECT_FIRST_POST, &[Type::OID_ARRAY])
+            .await?;
+        let row = tx
+            .query(&st, &[&req.post_ids])
+            .await?
+            .into_iter()
+            .next()
+            .ok_or(ResError::BadRequest)?;
+        let uid: u32 = row.try_get(0)?;
+        let created_at: NaiveDateTime = row.try_get(1)?;
+
+        let id = crate::model::common::global().lock().next_tid();
+        let now = &Utc::now().naive_utc();
+        let st = tx.prepare_typed(INSERT_TOPIC, INSERT_TOPIC_TYPES).await?;
+        let params: [&(dyn ToSql + Sync); 8] = [
+            &id,
+            &uid,
+            &cid,
+            &t.thumbnail,
+            &req.title,
+            &req.body,
+            &created_at,
+            now,
+        ];
+        let new: Vec<Topic> = tx
+            .query_raw(&st, params.iter().map(|s| *s as _))
+            .await?
+            .parse_row()
+            .await?;
+
+        let st = tx
+            .prepare_typed(MOVE_POSTS, &[Type::OID_ARRAY, Type::OID, Type::OID])
+            .await?;
+        tx.execute(&st, &[&req.post_ids, &id, &cid]).await?;
+
+        let st = tx
+            .prepare_typed(
+                DETACH_SPLIT_REPLIES,
+                &[Type::OID, Type::OID, Type::OID_ARRAY],
+            )
+            .await?;
+        tx.execute(&st, &[&t.id, &id, &req.post_ids]).await?;
+
+        tx.commit().await?;
+
+        let mut topics = vec![t.clone()];
+        topics.extend(new);
+
+        Ok(TopicMoveReport {
+            removed: vec![(t, pids)],
+            topics,
+        })
+    }
+}
+
+// lock alive topic and get its alive post ids.
+async fn lock_topic(tx: &Transaction<'_>, tid: u32) -> Result<(Topic, Vec<u32>), ResError> {
+    let st = tx.prepare_typed(SELECT_TOPIC_LOCK, &[Type::OID]).await?;
+    let t = tx
+        .query_raw(&st, [&tid as &(dyn ToSql + Sync)].iter().map(|s| *s as _))
+        .await?
+        .parse_row::<Topic>()
+        .await?
+        .into_iter()
+        .next()
+        .ok_or(ResError::NotFound)?;
+
+    let st = tx
+        .prepare_typed(SELECT_TOPIC_POST_IDS, &[Type::OID])
+        .await?;
+    let pids = tx
+        .query(&st, &[&tid])
+        .await?
+        .iter()
+        .map(|r| r.try_get(0))
+        .collect::<Result<Vec<u32>, _>>()?;
+
+    Ok((t, pids))
+}
+
+async fn check_category(tx: &Transaction<'_>, cid: u32) -> Result<(), ResError> {
+    let st = tx
+        .prepare_typed(SELECT_CATEGORY_EXIST, &[Type::OID])
+        .await?;
+    if tx.query(&st, &[&cid]).await?.is_empty() {
+        Err(ResError::NotFound)
+    } else {
+        Ok(())
+    }
 }
 
 impl MyRedisPool {
+    // pinned topics are prepended to the first page of category lists.
     pub(crate) async fn get_topics_pop(
         &self,
         cid: u32,
         page: usize,
     ) -> Result<(Vec<Topic>, Vec<u32>), ResError> {
         let key = format!("category:{}:list_pop", cid);
-        self.get_cache_with_uids_from_list(key.as_str(), page, crate::handler::cache::TOPIC_U8)
-            .await
+        let start = (page - 1) * LIMIT;
+
+        let mut conn = self.get().await?.get_conn().clone();
+        let ids = Self::ids_from_list(&mut conn, key.as_str(), start, start + LIMIT - 1).await;
+        with_pinned(&mut conn, Some(cid), page, ids).await
     }
 
-    pub(crate) fn get_topics_pop_all(
+    // hidden is the categories caller can't see and their topics are left out before paging.
+    pub(crate) async fn get_topics_pop_all(
         &self,
         page: usize,
-    ) -> impl Future<Output = Result<(Vec<Topic>, Vec<u32>), ResError>> + '_ {
-        self.get_cache_with_uids_from_list(
-            "category:all:list_pop",
-            page,
-            crate::handler::cache::TOPIC_U8,
-        )
+        hidden: &[u32],
+    ) -> Result<(Vec<Topic>, Vec<u32>), ResError> {
+        let key = "category:all:list_pop";
+
+        let mut conn = self.get().await?.get_conn().clone();
+        let ids = if hidden.is_empty() {
+            let start = (page - 1) * LIMIT;
+            Self::ids_from_list(&mut conn, key, start, start + LIMIT - 1).await
+        } else {
+            Self::visible_topic_ids(&mut conn, key, true, hidden, page).await
+        };
+        with_pinned(&mut conn, None, page, ids).await
+    }
+
+    pub(crate) async fn get_topics_hot(
+        &self,
+        cid: u32,
+        page: usize,
+    ) -> Result<(Vec<Topic>, Vec<u32>), ResError> {
+        let key = format!("category:{}:list_hot", cid);
+        let start = (page - 1) * LIMIT;
+
+        let mut conn = self.get().await?.get_conn().clone();
+        let ids = Self::ids_from_list(&mut conn, key.as_str(), start, start + LIMIT - 1).await;
+        with_pinned(&mut conn, Some(cid), page, ids).await
+    }
+
+    pub(crate) async fn get_topics_hot_all(
+        &self,
+        page: usize,
+        hidden: &[u32],
+    ) -> Result<(Vec<Topic>, Vec<u32>), ResError> {
+        let key = "category:all:list_hot";
+
+        let mut conn = self.get().await?.get_conn().clone();
+        let ids = if hidden.is_empty() {
+            let start = (page - 1) * LIMIT;
+            Self::ids_from_list(&mut conn, key, start, start + LIMIT - 1).await
+        } else {
+            Self::visible_topic_ids(&mut conn, key, true, hidden, page).await
+        };
+        with_pinned(&mut conn, None, page, ids).await
     }
 
     pub(crate) async fn get_topics_late(
@@ -171,8 +616,10 @@ impl MyRedisPool {
         page: usize,
     ) -> Result<(Vec<Topic>, Vec<u32>), ResError> {
         let key = format!("category:{}:topics_time", cid);
-        self.get_cache_with_uids_from_zrevrange(key.as_str(), page, crate::handler::cache::TOPIC_U8)
-            .await
+
+        let mut conn = self.get().await?.get_conn().clone();
+        let ids = Self::ids_from_zrange(&mut conn, key.as_str(), true, (page - 1) * LIMIT).await;
+        with_pinned(&mut conn, Some(cid), page, ids).await
     }
 
     pub(crate) fn get_topics(
@@ -202,4 +649,86 @@ impl MyRedisPool {
             }
         };
     }
+
+    // remove the old topics from lists and rebuild the changed ones from database.
+    pub(crate) async fn rebuild_moved_topics(
+        &self,
+        db_pool: &MyPostgresPool,
+        r: &TopicMoveReport,
+    ) -> Result<(), ResError> {
+        for (t, pids) in r.removed.iter() {
+            self.remove_topic(t, pids).await?;
+        }
+        for t in r.topics.iter() {
+            self.restore_topic_with_posts(db_pool, t).await?;
+        }
+        self.update_topics(&r.topics).await
+    }
+
+    pub(crate) async fn rebuild_moved_topics_send_fail(
+        &self,
+        db_pool: &MyPostgresPool,
+        r: TopicMoveReport,
+        addr: CacheServiceAddr,
+    ) {
+        if self.rebuild_moved_topics(db_pool, &r).await.is_err() {
+            let _ = addr.send(CacheFailedMessage::FailedTopicMove(r)).await;
+        }
+    }
+
+    // posts of topic are queried from database as they are needed to clean up or rebuild cache.
+    pub(crate) async fn remove_topic_with_posts(
+        &self,
+        db_pool: &MyPostgresPool,
+        t: &Topic,
+    ) -> Result<(), ResError> {
+        let pids = db_pool
+            .get_topic_posts_stat(t.id)
+            .await?
+            .into_iter()
+            .map(|(_, pid, _, _, _)| pid)
+            .collect::<Vec<u32>>();
+        self.remove_topic(t, &pids).await
+    }
+
+    pub(crate) async fn restore_topic_with_posts(
+        &self,
+        db_pool: &MyPostgresPool,
+        t: &Topic,
+    ) -> Result<(), ResError> {
+        let posts = db_pool.get_topic_posts_stat(t.id).await?;
+        let pins = db_pool.get_topic_pins(t.id).await?;
+        let reactions = db_pool.get_reaction_counts(Some(t.id)).await?;
+        self.restore_topic(t, posts).await?;
+        self.set_topic_pins(t, &pins).await?;
+        self.set_reaction_counts(&reactions).await
+    }
+
+    pub(crate) async fn remove_topic_send_fail(
+        &self,
+        db_pool: &MyPostgresPool,
+        t: Vec<Topic>,
+        addr: CacheServiceAddr,
+    ) {
+        if let Some(t) = t.first() {
+            if self.remove_topic_with_posts(db_pool, t).await.is_err() {
+                let _ = addr.send(CacheFailedMessage::FailedTopicDelete(t.id)).await;
+            }
+        }
+    }
+
+    pub(crate) async fn restore_topic_send_fail(
+        &self,
+        db_pool: &MyPostgresPool,
+        t: Vec<Topic>,
+        addr: CacheServiceAddr,
+    ) {
+        if let Some(t) = t.first() {
+            if self.restore_topic_with_posts(db_pool, t).await.is_err() {
+                let _ = addr
+                    .send(CacheFailedMessage::FailedTopicRestore(t.id))
+                    .await;
+            }
+        }
+    }
 }
@@ -48,9 +48,17 @@ impl MyPostgresPool {
             params.push(s as &(dyn ToSql + Sync));
             index += 1;
         }
+        if let Some(s) = u.locale.as_ref() {
+            query.push_str(" locale=$");
+            query.push_str(index.to_string().as_str());
+            query.push_str(",");
+            params.push(s as &(dyn ToSql + Sync));
+            index += 1;
+        }
         if let Some(s) = u.privilege.as_ref() {
             query.push_str(" privilege=$");
             query.push_str(index.to_string().as_str());
+            query.push_str(",");
             params.push(s as &(dyn ToSql + Sync));
             index += 1;
         }
@@ -60,19 +60,42 @@ async fn main() -> std::io::Result<()> {
         db_pool.clone(),
         cache_pool.clone(),
         rep_addr.clone(),
+        env.rank(),
     )
     .await;
 
+    let reactions = crate::model::reaction::ReactionSet::new(env.reactions());
+
+    /*
+        init_talk_fanout function will subscribe to the redis channel for talk messages and talk changes.
+        TalkService publishes to the channel so messages reach users connected to other server instances.
+        Every instance delivers them to its own websocket sessions.
+        It also refreshes the instance's heartbeat key so its users' connection counts are kept online.
+    */
+    crate::handler::talk_fanout::init_talk_fanout(
+        env.redis_url(),
+        db_pool.clone(),
+        cache_pool.clone(),
+        talks.clone(),
+        sessions.clone(),
+        rep_addr.clone(),
+    );
+
     /*
         init_psn_service function will start PSNService. It is an actor runs in main thread.
         The return addr is used to send messages to PSNService.
         Request to PSN data will hit local cache and db with a delayed schedule request.
     */
-    let psn_addr =
-        crate::handler::psn::init_psn_service(db_pool.clone(), cache_pool.clone(), rep_addr).await;
+    let psn_addr = crate::handler::psn::init_psn_service(
+        db_pool.clone(),
+        cache_pool.clone(),
+        rep_addr.clone(),
+    )
+    .await;
 
     // server address
     let addr = env.addr();
+    let capture_mails = env.capture_mails();
 
     HttpServer::new(move || {
         /*
@@ -109,6 +132,7 @@ async fn main() -> std::io::Result<()> {
             .app_data(DataRc::new(cache_pool.clone()))
             .app_data(DataRc::new(psn_addr.clone()))
             .app_data(DataRc::new(cache_addr.clone()))
+            .app_data(DataRc::new(reactions.clone()))
             // TalkService is an actor handle web socket connections and communication between
             // client web socket actors.
             .data_factory(move || {
@@ -117,6 +141,7 @@ async fn main() -> std::io::Result<()> {
                     cache_pool.clone(),
                     talks.clone(),
                     sessions.clone(),
+                    rep_addr.clone(),
                 )
             })
             // .wrap(Logger::default())
@@ -124,7 +149,7 @@ async fn main() -> std::io::Result<()> {
             .configure(config::conf_admin)
             .configure(config::conf_auth)
             .configure(config::conf_psn)
-            .configure(config::conf_test)
+            .configure(|cfg| config::conf_test(cfg, capture_mails))
             .configure(config::conf_comm)
             .service(router::stream::upload_file)
             .service(router::talk::talk)
@@ -1,6 +1,7 @@
+use std::sync::atomic::{AtomicU32, Ordering};
 use std::time::{Duration, Instant};
 
-use actix::prelude::{Actor, ActorContext, AsyncContext, Running};
+use actix::prelude::{Actor, ActorContext, Addr, AsyncContext, Running};
 use actix_web_actors::ws;
 
 use crate::handler::talk::{DisconnectRequest, TalkServiceAddr};
@@ -10,12 +11,27 @@ const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(5);
 const CLIENT_TIMEOUT: Duration = Duration::from_secs(10);
 
 // actor handles individual user's websocket connection and communicate with TalkService Actors.
+// id is the user id after auth. conn_id is unique for every connection so one user could connect from many devices.
 pub struct WsChatSession {
     pub id: u32,
+    pub conn_id: u32,
     pub hb: Instant,
     pub addr: TalkServiceAddr,
 }
 
+// websocket connection of user stored in GlobalSessions.
+#[derive(Clone)]
+pub struct SessionAddr {
+    pub conn_id: u32,
+    pub online_status: u32,
+    pub addr: Addr<WsChatSession>,
+}
+
+pub fn next_conn_id() -> u32 {
+    static CONN_ID: AtomicU32 = AtomicU32::new(1);
+    CONN_ID.fetch_add(1, Ordering::Relaxed)
+}
+
 impl Actor for WsChatSession {
     type Context = ws::WebsocketContext<Self>;
 
@@ -26,6 +42,7 @@ impl Actor for WsChatSession {
     fn stopping(&mut self, _: &mut Self::Context) -> Running {
         self.addr.do_send(DisconnectRequest {
             session_id: self.id,
+            conn_id: self.conn_id,
         });
         Running::Stop
     }
@@ -35,7 +52,10 @@ impl WsChatSession {
     pub fn hb(&self, ctx: &mut ws::WebsocketContext<Self>) {
         ctx.run_interval(HEARTBEAT_INTERVAL, |act, ctx| {
             if Instant::now().duration_since(act.hb) > CLIENT_TIMEOUT {
-                act.addr.do_send(DisconnectRequest { session_id: act.id });
+                act.addr.do_send(DisconnectRequest {
+                    session_id: act.id,
+                    conn_id: act.conn_id,
+                });
                 ctx.stop();
                 return;
             }
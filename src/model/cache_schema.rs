@@ -1,7 +1,14 @@
 use chrono::NaiveDateTime;
 use redis::{from_redis_value, ErrorKind, FromRedisValue, RedisResult, Value};
 
-use crate::model::{category::Category, post::Post, psn::UserPSNProfile, topic::Topic, user::User};
+use crate::model::{
+    category::{Category, Visibility},
+    post::Post,
+    psn::UserPSNProfile,
+    reaction::Reactions,
+    topic::Topic,
+    user::User,
+};
 
 // any from redis value error will lead to a database query.
 // (except the data that only live in redis.They are ignored if for whatever reason they are lost or can't be load.)
@@ -136,7 +143,7 @@ impl FromRedisValue for Topic {
         Topic::crate_from_redis_values(items, |t, k, v| match k {
             b"last_reply_time" => t.last_reply_time = parse_naive_date_time(&v),
             b"reply_count" => t.reply_count = parse_count(&v),
-            _ => {}
+            k => parse_reaction(&mut t.reactions, k, v),
         })
     }
 }
@@ -169,7 +176,7 @@ impl FromRedisValue for Post {
         Post::crate_from_redis_values(items, |p, k, v| match k {
             b"last_reply_time" => p.last_reply_time = parse_naive_date_time(&v),
             b"reply_count" => p.reply_count = parse_count(&v),
-            _ => {}
+            k => parse_reaction(&mut p.reactions, k, v),
         })
     }
 }
@@ -190,6 +197,7 @@ impl FromRedisValue for User {
                     u.created_at = uu.created_at;
                     u.privilege = uu.privilege;
                     u.show_email = uu.show_email;
+                    u.locale = uu.locale;
                 }
                 _ => return Err((ErrorKind::ResponseError, "Response type not compatible").into()),
             };
@@ -218,6 +226,22 @@ impl FromRedisValue for Category {
                 b"post_count" => c.post_count = from_redis_value(v).ok(),
                 b"topic_count_new" => c.topic_count_new = from_redis_value(v).ok(),
                 b"post_count_new" => c.post_count_new = from_redis_value(v).ok(),
+                b"visibility" => c.visibility = Visibility::from_u32(from_redis_value(v)?),
+                b"role" => {
+                    let r: String = from_redis_value(v)?;
+                    c.role = if r.is_empty() { None } else { Some(r) };
+                }
+                b"moderators" => {
+                    let m: String = from_redis_value(v)?;
+                    c.moderators = m.split(',').filter_map(|id| id.parse().ok()).collect();
+                }
+                b"parent_id" => {
+                    let p: String = from_redis_value(v)?;
+                    c.parent_id = p.parse().ok();
+                }
+                b"position" => c.position = from_redis_value(v)?,
+                // counts of category self. the ones include subcategories are built from them.
+                b"topic_count_own" | b"post_count_own" => {}
                 _ => return Err((ErrorKind::ResponseError, "Response type not compatible").into()),
             };
             Ok(())
@@ -312,6 +336,35 @@ impl FromRef<Category> for Vec<(&str, Vec<u8>)> {
                 "thumbnail",
                 c.thumbnail.as_str().as_bytes().iter().copied().collect(),
             ),
+            ("visibility", c.visibility.as_u32().to_string().into_bytes()),
+            // empty role is stored so the outdated one is overwritten.
+            (
+                "role",
+                c.role
+                    .as_deref()
+                    .unwrap_or("")
+                    .as_bytes()
+                    .iter()
+                    .copied()
+                    .collect(),
+            ),
+            (
+                "moderators",
+                c.moderators
+                    .iter()
+                    .map(|id| id.to_string())
+                    .collect::<Vec<String>>()
+                    .join(",")
+                    .into_bytes(),
+            ),
+            (
+                "parent_id",
+                c.parent_id
+                    .map(|id| id.to_string())
+                    .unwrap_or_default()
+                    .into_bytes(),
+            ),
+            ("position", c.position.to_string().into_bytes()),
         ]
     }
 }
@@ -348,6 +401,18 @@ fn parse_naive_date_time(t: &[u8]) -> Option<NaiveDateTime> {
     .ok()
 }
 
+// reaction counts are stored as reaction:{name} fields. reactions removed to zero are skipped.
+fn parse_reaction(r: &mut Option<Reactions>, k: &[u8], v: &[u8]) {
+    if k.starts_with(b"reaction:") {
+        if let (Ok(name), Some(count)) = (std::str::from_utf8(&k[9..]), parse_count(v)) {
+            if count > 0 {
+                r.get_or_insert_with(Default::default)
+                    .insert(name.to_owned(), count);
+            }
+        }
+    }
+}
+
 fn parse_count(c: &[u8]) -> Option<u32> {
     unsafe { std::str::from_utf8_unchecked(c) }
         .parse::<u32>()
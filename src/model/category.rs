@@ -6,11 +6,48 @@ pub struct Category {
     pub name: String,
     pub thumbnail: String,
     // fields below stored only in redis. return None when querying database.
+    // counts include the topics and posts of all subcategories.
     pub topic_count: Option<u32>,
     pub post_count: Option<u32>,
     // new is last 24 hrs stores only in redis.
     pub topic_count_new: Option<u32>,
     pub post_count_new: Option<u32>,
+    pub visibility: Visibility,
+    // role needed to view the category when visibility is Role.
+    pub role: Option<String>,
+    // users granted the moderator role in this category.
+    pub moderators: Vec<u32>,
+    // None for top level category.
+    pub parent_id: Option<u32>,
+    // order among the categories with the same parent.
+    pub position: u32,
+}
+
+// Members only categories are visible to all active users.
+// users with global category.manage permission or any role in the category could see it no matter the visibility.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum Visibility {
+    Public,
+    Members,
+    Role,
+}
+
+impl Visibility {
+    pub fn from_u32(v: u32) -> Self {
+        match v {
+            1 => Visibility::Members,
+            2 => Visibility::Role,
+            _ => Visibility::Public,
+        }
+    }
+
+    pub fn as_u32(self) -> u32 {
+        match self {
+            Visibility::Public => 0,
+            Visibility::Members => 1,
+            Visibility::Role => 2,
+        }
+    }
 }
 
 impl Default for Category {
@@ -23,6 +60,11 @@ impl Default for Category {
             post_count: None,
             topic_count_new: None,
             post_count_new: None,
+            visibility: Visibility::Public,
+            role: None,
+            moderators: vec![],
+            parent_id: None,
+            position: 0,
         }
     }
 }
@@ -32,6 +74,13 @@ pub struct CategoryRequest {
     pub id: Option<u32>,
     pub name: Option<String>,
     pub thumbnail: Option<String>,
+    pub visibility: Option<Visibility>,
+    pub role: Option<String>,
+    // replace all moderators of category with the given users.
+    pub moderators: Option<Vec<u32>>,
+    // 0 moves category to top level.
+    pub parent_id: Option<u32>,
+    pub position: Option<u32>,
 }
 
 impl SelfIdString for Category {
@@ -40,29 +89,144 @@ impl SelfIdString for Category {
     }
 }
 
+#[derive(Serialize)]
+pub struct CategoryNode<'a> {
+    #[serde(flatten)]
+    pub category: &'a Category,
+    pub children: Vec<CategoryNode<'a>>,
+}
+
+impl Category {
+    // categories with parent not in the slice are treated as top level.
+    pub fn build_tree<'a>(c: &[&'a Category]) -> Vec<CategoryNode<'a>> {
+        let mut roots = c
+            .iter()
+            .filter(|cat| match cat.parent_id {
+                Some(pid) => !c.iter().any(|p| p.id == pid),
+                None => true,
+            })
+            .map(|cat| Category::build_node(cat, c, 0))
+            .collect::<Vec<CategoryNode>>();
+        CategoryNode::sort(&mut roots);
+        roots
+    }
+
+    // depth is checked in case of a broken tree with cycle.
+    fn build_node<'a>(cat: &'a Category, c: &[&'a Category], depth: usize) -> CategoryNode<'a> {
+        let mut children = if depth < c.len() {
+            c.iter()
+                .filter(|child| child.parent_id == Some(cat.id))
+                .map(|child| Category::build_node(child, c, depth + 1))
+                .collect()
+        } else {
+            vec![]
+        };
+        CategoryNode::sort(&mut children);
+        CategoryNode {
+            category: cat,
+            children,
+        }
+    }
+
+    // ids of the category's ancestors from parent to root.
+    pub fn ancestors(&self, c: &[Category]) -> Vec<u32> {
+        let mut ids = Vec::new();
+        let mut parent = self.parent_id;
+        while let Some(pid) = parent {
+            if pid == self.id || ids.contains(&pid) {
+                break;
+            }
+            ids.push(pid);
+            parent = c.iter().find(|c| c.id == pid).and_then(|c| c.parent_id);
+        }
+        ids
+    }
+}
+
+impl CategoryNode<'_> {
+    fn sort(nodes: &mut Vec<CategoryNode>) {
+        nodes.sort_by(|a, b| {
+            (a.category.position, a.category.id).cmp(&(b.category.position, b.category.id))
+        });
+    }
+}
+
 impl CategoryRequest {
     pub fn check_new(self) -> Result<Self, ResError> {
         if self.name.is_none() || self.thumbnail.is_none() {
             Err(ResError::BadRequest)
         } else {
-            Ok(self)
+            self.check_role()
         }
     }
     pub fn check_update(self) -> Result<Self, ResError> {
         if self.id.is_none() {
             Err(ResError::BadRequest)
+        } else {
+            self.check_role()
+        }
+    }
+    fn check_role(self) -> Result<Self, ResError> {
+        if self.visibility == Some(Visibility::Role) && self.role.is_none() {
+            Err(ResError::BadRequestExplained(
+                "Role visibility needs a role name".into(),
+            ))
         } else {
             Ok(self)
         }
     }
 }
 
+// topics of removed category are moved to target category or soft deleted.
+#[derive(Deserialize, Debug)]
+pub enum RemoveMode {
+    Move,
+    Cascade,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct CategoryRemoveRequest {
+    pub mode: RemoveMode,
+    pub target_id: Option<u32>,
+}
+
+impl CategoryRemoveRequest {
+    pub fn check_remove(self, cid: u32) -> Result<Self, ResError> {
+        match (&self.mode, self.target_id) {
+            (RemoveMode::Move, Some(target)) if target != cid => Ok(self),
+            (RemoveMode::Move, _) => Err(ResError::BadRequestExplained(
+                "Move mode needs a target category other than the removed one".into(),
+            )),
+            (RemoveMode::Cascade, _) => Ok(self),
+        }
+    }
+}
+
+// alive topics and posts moved or deleted along with the removed category.
+// target_id is None when they are deleted.
+#[derive(Serialize, Clone, Debug)]
+pub struct CategoryRemoveReport {
+    pub category_id: u32,
+    pub target_id: Option<u32>,
+    // subcategories are moved to the parent of removed category.
+    pub parent_id: Option<u32>,
+    pub child_ids: Vec<u32>,
+    pub topic_ids: Vec<u32>,
+    pub post_ids: Vec<u32>,
+}
+
 #[derive(Deserialize, Debug)]
 pub enum QueryType {
     Latest,
     Popular,
     PopularAll,
+    // popular topics decayed by the time since the last reply.
+    Hot,
+    HotAll,
     All,
+    // topics with the tag from all categories.
+    TagLatest,
+    TagPopular,
 }
 
 #[derive(Deserialize, Debug)]
@@ -70,4 +234,5 @@ pub struct CategoryQuery {
     pub category_id: Option<u32>,
     pub page: Option<usize>,
     pub query_type: QueryType,
+    pub tag: Option<String>,
 }
@@ -4,12 +4,11 @@ use std::sync::Arc;
 use std::task::{Context, Poll};
 use std::time::Duration;
 
-use actix::prelude::Addr;
 use hashbrown::HashMap;
 use once_cell::sync::OnceCell;
 use parking_lot::{Mutex, RwLock};
 
-use crate::model::{actors::WsChatSession, errors::ResError, talk::Talk};
+use crate::model::{actors::SessionAddr, errors::ResError, talk::Talk};
 use crate::util::validation as validate;
 
 pub const fn dur(millis: u64) -> Duration {
@@ -102,8 +101,9 @@ where
 #[derive(Clone, Default)]
 pub struct GlobalTalks(pub Arc<RwLock<HashMap<u32, Talk>>>);
 
+// user id as key and all websocket connections of the user as value.
 #[derive(Clone, Default)]
-pub struct GlobalSessions(pub Arc<RwLock<HashMap<u32, Addr<WsChatSession>>>>);
+pub struct GlobalSessions(pub Arc<RwLock<HashMap<u32, Vec<SessionAddr>>>>);
 
 pub fn global() -> &'static Mutex<GlobalVars> {
     static GLOBALS: OnceCell<Mutex<GlobalVars>> = OnceCell::new();
@@ -2,10 +2,14 @@ use chrono::NaiveDateTime;
 use tokio_postgres::Row;
 
 use crate::model::{
-    category::Category,
+    category::{Category, Visibility},
     errors::ResError,
+    pin::TopicPin,
     post::Post,
     psn::{UserTrophy, UserTrophySet, UserTrophyTitle},
+    read::{CategoryRead, TopicRead},
+    revision::{PostRevision, TopicRevision},
+    search::SearchHit,
     talk::{PrivateMessage, PublicMessage, Relation, Talk},
     topic::Topic,
     user::User,
@@ -32,6 +36,88 @@ impl TryFromRow<Row> for Topic {
             is_visible: row.try_get(9)?,
             last_reply_time: None,
             reply_count: None,
+            reactions: None,
+        })
+    }
+}
+
+impl TryFromRow<Row> for SearchHit {
+    type Error = ResError;
+    fn try_from_row(row: &Row) -> Result<Self, Self::Error> {
+        Ok(SearchHit {
+            topic_id: row.try_get(0)?,
+            post_id: row.try_get(1)?,
+            category_id: row.try_get(2)?,
+            user_id: row.try_get(3)?,
+            title: row.try_get(4)?,
+            snippet: row.try_get(5)?,
+            rank: row.try_get(6)?,
+            created_at: row.try_get(7)?,
+        })
+    }
+}
+
+impl TryFromRow<Row> for TopicRevision {
+    type Error = ResError;
+    fn try_from_row(row: &Row) -> Result<Self, Self::Error> {
+        Ok(TopicRevision {
+            id: row.try_get(0)?,
+            topic_id: row.try_get(1)?,
+            title: row.try_get(2)?,
+            body: row.try_get(3)?,
+            thumbnail: row.try_get(4)?,
+            edited_by: row.try_get(5)?,
+            edited_at: row.try_get(6)?,
+        })
+    }
+}
+
+impl TryFromRow<Row> for PostRevision {
+    type Error = ResError;
+    fn try_from_row(row: &Row) -> Result<Self, Self::Error> {
+        Ok(PostRevision {
+            id: row.try_get(0)?,
+            post_id: row.try_get(1)?,
+            post_content: row.try_get(2)?,
+            edited_by: row.try_get(3)?,
+            edited_at: row.try_get(4)?,
+        })
+    }
+}
+
+impl TryFromRow<Row> for TopicPin {
+    type Error = ResError;
+    fn try_from_row(row: &Row) -> Result<Self, Self::Error> {
+        Ok(TopicPin {
+            topic_id: row.try_get(0)?,
+            category_id: row.try_get(1)?,
+            is_global: row.try_get(2)?,
+            is_pinned: true,
+            expires_at: row.try_get(3)?,
+        })
+    }
+}
+
+impl TryFromRow<Row> for TopicRead {
+    type Error = ResError;
+    fn try_from_row(row: &Row) -> Result<Self, Self::Error> {
+        Ok(TopicRead {
+            user_id: row.try_get(0)?,
+            topic_id: row.try_get(1)?,
+            last_read_post_id: row.try_get(2)?,
+            read_count: row.try_get(3)?,
+            read_at: row.try_get(4)?,
+        })
+    }
+}
+
+impl TryFromRow<Row> for CategoryRead {
+    type Error = ResError;
+    fn try_from_row(row: &Row) -> Result<Self, Self::Error> {
+        Ok(CategoryRead {
+            user_id: row.try_get(0)?,
+            category_id: row.try_get(1)?,
+            read_at: row.try_get(2)?,
         })
     }
 }
@@ -49,6 +135,7 @@ impl TryFromRow<Row> for User {
             created_at: row.try_get(6)?,
             privilege: row.try_get(7)?,
             show_email: row.try_get(8)?,
+            locale: row.try_get("locale")?,
             online_status: None,
             last_online: None,
         })
@@ -70,6 +157,7 @@ impl TryFromRow<Row> for Post {
             last_reply_time: None,
             is_locked: row.try_get(8)?,
             reply_count: None,
+            reactions: None,
         })
     }
 }
@@ -101,6 +189,11 @@ impl TryFromRow<Row> for Category {
             post_count: None,
             topic_count_new: None,
             post_count_new: None,
+            visibility: Visibility::from_u32(row.try_get(3)?),
+            role: row.try_get(4)?,
+            moderators: row.try_get(5)?,
+            parent_id: row.try_get(6)?,
+            position: row.try_get(7)?,
         })
     }
 }
@@ -118,9 +211,12 @@ impl TryFromRow<Row> for PublicMessage {
     type Error = ResError;
     fn try_from_row(row: &Row) -> Result<Self, Self::Error> {
         Ok(PublicMessage {
-            talk_id: row.try_get(0)?,
-            time: row.try_get(1)?,
-            text: row.try_get(2)?,
+            id: row.try_get(0)?,
+            talk_id: row.try_get(1)?,
+            user_id: row.try_get(2)?,
+            time: row.try_get(3)?,
+            text: row.try_get(4)?,
+            edited_at: row.try_get(5)?,
         })
     }
 }
@@ -129,9 +225,12 @@ impl TryFromRow<Row> for PrivateMessage {
     type Error = ResError;
     fn try_from_row(row: &Row) -> Result<Self, Self::Error> {
         Ok(PrivateMessage {
-            user_id: row.try_get(0)?,
-            time: row.try_get(2)?,
-            text: row.try_get(3)?,
+            id: row.try_get(0)?,
+            user_id: row.try_get(1)?,
+            to_id: row.try_get(2)?,
+            time: row.try_get(3)?,
+            text: row.try_get(4)?,
+            edited_at: row.try_get(5)?,
         })
     }
 }
@@ -61,6 +61,8 @@ pub enum ResError {
     HttpClient,
     #[display(fmt = "Mail Service Error")]
     MailingError,
+    #[display(fmt = "Too Many Requests")]
+    TooManyRequests,
 }
 
 impl ResponseError for ResError {
@@ -80,6 +82,9 @@ impl ResponseError for ResError {
             ResError::NoContent => {
                 HttpResponse::NoContent().json(ErrorMessage::new(204, "No Content"))
             }
+            ResError::NotFound => {
+                HttpResponse::NotFound().json(ErrorMessage::new(404, "Not Found"))
+            }
             ResError::UsernameTaken => {
                 HttpResponse::BadRequest().json(ErrorMessage::new(400, "Username already taken"))
             }
@@ -124,6 +129,10 @@ impl ResponseError for ResError {
                 HttpResponse::Forbidden().json(ErrorMessage::new(403, "User is blocked"))
             }
 
+            ResError::TooManyRequests => {
+                HttpResponse::TooManyRequests().json(ErrorMessage::new(429, "Too Many Requests"))
+            }
+
             _ => HttpResponse::InternalServerError()
                 .json(ErrorMessage::new(500, "Internal Server Error")),
         }
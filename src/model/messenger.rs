@@ -1,7 +1,8 @@
-use lettre::SmtpTransport;
+use crate::handler::{mail_template::MailTemplates, mail_transport::MailTransport};
 
 pub struct Mailer {
-    pub mailer: SmtpTransport,
+    pub transport: Box<dyn MailTransport>,
+    pub templates: MailTemplates,
     pub server_url: String,
     pub self_addr: String,
     pub self_name: String,
@@ -16,13 +17,30 @@ pub struct Twilio {
 
 #[derive(Serialize, Deserialize, Debug)]
 pub enum Mail<'a> {
-    Activation { to: &'a str, uuid: &'a str },
-    ErrorReport { report: &'a str },
+    Activation {
+        to: &'a str,
+        uuid: &'a str,
+        #[serde(default)]
+        locale: &'a str,
+    },
+    PasswordReset {
+        to: &'a str,
+        uuid: &'a str,
+        #[serde(default)]
+        locale: &'a str,
+    },
+    ErrorReport {
+        report: &'a str,
+    },
 }
 
 impl<'a> Mail<'a> {
-    pub fn new_activation(to: &'a str, uuid: &'a str) -> Self {
-        Mail::Activation { to, uuid }
+    pub fn new_activation(to: &'a str, uuid: &'a str, locale: &'a str) -> Self {
+        Mail::Activation { to, uuid, locale }
+    }
+
+    pub fn new_password_reset(to: &'a str, uuid: &'a str, locale: &'a str) -> Self {
+        Mail::PasswordReset { to, uuid, locale }
     }
 }
 
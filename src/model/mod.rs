@@ -5,8 +5,15 @@ pub mod common;
 pub mod db_schema;
 pub mod errors;
 pub mod messenger;
+pub mod permission;
+pub mod pin;
 pub mod post;
 pub mod psn;
+pub mod reaction;
+pub mod read;
+pub mod revision;
+pub mod search;
+pub mod tag;
 pub mod talk;
 pub mod topic;
 pub mod user;
@@ -0,0 +1,112 @@
+use crate::model::errors::ResError;
+
+// permissions are granted to users through roles stored in database.
+// a role could be granted globally or in a single category.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum Permission {
+    TopicEditAny,
+    TopicLock,
+    TopicHide,
+    TopicPin,
+    TopicMove,
+    TopicDeleteAny,
+    PostEditAny,
+    PostLock,
+    PostDeleteAny,
+    CategoryManage,
+    UserManage,
+    RoleManage,
+    PsnAdmin,
+}
+
+impl Permission {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Permission::TopicEditAny => "topic.edit_any",
+            Permission::TopicLock => "topic.lock",
+            Permission::TopicHide => "topic.hide",
+            Permission::TopicPin => "topic.pin",
+            Permission::TopicMove => "topic.move",
+            Permission::TopicDeleteAny => "topic.delete_any",
+            Permission::PostEditAny => "post.edit_any",
+            Permission::PostLock => "post.lock",
+            Permission::PostDeleteAny => "post.delete_any",
+            Permission::CategoryManage => "category.manage",
+            Permission::UserManage => "user.manage",
+            Permission::RoleManage => "role.manage",
+            Permission::PsnAdmin => "psn.admin",
+        }
+    }
+
+    pub fn from_name(s: &str) -> Option<Self> {
+        match s {
+            "topic.edit_any" => Some(Permission::TopicEditAny),
+            "topic.lock" => Some(Permission::TopicLock),
+            "topic.hide" => Some(Permission::TopicHide),
+            "topic.pin" => Some(Permission::TopicPin),
+            "topic.move" => Some(Permission::TopicMove),
+            "topic.delete_any" => Some(Permission::TopicDeleteAny),
+            "post.edit_any" => Some(Permission::PostEditAny),
+            "post.lock" => Some(Permission::PostLock),
+            "post.delete_any" => Some(Permission::PostDeleteAny),
+            "category.manage" => Some(Permission::CategoryManage),
+            "user.manage" => Some(Permission::UserManage),
+            "role.manage" => Some(Permission::RoleManage),
+            "psn.admin" => Some(Permission::PsnAdmin),
+            _ => None,
+        }
+    }
+}
+
+// where permissions are resolved. roles granted in the category of topic/post are counted along with the global ones.
+#[derive(Debug, Clone, Copy)]
+pub enum Scope {
+    Global,
+    Category(u32),
+    Topic(u32),
+    Post(u32),
+}
+
+#[derive(Serialize, Debug, Default)]
+pub struct PermissionSet(pub Vec<Permission>);
+
+impl PermissionSet {
+    pub fn has(&self, p: Permission) -> bool {
+        self.0.contains(&p)
+    }
+
+    pub fn require(&self, p: Permission) -> Result<(), ResError> {
+        if self.has(p) {
+            Ok(())
+        } else {
+            Err(ResError::Unauthorized)
+        }
+    }
+
+    // require permission only when the field is going to be updated.
+    pub fn require_some<T>(&self, field: &Option<T>, p: Permission) -> Result<(), ResError> {
+        match field {
+            Some(_) => self.require(p),
+            None => Ok(()),
+        }
+    }
+
+    pub fn contains_all(&self, other: &PermissionSet) -> bool {
+        other.0.iter().all(|p| self.has(*p))
+    }
+}
+
+// grant or revoke role of user. category_id is None for global role.
+#[derive(Deserialize, Debug)]
+pub struct RoleRequest {
+    pub user_id: u32,
+    pub role: String,
+    pub category_id: Option<u32>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct UserRole {
+    pub user_id: u32,
+    pub role: String,
+    pub category_id: Option<u32>,
+}
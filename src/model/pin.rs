@@ -0,0 +1,62 @@
+use chrono::{NaiveDateTime, Utc};
+
+use crate::model::errors::ResError;
+
+// category pins are shown on top of the category's lists.
+// global pins are shown on top of all lists.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum PinScope {
+    Category,
+    Global,
+}
+
+impl PinScope {
+    pub fn is_global(self) -> bool {
+        self == PinScope::Global
+    }
+}
+
+// pin or unpin topic. pin never expires when expires_at is None.
+#[derive(Deserialize, Debug)]
+pub struct PinRequest {
+    pub scope: PinScope,
+    pub is_pinned: bool,
+    pub expires_at: Option<NaiveDateTime>,
+}
+
+impl PinRequest {
+    pub fn check(&self) -> Result<(), ResError> {
+        match self.expires_at {
+            Some(t) if t <= Utc::now().naive_utc() => Err(ResError::BadRequestExplained(
+                "Pin expire time must be in the future".to_owned(),
+            )),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub struct TopicPin {
+    pub topic_id: u32,
+    pub category_id: u32,
+    pub is_global: bool,
+    pub is_pinned: bool,
+    pub expires_at: Option<NaiveDateTime>,
+}
+
+impl TopicPin {
+    pub fn list_key(&self) -> String {
+        if self.is_global {
+            "category:all:pinned".to_owned()
+        } else {
+            format!("category:{}:pinned", self.category_id)
+        }
+    }
+
+    // pinned lists are scored by expire time in milliseconds so the expired ones could be skipped.
+    pub fn score(&self) -> f64 {
+        self.expires_at
+            .map(|t| t.timestamp_millis() as f64)
+            .unwrap_or(std::f64::INFINITY)
+    }
+}
@@ -3,10 +3,14 @@ use chrono::NaiveDateTime;
 use crate::model::{
     common::{SelfId, SelfIdString, SelfUserId},
     errors::ResError,
+    reaction::Reactions,
     user::{AttachUser, User, UserRef},
 };
 use std::future::Future;
 
+// replies nested deeper than this are not included in thread.
+const THREAD_DEPTH_MAX: usize = 64;
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Post {
     pub id: u32,
@@ -21,6 +25,8 @@ pub struct Post {
     pub last_reply_time: Option<NaiveDateTime>,
     pub is_locked: bool,
     pub reply_count: Option<u32>,
+    // reaction counts only store in redis too.
+    pub reactions: Option<Reactions>,
 }
 
 impl Default for Post {
@@ -37,6 +43,7 @@ impl Default for Post {
             last_reply_time: None,
             is_locked: false,
             reply_count: None,
+            reactions: None,
         }
     }
 }
@@ -58,6 +65,34 @@ impl Post {
         p.iter().map(|p| p.attach_user(u)).collect()
     }
 
+    // build reply tree with the posts replied to root. root is None for the top level posts of topic.
+    pub fn attach_users_thread<'a>(
+        root: Option<u32>,
+        p: &'a [Post],
+        u: &'a [User],
+    ) -> Vec<PostThread<'a>> {
+        Post::build_thread(root, p, u, 0)
+    }
+
+    // depth is checked in case of broken reply links with cycle.
+    fn build_thread<'a>(
+        root: Option<u32>,
+        p: &'a [Post],
+        u: &'a [User],
+        depth: usize,
+    ) -> Vec<PostThread<'a>> {
+        if depth >= p.len().min(THREAD_DEPTH_MAX) {
+            return vec![];
+        }
+        p.iter()
+            .filter(|post| post.post_id == root)
+            .map(|post| PostThread {
+                post: post.attach_user(u),
+                replies: Post::build_thread(Some(post.id), p, u, depth + 1),
+            })
+            .collect()
+    }
+
     pub fn sort(p: Vec<Post>, pids: &[u32]) -> impl Future<Output = Vec<Post>> + '_ {
         crate::model::common::OutOfOrder::sort(pids, p)
     }
@@ -97,6 +132,13 @@ pub struct PostWithUser<'a> {
     pub user: Option<UserRef<'a>>,
 }
 
+#[derive(Serialize)]
+pub struct PostThread<'a> {
+    #[serde(flatten)]
+    pub post: PostWithUser<'a>,
+    pub replies: Vec<PostThread<'a>>,
+}
+
 impl<'u> AttachUser<'u> for Post {
     type Output = PostWithUser<'u>;
     fn self_user_id(&self) -> u32 {
@@ -0,0 +1,85 @@
+use std::collections::BTreeMap;
+
+use crate::model::errors::ResError;
+
+const MAX_REACTION_LEN: usize = 32;
+
+// reaction names allowed. like is always in the set and the rest are configured in .env.
+#[derive(Clone, Debug)]
+pub struct ReactionSet(Vec<String>);
+
+impl ReactionSet {
+    pub fn new(names: &str) -> Self {
+        let mut set = vec!["like".to_owned()];
+        for name in names.split(',') {
+            let name = name.trim();
+            // name is a part of redis hash field so the separator is not allowed.
+            if name.is_empty() || name.len() > MAX_REACTION_LEN || name.contains(':') {
+                continue;
+            }
+            if !set.iter().any(|s| s == name) {
+                set.push(name.to_owned());
+            }
+        }
+        ReactionSet(set)
+    }
+
+    pub fn check(&self, name: &str) -> Result<(), ResError> {
+        if self.0.iter().any(|s| s == name) {
+            Ok(())
+        } else {
+            Err(ResError::BadRequestExplained(format!(
+                "Unknown reaction: {}",
+                name
+            )))
+        }
+    }
+}
+
+// react to a topic or a post. is_reacted false removes the reaction.
+#[derive(Deserialize, Debug)]
+pub struct ReactionRequest {
+    pub topic_id: Option<u32>,
+    pub post_id: Option<u32>,
+    pub reaction: String,
+    pub is_reacted: bool,
+}
+
+impl ReactionRequest {
+    pub fn check(self, set: &ReactionSet) -> Result<Self, ResError> {
+        if self.topic_id.is_some() == self.post_id.is_some() {
+            return Err(ResError::BadRequest);
+        }
+        set.check(self.reaction.as_str())?;
+        Ok(self)
+    }
+}
+
+// the topic or post reacted to. category_id is used to check visibility and update the category's reaction list.
+#[derive(Debug)]
+pub struct ReactionTarget {
+    pub topic_id: u32,
+    pub post_id: Option<u32>,
+    pub category_id: u32,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct ReactionReport {
+    pub topic_id: u32,
+    pub post_id: Option<u32>,
+    #[serde(skip)]
+    pub category_id: u32,
+    pub reaction: String,
+    // 1 for added, -1 for removed and 0 when nothing changed.
+    pub incr: i64,
+}
+
+// reaction counts of topics and posts used to rebuild cache.
+// topics are (topic_id, category_id, reaction, count) and posts are (post_id, reaction, count).
+#[derive(Default, Debug)]
+pub struct ReactionCounts {
+    pub topics: Vec<(u32, u32, String, u32)>,
+    pub posts: Vec<(u32, String, u32)>,
+}
+
+pub type Reactions = BTreeMap<String, u32>;
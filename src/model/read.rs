@@ -0,0 +1,93 @@
+use chrono::NaiveDateTime;
+
+use crate::model::topic::TopicWithUser;
+
+// read state of a topic. read_count and read_at are the topic's reply_count and last_reply_time when it's read.
+#[derive(Debug)]
+pub struct TopicRead {
+    pub user_id: u32,
+    pub topic_id: u32,
+    pub last_read_post_id: Option<u32>,
+    pub read_count: u32,
+    pub read_at: NaiveDateTime,
+}
+
+// all topics of category with no reply after read_at are read.
+#[derive(Debug)]
+pub struct CategoryRead {
+    pub user_id: u32,
+    pub category_id: u32,
+    pub read_at: NaiveDateTime,
+}
+
+// read states of user are stored in user:{uid}:reads hash with topic:{tid} and category:{cid} fields.
+// topic field value is {last_read_post_id}:{read_count}:{read_at in milliseconds}. last_read_post_id is 0 for no post.
+// category field value is {read_at in milliseconds}.
+impl TopicRead {
+    pub fn field(topic_id: u32) -> String {
+        format!("topic:{}", topic_id)
+    }
+
+    pub fn value(&self) -> String {
+        format!(
+            "{}:{}:{}",
+            self.last_read_post_id.unwrap_or(0),
+            self.read_count,
+            self.read_at.timestamp_millis()
+        )
+    }
+
+    pub fn from_value(user_id: u32, topic_id: u32, v: &str) -> Option<Self> {
+        let mut v = v.split(':');
+        let pid = v.next()?.parse::<u32>().ok()?;
+        let read_count = v.next()?.parse::<u32>().ok()?;
+        let read_at = v.next()?.parse::<i64>().ok()?;
+        Some(TopicRead {
+            user_id,
+            topic_id,
+            last_read_post_id: if pid == 0 { None } else { Some(pid) },
+            read_count,
+            read_at: from_millis(read_at),
+        })
+    }
+}
+
+impl CategoryRead {
+    pub fn field(category_id: u32) -> String {
+        format!("category:{}", category_id)
+    }
+
+    pub fn value(&self) -> String {
+        self.read_at.timestamp_millis().to_string()
+    }
+
+    pub fn from_value(user_id: u32, category_id: u32, v: &str) -> Option<Self> {
+        let read_at = v.parse::<i64>().ok()?;
+        Some(CategoryRead {
+            user_id,
+            category_id,
+            read_at: from_millis(read_at),
+        })
+    }
+}
+
+// read state of the user requesting. it's attached to topic lists per request and never cached.
+#[derive(Serialize, Default, Clone, Debug)]
+pub struct TopicUnread {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub unread_count: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub first_unread_post_id: Option<u32>,
+}
+
+#[derive(Serialize)]
+pub struct TopicWithUnread<'a> {
+    #[serde(flatten)]
+    pub topic: TopicWithUser<'a>,
+    #[serde(flatten)]
+    pub unread: Option<&'a TopicUnread>,
+}
+
+fn from_millis(t: i64) -> NaiveDateTime {
+    NaiveDateTime::from_timestamp(t / 1000, (t % 1000) as u32 * 1_000_000)
+}
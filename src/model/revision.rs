@@ -0,0 +1,64 @@
+use chrono::NaiveDateTime;
+
+use crate::util::diff::{diff, DiffChunk};
+
+// a revision is the content of topic/post before an edit. edited_by is the user made the edit.
+#[derive(Serialize, Debug)]
+pub struct TopicRevision {
+    pub id: i32,
+    pub topic_id: u32,
+    pub title: String,
+    pub body: String,
+    pub thumbnail: String,
+    pub edited_by: u32,
+    pub edited_at: NaiveDateTime,
+}
+
+#[derive(Serialize, Debug)]
+pub struct PostRevision {
+    pub id: i32,
+    pub post_id: u32,
+    pub post_content: String,
+    pub edited_by: u32,
+    pub edited_at: NaiveDateTime,
+}
+
+// diff from revision to another revision. to the current content when to is None.
+#[derive(Deserialize, Debug)]
+pub struct RevisionDiffQuery {
+    pub from: i32,
+    pub to: Option<i32>,
+}
+
+#[derive(Serialize)]
+pub struct TopicRevisionDiff {
+    pub title: Vec<DiffChunk>,
+    pub body: Vec<DiffChunk>,
+    pub thumbnail: Vec<DiffChunk>,
+}
+
+impl TopicRevisionDiff {
+    pub fn new(
+        (old_title, old_body, old_thumb): (&str, &str, &str),
+        (title, body, thumb): (&str, &str, &str),
+    ) -> Self {
+        TopicRevisionDiff {
+            title: diff(old_title, title),
+            body: diff(old_body, body),
+            thumbnail: diff(old_thumb, thumb),
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct PostRevisionDiff {
+    pub post_content: Vec<DiffChunk>,
+}
+
+impl PostRevisionDiff {
+    pub fn new(old: &str, new: &str) -> Self {
+        PostRevisionDiff {
+            post_content: diff(old, new),
+        }
+    }
+}
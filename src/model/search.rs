@@ -0,0 +1,76 @@
+use chrono::NaiveDateTime;
+
+use crate::model::{
+    errors::ResError,
+    user::{AttachUser, User, UserRef},
+};
+
+#[derive(Deserialize, Debug)]
+pub enum SearchOrder {
+    Relevance,
+    Recency,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct SearchQuery {
+    pub q: String,
+    pub category_id: Option<u32>,
+    pub user_id: Option<u32>,
+    // date range of created_at in the form of 2020-01-01T00:00:00
+    pub from: Option<NaiveDateTime>,
+    pub to: Option<NaiveDateTime>,
+    pub order: Option<SearchOrder>,
+    pub page: Option<usize>,
+}
+
+impl SearchQuery {
+    pub fn check_query(self) -> Result<Self, ResError> {
+        let len = self.q.trim().chars().count();
+        if len == 0 || len > 256 {
+            return Err(ResError::BadRequestExplained(
+                "Search query must be 1 to 256 characters".into(),
+            ));
+        }
+        Ok(self)
+    }
+}
+
+// a search hit could be a topic or a post. post_id is None when the hit is a topic.
+// title and snippet are html escaped and highlighted with <b></b> tags around matched words.
+#[derive(Serialize, Debug)]
+pub struct SearchHit {
+    pub topic_id: u32,
+    pub post_id: Option<u32>,
+    pub category_id: u32,
+    pub user_id: u32,
+    pub title: String,
+    pub snippet: String,
+    pub rank: f32,
+    pub created_at: NaiveDateTime,
+}
+
+impl SearchHit {
+    pub fn attach_users<'a>(h: &'a [SearchHit], u: &'a [User]) -> Vec<SearchHitWithUser<'a>> {
+        h.iter().map(|h| h.attach_user(u)).collect()
+    }
+}
+
+#[derive(Serialize)]
+pub struct SearchHitWithUser<'a> {
+    #[serde(flatten)]
+    pub hit: &'a SearchHit,
+    pub user: Option<UserRef<'a>>,
+}
+
+impl<'u> AttachUser<'u> for SearchHit {
+    type Output = SearchHitWithUser<'u>;
+    fn self_user_id(&self) -> u32 {
+        self.user_id
+    }
+    fn attach_user(&'u self, users: &'u [User]) -> Self::Output {
+        SearchHitWithUser {
+            user: self.make_field(users),
+            hit: self,
+        }
+    }
+}
@@ -0,0 +1,51 @@
+use crate::model::errors::ResError;
+
+const MAX_TAGS: usize = 10;
+const MAX_TAG_LEN: usize = 32;
+
+// replace all tags of topic. tags are lowercased and the duplicates are removed.
+#[derive(Deserialize, Debug)]
+pub struct TopicTagRequest {
+    pub topic_id: u32,
+    pub tags: Vec<String>,
+}
+
+impl TopicTagRequest {
+    pub fn check(self) -> Result<Self, ResError> {
+        let mut tags: Vec<String> = Vec::with_capacity(self.tags.len());
+        for tag in self.tags.iter() {
+            let tag = tag.trim().to_lowercase();
+            // tag is a part of redis key so the separator is not allowed.
+            if tag.is_empty()
+                || tag.chars().count() > MAX_TAG_LEN
+                || tag.contains(|c: char| c == ':' || c.is_whitespace())
+            {
+                return Err(ResError::BadRequestExplained(format!(
+                    "Invalid tag: {}",
+                    tag
+                )));
+            }
+            if !tags.contains(&tag) {
+                tags.push(tag);
+            }
+        }
+
+        if tags.len() > MAX_TAGS {
+            return Err(ResError::BadRequestExplained(format!(
+                "Topic could have {} tags at most",
+                MAX_TAGS
+            )));
+        }
+
+        Ok(TopicTagRequest {
+            topic_id: self.topic_id,
+            tags,
+        })
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub struct TopicTags {
+    pub topic_id: u32,
+    pub tags: Vec<String>,
+}
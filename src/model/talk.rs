@@ -24,6 +24,8 @@ pub enum SendMessage<'a> {
     Users(&'a [User]),
     Talks(&'a [Talk]),
     Friends(&'a [u32]),
+    MessageEdited(&'a MessageEdited),
+    MessageDeleted(&'a MessageDeleted),
     Success(&'a str),
     Error(&'a str),
 }
@@ -41,16 +43,39 @@ pub struct Relation {
 
 #[derive(Serialize)]
 pub struct PublicMessage {
+    pub id: i64,
     pub talk_id: u32,
+    pub user_id: u32,
     pub time: NaiveDateTime,
     pub text: String,
+    pub edited_at: Option<NaiveDateTime>,
 }
 
+// user_id is the sender and to_id is the receiver.
 #[derive(Serialize)]
 pub struct PrivateMessage {
+    pub id: i64,
     pub user_id: u32,
+    pub to_id: u32,
     pub time: NaiveDateTime,
     pub text: String,
+    pub edited_at: Option<NaiveDateTime>,
+}
+
+// talk_id is Some for public message and None for private message.
+#[derive(Serialize)]
+pub struct MessageEdited {
+    pub id: i64,
+    pub talk_id: Option<u32>,
+    pub text: String,
+    pub edited_at: NaiveDateTime,
+}
+
+#[derive(Serialize)]
+pub struct MessageDeleted {
+    pub id: i64,
+    pub talk_id: Option<u32>,
+    pub deleted_at: NaiveDateTime,
 }
 
 #[derive(Message)]
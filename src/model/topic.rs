@@ -3,12 +3,14 @@ use chrono::NaiveDateTime;
 use crate::model::{
     common::{SelfId, SelfIdString, SelfUserId},
     errors::ResError,
-    post::{Post, PostWithUser},
+    pin::PinRequest,
+    post::{Post, PostThread, PostWithUser},
+    reaction::Reactions,
     user::{AttachUser, User, UserRef},
 };
 use std::future::Future;
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Topic {
     pub id: u32,
     pub user_id: u32,
@@ -24,6 +26,8 @@ pub struct Topic {
     pub last_reply_time: Option<NaiveDateTime>,
     // the same as last_reply_time only stores in redis.
     pub reply_count: Option<u32>,
+    // reaction counts only store in redis too.
+    pub reactions: Option<Reactions>,
 }
 
 impl Default for Topic {
@@ -41,6 +45,7 @@ impl Default for Topic {
             is_visible: true,
             last_reply_time: None,
             reply_count: None,
+            reactions: None,
         }
     }
 }
@@ -56,6 +61,17 @@ impl Topic {
             posts: p.iter().map(|p| p.attach_user(&u)).collect(),
         }
     }
+    pub fn attach_users_with_thread<'a>(
+        t: Option<&'a Topic>,
+        root: Option<u32>,
+        p: &'a [Post],
+        u: &'a [User],
+    ) -> TopicWithThread<'a> {
+        TopicWithThread {
+            topic: t.map(|t| t.attach_user(&u)),
+            posts: Post::attach_users_thread(root, p, u),
+        }
+    }
     pub fn attach_users<'a>(t: &'a [Topic], u: &'a [User]) -> Vec<TopicWithUser<'a>> {
         t.iter().map(|t| t.attach_user(&u)).collect()
     }
@@ -75,6 +91,7 @@ pub struct TopicRequest {
     pub thumbnail: Option<String>,
     pub is_locked: Option<bool>,
     pub is_visible: Option<bool>,
+    pub pin: Option<PinRequest>,
 }
 
 impl TopicRequest {
@@ -98,9 +115,79 @@ impl TopicRequest {
         if self.user_id.is_some() {
             self.is_locked = None;
             self.is_visible = None;
+            self.pin = None;
+        }
+        if let Some(pin) = self.pin.as_ref() {
+            pin.check()?;
         }
         Ok(self)
     }
+
+    // pin is stored in its own table so the topic row is not touched.
+    pub fn is_pin_only(&self) -> bool {
+        self.pin.is_some()
+            && self.title.is_none()
+            && self.body.is_none()
+            && self.thumbnail.is_none()
+            && self.is_locked.is_none()
+            && self.is_visible.is_none()
+    }
+}
+
+// move topic and all its posts to another category.
+#[derive(Deserialize)]
+pub struct TopicMoveRequest {
+    pub topic_id: u32,
+    pub category_id: u32,
+}
+
+// move all posts of topic to target and soft delete the topic.
+#[derive(Deserialize)]
+pub struct TopicMergeRequest {
+    pub topic_id: u32,
+    pub target_id: u32,
+}
+
+impl TopicMergeRequest {
+    pub fn check(self) -> Result<Self, ResError> {
+        if self.topic_id == self.target_id {
+            Err(ResError::BadRequest)
+        } else {
+            Ok(self)
+        }
+    }
+}
+
+// move posts to a new topic. the first post(by created time) is used as the author of new topic.
+// the posts are all moved so the body of new topic is given by request.
+// category_id is None when the new topic is in the same category.
+#[derive(Deserialize)]
+pub struct TopicSplitRequest {
+    pub topic_id: u32,
+    pub post_ids: Vec<u32>,
+    pub title: String,
+    pub body: String,
+    pub category_id: Option<u32>,
+}
+
+impl TopicSplitRequest {
+    pub fn check(mut self) -> Result<Self, ResError> {
+        self.post_ids.sort();
+        self.post_ids.dedup();
+        if self.post_ids.is_empty() || self.title.trim().is_empty() || self.body.trim().is_empty() {
+            Err(ResError::BadRequest)
+        } else {
+            Ok(self)
+        }
+    }
+}
+
+// topics before the change with their alive post ids, and the alive topics after the change.
+// cache of the old ones are removed and the new ones are rebuilt from database.
+#[derive(Debug, Clone)]
+pub struct TopicMoveReport {
+    pub removed: Vec<(Topic, Vec<u32>)>,
+    pub topics: Vec<Topic>,
 }
 
 impl SelfIdString for Topic {
@@ -147,10 +234,19 @@ pub struct TopicWithPost<'a> {
     pub posts: Vec<PostWithUser<'a>>,
 }
 
+#[derive(Serialize)]
+pub struct TopicWithThread<'a> {
+    pub topic: Option<TopicWithUser<'a>>,
+    pub posts: Vec<PostThread<'a>>,
+}
+
 #[derive(Deserialize, Debug)]
 pub enum QueryType {
     Oldest,
     Popular,
+    // nested reply tree of topic. page is applied to the top level posts.
+    // when post_id is given return the subtree under that post and page is applied to its replies.
+    Thread,
 }
 
 #[derive(Deserialize, Debug)]
@@ -158,4 +254,6 @@ pub struct TopicQuery {
     pub topic_id: u32,
     pub page: usize,
     pub query_type: QueryType,
+    pub post_id: Option<u32>,
+    pub depth: Option<u32>,
 }
@@ -14,14 +14,22 @@ pub struct User {
     pub avatar_url: String,
     pub signature: String,
     pub created_at: NaiveDateTime,
-    // privilege level : 0 is blocked, 1 is not active, 2 is normal user, 3 and above is admin level.
+    // privilege is the account state: 0 is blocked, 1 is not active, 2 is active.
+    // admin permissions are granted through roles. see crate::model::permission.
     pub privilege: u32,
     pub show_email: bool,
+    // preferred locale of mails sent to user.
+    #[serde(default = "default_locale")]
+    pub locale: String,
     // online_status and last_online are stored in redis only. return None when querying database.
     pub online_status: Option<u32>,
     pub last_online: Option<NaiveDateTime>,
 }
 
+fn default_locale() -> String {
+    crate::handler::mail_template::DEFAULT_LOCALE.to_owned()
+}
+
 //user ref is attached to post and topic after privacy filter.
 #[derive(Serialize)]
 pub struct UserRef<'a> {
@@ -62,6 +70,7 @@ impl Default for User {
             created_at: NaiveDateTime::from_timestamp(0, 0),
             privilege: 0,
             show_email: false,
+            locale: default_locale(),
             online_status: None,
             last_online: None,
         }
@@ -149,9 +158,26 @@ impl AuthRequest {
 #[derive(Serialize)]
 pub struct AuthResponse {
     pub token: String,
+    pub refresh_token: String,
     pub user: User,
 }
 
+// handle incoming json request for token refresh and logout.
+// pass all = true when logout to revoke tokens on every device.
+#[derive(Deserialize)]
+pub struct RefreshRequest {
+    pub refresh_token: String,
+    pub all: Option<bool>,
+}
+
+// handle incoming json request for password reset.
+// email is used when requesting the reset mail and password is used when confirming the reset.
+#[derive(Deserialize)]
+pub struct ResetRequest {
+    pub email: Option<String>,
+    pub password: Option<String>,
+}
+
 // handle incoming json request for user data update
 #[derive(Deserialize, Debug)]
 pub struct UpdateRequest {
@@ -161,6 +187,7 @@ pub struct UpdateRequest {
     pub signature: Option<String>,
     pub privilege: Option<u32>,
     pub show_email: Option<bool>,
+    pub locale: Option<String>,
 }
 
 impl UpdateRequest {
@@ -176,6 +203,7 @@ impl UpdateRequest {
                 self.avatar_url = None;
                 self.signature = None;
                 self.show_email = None;
+                self.locale = None;
                 self
             }
         }
@@ -189,6 +217,21 @@ impl UpdateRequest {
             signature: None,
             privilege: Some(2),
             show_email: None,
+            locale: None,
+        }
+    }
+
+    // locale is a language tag like en or zh-CN.
+    pub fn check_locale(self) -> Result<Self, ResError> {
+        match self.locale.as_ref() {
+            Some(l)
+                if l.len() < 2
+                    || l.len() > 8
+                    || !l.chars().all(|c| c.is_ascii_alphabetic() || c == '-') =>
+            {
+                Err(ResError::BadRequestExplained("Invalid locale".into()))
+            }
+            _ => Ok(self),
         }
     }
 }
@@ -209,6 +252,22 @@ impl Validator for AuthRequest {
     }
 }
 
+impl Validator for ResetRequest {
+    fn get_username(&self) -> &str {
+        ""
+    }
+    fn get_password(&self) -> &str {
+        self.password.as_deref().unwrap_or("")
+    }
+    fn get_email(&self) -> &str {
+        self.email.as_deref().unwrap_or("")
+    }
+
+    fn check_self_id(&self) -> Result<(), ResError> {
+        Ok(())
+    }
+}
+
 impl Validator for UpdateRequest {
     // ToDo: handle update validation separately.
     fn get_username(&self) -> &str {
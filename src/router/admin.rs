@@ -4,23 +4,31 @@ use actix_web::{
 };
 
 use crate::handler::{
-    auth::UserJwt, cache::MyRedisPool, cache_update::CacheServiceAddr, data::DataRc,
-    db::MyPostgresPool,
+    cache::MyRedisPool, cache_update::CacheServiceAddr, data::DataRc, db::MyPostgresPool,
+    permission::UserPerm,
 };
 use crate::model::{
-    category::CategoryRequest, common::Validator, post::PostRequest, topic::TopicRequest,
+    category::{CategoryRemoveRequest, CategoryRequest},
+    common::Validator,
+    errors::ResError,
+    permission::{Permission, RoleRequest, Scope},
+    post::PostRequest,
+    tag::TopicTagRequest,
+    topic::{
+        TopicMergeRequest, TopicMoveReport, TopicMoveRequest, TopicRequest, TopicSplitRequest,
+    },
     user::UpdateRequest,
 };
 
 pub async fn add_category(
     db_pool: DataRc<MyPostgresPool>,
     cache_pool: DataRc<MyRedisPool>,
-    jwt: UserJwt,
+    perm: UserPerm,
     req: Json<CategoryRequest>,
     addr: DataRc<CacheServiceAddr>,
 ) -> Result<HttpResponse, Error> {
     let req = req.into_inner().check_new()?;
-    let c = db_pool.admin_add_category(jwt.privilege, req).await?;
+    let c = db_pool.admin_add_category(&perm, req).await?;
 
     let res = HttpResponse::Ok().json(&c);
 
@@ -36,11 +44,11 @@ pub async fn add_category(
 pub async fn update_category(
     db_pool: DataRc<MyPostgresPool>,
     cache_pool: DataRc<MyRedisPool>,
-    jwt: UserJwt,
+    perm: UserPerm,
     req: Json<CategoryRequest>,
 ) -> Result<HttpResponse, Error> {
     let req = req.into_inner().check_update()?;
-    let c = db_pool.admin_update_category(jwt.privilege, req).await?;
+    let c = db_pool.admin_update_category(&perm, req).await?;
 
     let res = HttpResponse::Ok().json(&c);
     let _ = cache_pool.update_categories(&c).await;
@@ -50,31 +58,48 @@ pub async fn update_category(
 
 pub async fn remove_category(
     db_pool: DataRc<MyPostgresPool>,
-    // cache_pool: DataRc<MyRedisPool>,
-    jwt: UserJwt,
+    cache_pool: DataRc<MyRedisPool>,
+    perm: UserPerm,
     id: Path<u32>,
+    req: Json<CategoryRemoveRequest>,
+    addr: DataRc<CacheServiceAddr>,
 ) -> Result<HttpResponse, Error> {
     let id = id.into_inner();
+    let req = req.into_inner().check_remove(id)?;
+
+    let r = db_pool.admin_remove_category(&perm, id, req).await?;
+
+    let res = HttpResponse::Ok().json(&r);
 
-    db_pool.admin_remove_category(id, jwt.privilege).await?;
-    //ToDo: fix remove category cache
-    //    let _ = cache_pool.remove_category(id).await?;
+    actix_rt::spawn(async move {
+        cache_pool
+            .remove_category_send_fail(r, addr.get_ref().clone())
+            .await
+    });
 
-    Ok(HttpResponse::Ok().finish())
+    Ok(res)
 }
 
 pub async fn update_user(
     db_pool: DataRc<MyPostgresPool>,
     cache_pool: DataRc<MyRedisPool>,
-    jwt: UserJwt,
+    perm: UserPerm,
     req: Json<UpdateRequest>,
     addr: DataRc<CacheServiceAddr>,
 ) -> Result<HttpResponse, Error> {
     let req = req.into_inner().attach_id(None).check_update()?;
 
-    let req = db_pool.update_user_check(jwt.privilege, req).await?;
+    let req = db_pool.update_user_check(&perm, req).await?;
+    let privilege_changed = req.privilege.is_some();
     let u = db_pool.update_user(req).await?;
 
+    // revoke all tokens of target user so the new privilege takes effect immediately.
+    if privilege_changed {
+        if let Some(u) = u.first() {
+            cache_pool.revoke_tokens(u.id).await?;
+        }
+    }
+
     let res = HttpResponse::Ok().json(&u);
 
     crate::router::user::update_user_send_fail(cache_pool, u, addr);
@@ -85,17 +110,129 @@ pub async fn update_user(
 pub async fn update_topic(
     db_pool: DataRc<MyPostgresPool>,
     cache_pool: DataRc<MyRedisPool>,
-    jwt: UserJwt,
+    perm: UserPerm,
     req: Json<TopicRequest>,
     addr: DataRc<CacheServiceAddr>,
 ) -> Result<HttpResponse, Error> {
     let req = req.into_inner().add_user_id(None).check_update()?;
 
-    let t = db_pool.admin_update_topic(jwt.privilege, &req).await?;
+    let (t, pin, visible) = db_pool.admin_update_topic(&perm, &req).await?;
 
     let res = HttpResponse::Ok().json(&t);
 
-    crate::router::topic::update_topic_send_fail(cache_pool, t, addr);
+    if let Some(pin) = pin {
+        let cache_pool = cache_pool.clone();
+        let addr = addr.get_ref().clone();
+        actix_rt::spawn(async move { cache_pool.update_topic_pin_send_fail(pin, addr).await });
+    }
+
+    // hidden topic is removed from cache lists like a deleted one and it's added back when it's visible again.
+    match visible {
+        Some(false) => actix_rt::spawn(async move {
+            cache_pool
+                .remove_topic_send_fail(db_pool.get_ref(), t, addr.get_ref().clone())
+                .await
+        }),
+        Some(true) => actix_rt::spawn(async move {
+            let addr = addr.get_ref().clone();
+            cache_pool
+                .update_topic_send_fail(t.clone(), addr.clone())
+                .await;
+            cache_pool
+                .restore_topic_send_fail(db_pool.get_ref(), t, addr)
+                .await
+        }),
+        None => {
+            if !req.is_pin_only() {
+                crate::router::topic::update_topic_send_fail(cache_pool, t, addr);
+            }
+        }
+    }
+
+    Ok(res)
+}
+
+pub async fn move_topic(
+    db_pool: DataRc<MyPostgresPool>,
+    cache_pool: DataRc<MyRedisPool>,
+    perm: UserPerm,
+    req: Json<TopicMoveRequest>,
+    addr: DataRc<CacheServiceAddr>,
+) -> Result<HttpResponse, Error> {
+    let r = db_pool.admin_move_topic(&perm, req.into_inner()).await?;
+
+    let res = HttpResponse::Ok().json(&r.topics);
+
+    rebuild_moved_topics_send_fail(db_pool, cache_pool, r, addr);
+
+    Ok(res)
+}
+
+pub async fn merge_topic(
+    db_pool: DataRc<MyPostgresPool>,
+    cache_pool: DataRc<MyRedisPool>,
+    perm: UserPerm,
+    req: Json<TopicMergeRequest>,
+    addr: DataRc<CacheServiceAddr>,
+) -> Result<HttpResponse, Error> {
+    let req = req.into_inner().check()?;
+    let r = db_pool.admin_merge_topic(&perm, req).await?;
+
+    let res = HttpResponse::Ok().json(&r.topics);
+
+    rebuild_moved_topics_send_fail(db_pool, cache_pool, r, addr);
+
+    Ok(res)
+}
+
+pub async fn split_topic(
+    db_pool: DataRc<MyPostgresPool>,
+    cache_pool: DataRc<MyRedisPool>,
+    perm: UserPerm,
+    req: Json<TopicSplitRequest>,
+    addr: DataRc<CacheServiceAddr>,
+) -> Result<HttpResponse, Error> {
+    let req = req.into_inner().check()?;
+    let r = db_pool.admin_split_topic(&perm, req).await?;
+
+    let res = HttpResponse::Ok().json(&r.topics);
+
+    rebuild_moved_topics_send_fail(db_pool, cache_pool, r, addr);
+
+    Ok(res)
+}
+
+fn rebuild_moved_topics_send_fail(
+    db_pool: DataRc<MyPostgresPool>,
+    cache_pool: DataRc<MyRedisPool>,
+    r: TopicMoveReport,
+    addr: DataRc<CacheServiceAddr>,
+) {
+    actix_rt::spawn(async move {
+        cache_pool
+            .rebuild_moved_topics_send_fail(db_pool.get_ref(), r, addr.get_ref().clone())
+            .await
+    });
+}
+
+pub async fn set_topic_tags(
+    db_pool: DataRc<MyPostgresPool>,
+    cache_pool: DataRc<MyRedisPool>,
+    perm: UserPerm,
+    req: Json<TopicTagRequest>,
+    addr: DataRc<CacheServiceAddr>,
+) -> Result<HttpResponse, Error> {
+    let req = req.into_inner().check()?;
+
+    let (old, t) = db_pool.admin_set_topic_tags(&perm, req).await?;
+
+    let res = HttpResponse::Ok().json(&t);
+
+    actix_rt::spawn(async move {
+        cache_pool
+            .update_topic_tags_send_fail(old, t, addr.get_ref().clone())
+            .await
+    });
 
     Ok(res)
 }
@@ -103,13 +240,13 @@ pub async fn update_topic(
 pub async fn update_post(
     db_pool: DataRc<MyPostgresPool>,
     cache_pool: DataRc<MyRedisPool>,
-    jwt: UserJwt,
+    perm: UserPerm,
     req: Json<PostRequest>,
     addr: DataRc<CacheServiceAddr>,
 ) -> Result<HttpResponse, Error> {
     let req = req.into_inner().attach_user_id(None).check_update()?;
 
-    let p = db_pool.admin_update_post(jwt.privilege, req).await?;
+    let p = db_pool.admin_update_post(&perm, req).await?;
 
     let res = HttpResponse::Ok().json(&p);
 
@@ -117,3 +254,103 @@ pub async fn update_post(
 
     Ok(res)
 }
+
+pub async fn restore_topic(
+    db_pool: DataRc<MyPostgresPool>,
+    cache_pool: DataRc<MyRedisPool>,
+    perm: UserPerm,
+    id: Path<u32>,
+    addr: DataRc<CacheServiceAddr>,
+) -> Result<HttpResponse, Error> {
+    let t = db_pool.admin_restore_topic(&perm, id.into_inner()).await?;
+
+    if t.is_empty() {
+        return Err(ResError::NotFound.into());
+    }
+
+    let res = HttpResponse::Ok().json(&t);
+
+    crate::router::topic::restore_topic_send_fail(db_pool, cache_pool, t, addr);
+
+    Ok(res)
+}
+
+pub async fn restore_post(
+    db_pool: DataRc<MyPostgresPool>,
+    cache_pool: DataRc<MyRedisPool>,
+    perm: UserPerm,
+    id: Path<u32>,
+    addr: DataRc<CacheServiceAddr>,
+) -> Result<HttpResponse, Error> {
+    let p = db_pool.admin_restore_post(&perm, id.into_inner()).await?;
+
+    if p.is_empty() {
+        return Err(ResError::NotFound.into());
+    }
+
+    let res = HttpResponse::Ok().json(&p);
+
+    crate::router::post::restore_post_send_fail(db_pool, cache_pool, p, addr);
+
+    Ok(res)
+}
+
+pub async fn rollback_topic(
+    db_pool: DataRc<MyPostgresPool>,
+    cache_pool: DataRc<MyRedisPool>,
+    perm: UserPerm,
+    id: Path<i32>,
+    addr: DataRc<CacheServiceAddr>,
+) -> Result<HttpResponse, Error> {
+    let t = db_pool.admin_rollback_topic(&perm, id.into_inner()).await?;
+
+    let res = HttpResponse::Ok().json(&t);
+
+    crate::router::topic::update_topic_send_fail(cache_pool, t, addr);
+
+    Ok(res)
+}
+
+pub async fn rollback_post(
+    db_pool: DataRc<MyPostgresPool>,
+    cache_pool: DataRc<MyRedisPool>,
+    perm: UserPerm,
+    id: Path<i32>,
+    addr: DataRc<CacheServiceAddr>,
+) -> Result<HttpResponse, Error> {
+    let p = db_pool.admin_rollback_post(&perm, id.into_inner()).await?;
+
+    let res = HttpResponse::Ok().json(&p);
+
+    crate::router::post::update_post_send_fail(cache_pool, p, addr);
+
+    Ok(res)
+}
+
+pub async fn grant_role(
+    db_pool: DataRc<MyPostgresPool>,
+    perm: UserPerm,
+    req: Json<RoleRequest>,
+) -> Result<HttpResponse, Error> {
+    let r = db_pool.admin_grant_role(&perm, req.into_inner()).await?;
+    Ok(HttpResponse::Ok().json(&r))
+}
+
+pub async fn revoke_role(
+    db_pool: DataRc<MyPostgresPool>,
+    perm: UserPerm,
+    req: Json<RoleRequest>,
+) -> Result<HttpResponse, Error> {
+    let r = db_pool.admin_revoke_role(&perm, req.into_inner()).await?;
+    Ok(HttpResponse::Ok().json(&r))
+}
+
+pub async fn user_roles(
+    db_pool: DataRc<MyPostgresPool>,
+    perm: UserPerm,
+    id: Path<u32>,
+) -> Result<HttpResponse, Error> {
+    perm.require(Permission::UserManage, Scope::Global).await?;
+    let r = db_pool.get_user_roles(id.into_inner()).await?;
+    Ok(HttpResponse::Ok().json(&r))
+}
@@ -10,18 +10,56 @@ use crate::handler::{
 use crate::model::{
     common::Validator,
     errors::ResError,
-    user::{AuthRequest, UpdateRequest},
+    user::{AuthRequest, RefreshRequest, ResetRequest, UpdateRequest},
 };
 
 pub async fn login(
     db_pool: DataRc<MyPostgresPool>,
+    cache_pool: DataRc<MyRedisPool>,
     req: Json<AuthRequest>,
 ) -> Result<HttpResponse, Error> {
     let r = req.into_inner().check_login()?;
-    let r = db_pool.login(r).await?;
+    let u = db_pool.login(r).await?;
+    let r = cache_pool.generate_auth_response(u).await?;
     Ok(HttpResponse::Ok().json(&r))
 }
 
+// exchange a refresh token for a new access token and a new refresh token.
+pub async fn refresh(
+    db_pool: DataRc<MyPostgresPool>,
+    cache_pool: DataRc<MyRedisPool>,
+    req: Json<RefreshRequest>,
+) -> Result<HttpResponse, Error> {
+    let uid = cache_pool
+        .take_refresh_token(req.refresh_token.as_str())
+        .await?;
+
+    // privilege in new token always come from database as the cached user could be outdated.
+    let u = db_pool
+        .get_users(&[uid])
+        .await?
+        .pop()
+        .ok_or(ResError::Unauthorized)?;
+
+    let r = cache_pool.generate_auth_response(u).await?;
+    Ok(HttpResponse::Ok().json(&r))
+}
+
+pub async fn logout(
+    cache_pool: DataRc<MyRedisPool>,
+    req: Json<RefreshRequest>,
+) -> Result<HttpResponse, Error> {
+    let uid = cache_pool
+        .take_refresh_token(req.refresh_token.as_str())
+        .await?;
+
+    if req.all.unwrap_or(false) {
+        cache_pool.revoke_tokens(uid).await?;
+    }
+
+    Ok(HttpResponse::Ok().finish())
+}
+
 pub async fn register(
     db_pool: DataRc<MyPostgresPool>,
     cache_pool: DataRc<MyRedisPool>,
@@ -34,7 +72,11 @@ pub async fn register(
 
     let res = HttpResponse::Ok().json(&u);
 
-    // pool_redis().add_activation_mail(u.clone()).await;
+    // registration is still successful when failed to enqueue the mail. user can ask for a resend.
+    if let Some(u) = u.first() {
+        let _ = cache_pool.add_activation_mail(u).await;
+    }
+
     crate::router::user::update_user_send_fail(cache_pool, u, addr);
 
     Ok(res)
@@ -48,13 +90,16 @@ pub async fn activate_by_mail(
 ) -> Result<HttpResponse, Error> {
     let uuid = req.into_inner();
 
-    let uid = cache_pool.get_uid_from_uuid(uuid.as_str()).await?;
+    let uid = cache_pool
+        .take_uid_from_activation_uuid(uuid.as_str())
+        .await?;
 
     let u = db_pool.update_user(UpdateRequest::make_active(uid)).await?;
 
-    let res = HttpResponse::Ok().json(&u);
+    // privilege changed so tokens issued before activation have to be refreshed.
+    cache_pool.revoke_tokens(uid).await?;
 
-    // cache_pool.remove_activation_uuid(uuid.as_str()).await;
+    let res = HttpResponse::Ok().json(&u);
 
     crate::router::user::update_user_send_fail(cache_pool, u, addr);
 
@@ -77,6 +122,76 @@ pub async fn add_activation_mail(
         }
     };
 
-    // let _ = cache_pool.add_activation_mail(u);
+    let u = u.first().ok_or(ResError::NotFound)?;
+
+    cache_pool.resend_activation_mail(u).await?;
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+pub async fn request_password_reset(
+    db_pool: DataRc<MyPostgresPool>,
+    cache_pool: DataRc<MyRedisPool>,
+    req: Json<ResetRequest>,
+) -> Result<HttpResponse, Error> {
+    req.check_email()?;
+
+    // always return Ok so this endpoint can't be used to find out registered emails.
+    if let Ok(u) = db_pool.get_user_by_email(req.get_email()).await {
+        cache_pool.add_password_reset_mail(&u).await?;
+    }
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+// the page linked in password reset mail. it posts the new password back to the same path.
+const RESET_PAGE: &str = r#"<!DOCTYPE html>
+<html>
+<head><meta charset="utf-8"><title>Reset password</title></head>
+<body>
+<form id="reset">
+<input type="password" name="password" placeholder="New password" required>
+<button type="submit">Reset</button>
+</form>
+<p id="result"></p>
+<script>
+document.getElementById("reset").addEventListener("submit", function (e) {
+    e.preventDefault();
+    fetch(location.pathname, {
+        method: "POST",
+        headers: { "Content-Type": "application/json" },
+        body: JSON.stringify({ password: e.target.password.value })
+    }).then(function (r) {
+        document.getElementById("result").textContent = r.ok ? "Password is reset" : "Reset failed";
+    });
+});
+</script>
+</body>
+</html>"#;
+
+pub async fn password_reset_page() -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .body(RESET_PAGE)
+}
+
+pub async fn confirm_password_reset(
+    db_pool: DataRc<MyPostgresPool>,
+    cache_pool: DataRc<MyRedisPool>,
+    uuid: Path<String>,
+    req: Json<ResetRequest>,
+    addr: DataRc<CacheServiceAddr>,
+) -> Result<HttpResponse, Error> {
+    req.check_password()?;
+
+    let uid = cache_pool.take_uid_from_reset_uuid(uuid.as_str()).await?;
+
+    let u = db_pool.update_password(uid, req.get_password()).await?;
+
+    // sessions started with the old password are all revoked.
+    cache_pool.revoke_tokens(uid).await?;
+
+    crate::router::user::update_user_send_fail(cache_pool, u, addr);
+
     Ok(HttpResponse::Ok().finish())
 }
@@ -1,51 +1,171 @@
-use actix_web::{web::Query, Error, HttpResponse};
+use actix_web::{
+    web::{Path, Query},
+    Error, HttpResponse,
+};
 
-use crate::handler::{cache::MyRedisPool, data::DataRc, db::MyPostgresPool};
+use crate::handler::{auth::UserJwt, cache::MyRedisPool, data::DataRc, db::MyPostgresPool};
 use crate::model::{
-    category::{CategoryQuery, QueryType},
+    category::{Category, CategoryQuery, QueryType, Visibility},
     errors::ResError,
+    read::TopicWithUnread,
     topic::Topic,
+    user::AttachUser,
 };
 
 pub async fn query_handler(
     db_pool: DataRc<MyPostgresPool>,
     cache_pool: DataRc<MyRedisPool>,
+    jwt: Option<UserJwt>,
     req: Query<CategoryQuery>,
 ) -> Result<HttpResponse, Error> {
+    let (c, hidden) = categories_with_hidden(&db_pool, &cache_pool, jwt.as_ref()).await?;
+    let uid = jwt.as_ref().map(|j| j.user_id);
+
     match req.query_type {
         QueryType::Popular => {
+            let cid = req.category_id.unwrap_or(1);
+            check_hidden(&hidden, cid)?;
+
+            let result = cache_pool.get_topics_pop(cid, req.page.unwrap_or(1)).await;
+
+            if_query_db(db_pool, cache_pool, &hidden, uid, result).await
+        }
+        QueryType::PopularAll => {
             let result = cache_pool
-                .get_topics_pop(req.category_id.unwrap_or(1), req.page.unwrap_or(1))
+                .get_topics_pop_all(req.page.unwrap_or(1), &hidden)
                 .await;
 
-            if_query_db(db_pool, cache_pool, result).await
+            if_query_db(db_pool, cache_pool, &hidden, uid, result).await
         }
-        QueryType::PopularAll => {
-            let result = cache_pool.get_topics_pop_all(req.page.unwrap_or(1)).await;
+        QueryType::Hot => {
+            let cid = req.category_id.unwrap_or(1);
+            check_hidden(&hidden, cid)?;
+
+            let result = cache_pool.get_topics_hot(cid, req.page.unwrap_or(1)).await;
+
+            if_query_db(db_pool, cache_pool, &hidden, uid, result).await
+        }
+        QueryType::HotAll => {
+            let result = cache_pool
+                .get_topics_hot_all(req.page.unwrap_or(1), &hidden)
+                .await;
 
-            if_query_db(db_pool, cache_pool, result).await
+            if_query_db(db_pool, cache_pool, &hidden, uid, result).await
         }
         QueryType::Latest => {
+            let cid = req.category_id.unwrap_or(1);
+            check_hidden(&hidden, cid)?;
+
+            let result = cache_pool.get_topics_late(cid, req.page.unwrap_or(1)).await;
+
+            if_query_db(db_pool, cache_pool, &hidden, uid, result).await
+        }
+        QueryType::TagLatest => {
+            let tag = req.tag.as_ref().ok_or(ResError::BadRequest)?.to_lowercase();
             let result = cache_pool
-                .get_topics_late(req.category_id.unwrap_or(1), req.page.unwrap_or(1))
+                .get_topics_by_tag_late(tag.as_str(), req.page.unwrap_or(1), &hidden)
                 .await;
 
-            if_query_db(db_pool, cache_pool, result).await
+            if_query_db(db_pool, cache_pool, &hidden, uid, result).await
+        }
+        QueryType::TagPopular => {
+            let tag = req.tag.as_ref().ok_or(ResError::BadRequest)?.to_lowercase();
+            let result = cache_pool
+                .get_topics_by_tag_pop(tag.as_str(), req.page.unwrap_or(1), &hidden)
+                .await;
+
+            if_query_db(db_pool, cache_pool, &hidden, uid, result).await
+        }
+        QueryType::All => {
+            let c = c
+                .iter()
+                .filter(|c| !hidden.contains(&c.id))
+                .collect::<Vec<&Category>>();
+            Ok(HttpResponse::Ok().json(&Category::build_tree(&c)))
         }
-        QueryType::All => match cache_pool.get_categories_all().await {
-            Ok(c) => Ok(HttpResponse::Ok().json(&c)),
-            Err(_) => {
-                let c = db_pool.get_categories_all().await?;
-                cache_pool.update_categories(&c).await?;
-                Ok(HttpResponse::Ok().json(&c))
-            }
-        },
+    }
+}
+
+// mark all topics of category and its visible subcategories read.
+pub async fn mark_read(
+    db_pool: DataRc<MyPostgresPool>,
+    cache_pool: DataRc<MyRedisPool>,
+    jwt: UserJwt,
+    id: Path<u32>,
+) -> Result<HttpResponse, Error> {
+    let cid = id.into_inner();
+
+    let (c, hidden) = categories_with_hidden(&db_pool, &cache_pool, Some(&jwt)).await?;
+    check_hidden(&hidden, cid)?;
+
+    if !c.iter().any(|c| c.id == cid) {
+        return Err(ResError::NotFound.into());
+    }
+
+    let cids = c
+        .iter()
+        .filter(|cat| {
+            !hidden.contains(&cat.id) && (cat.id == cid || cat.ancestors(&c).contains(&cid))
+        })
+        .map(|cat| cat.id)
+        .collect::<Vec<u32>>();
+
+    cache_pool.mark_categories_read(jwt.user_id, &cids).await?;
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+// all categories and the ids of the ones caller can't see.
+// subcategories of a hidden category are hidden too.
+// caller's roles are only queried when there is any non public category.
+pub(crate) async fn categories_with_hidden(
+    db_pool: &MyPostgresPool,
+    cache_pool: &MyRedisPool,
+    jwt: Option<&UserJwt>,
+) -> Result<(Vec<Category>, Vec<u32>), ResError> {
+    let c = match cache_pool.get_categories_all().await {
+        Ok(c) => c,
+        Err(_) => {
+            let c = db_pool.get_categories_all().await?;
+            cache_pool.update_categories(&c).await?;
+            c
+        }
+    };
+
+    if c.iter().all(|c| c.visibility == Visibility::Public) {
+        return Ok((c, vec![]));
+    }
+
+    let viewer = db_pool.get_viewer(jwt).await?;
+    let hidden = c
+        .iter()
+        .filter(|cat| !viewer.can_view(cat))
+        .map(|cat| cat.id)
+        .collect::<Vec<u32>>();
+    let hidden = c
+        .iter()
+        .filter(|cat| {
+            hidden.contains(&cat.id) || cat.ancestors(&c).iter().any(|id| hidden.contains(id))
+        })
+        .map(|cat| cat.id)
+        .collect();
+
+    Ok((c, hidden))
+}
+
+pub(crate) fn check_hidden(hidden: &[u32], cid: u32) -> Result<(), ResError> {
+    if hidden.contains(&cid) {
+        Err(ResError::Unauthorized)
+    } else {
+        Ok(())
     }
 }
 
 async fn if_query_db(
     db_pool: DataRc<MyPostgresPool>,
     cache_pool: DataRc<MyRedisPool>,
+    hidden: &[u32],
+    uid: Option<u32>,
     result: Result<(Vec<Topic>, Vec<u32>), ResError>,
 ) -> Result<HttpResponse, Error> {
     let mut should_update_t = false;
@@ -75,7 +195,25 @@ async fn if_query_db(
         }
     };
 
-    let res = HttpResponse::Ok().json(&Topic::attach_users(&t, &u));
+    // unread state is optional so the list is still returned when it's failed.
+    let unread = match uid {
+        Some(uid) => cache_pool.get_unread(uid, &t).await.unwrap_or_default(),
+        None => vec![],
+    };
+
+    // topics of hidden categories are already left out of category:all and tag lists when paging.
+    // pinned topics are filtered here. invisible topics are removed from lists when they are hidden
+    // and the check covers the ones not removed yet.
+    let res = HttpResponse::Ok().json(
+        &t.iter()
+            .enumerate()
+            .filter(|(_, t)| t.is_visible && !hidden.contains(&t.category_id))
+            .map(|(i, t)| TopicWithUnread {
+                topic: t.attach_user(&u),
+                unread: unread.get(i),
+            })
+            .collect::<Vec<_>>(),
+    );
 
     actix_rt::spawn(async move {
         if should_update_u {
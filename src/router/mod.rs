@@ -3,6 +3,8 @@ pub mod auth;
 pub mod category;
 pub mod post;
 pub mod psn;
+pub mod reaction;
+pub mod search;
 pub mod stream;
 pub mod talk;
 pub mod test;
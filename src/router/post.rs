@@ -1,16 +1,19 @@
 use actix_web::{
-    web::{Json, Path},
+    web::{Json, Path, Query},
     Error, HttpResponse,
 };
 
 use crate::handler::{
     auth::UserJwt, cache::MyRedisPool, cache_update::CacheServiceAddr, data::DataRc,
-    db::MyPostgresPool,
+    db::MyPostgresPool, permission::UserPerm,
 };
 use crate::model::{
     errors::ResError,
+    permission::{Permission, Scope},
     post::{Post, PostRequest},
+    revision::{PostRevisionDiff, RevisionDiffQuery},
 };
+use crate::router::category::{categories_with_hidden, check_hidden};
 
 pub async fn add(
     db_pool: DataRc<MyPostgresPool>,
@@ -26,6 +29,9 @@ pub async fn add(
         .attach_user_id(Some(jwt.user_id))
         .check_new()?;
 
+    let (_, hidden) = categories_with_hidden(&db_pool, &cache_pool, Some(&jwt)).await?;
+    check_hidden(&hidden, req.category_id)?;
+
     let p = db_pool.add_post(req).await?;
 
     let res = HttpResponse::Ok().json(&p);
@@ -51,7 +57,7 @@ pub async fn update(
         .attach_user_id(Some(jwt.user_id))
         .check_update()?;
 
-    let p = db_pool.update_post(req).await?;
+    let p = db_pool.update_post(req, jwt.user_id).await?;
 
     let res = HttpResponse::Ok().json(&p);
 
@@ -60,6 +66,58 @@ pub async fn update(
     Ok(res)
 }
 
+pub async fn delete(
+    db_pool: DataRc<MyPostgresPool>,
+    cache_pool: DataRc<MyRedisPool>,
+    perm: UserPerm,
+    id: Path<u32>,
+    addr: DataRc<CacheServiceAddr>,
+) -> Result<HttpResponse, Error> {
+    perm.jwt.check_privilege()?;
+
+    let id = id.into_inner();
+
+    // users without post.delete_any permission could only delete their own posts.
+    let uid = if perm
+        .resolve(Scope::Post(id))
+        .await?
+        .has(Permission::PostDeleteAny)
+    {
+        None
+    } else {
+        Some(perm.jwt.user_id)
+    };
+
+    let p = db_pool.delete_post(id, perm.jwt.user_id, uid).await?;
+
+    if p.is_empty() {
+        return Err(ResError::NotFound.into());
+    }
+
+    let res = HttpResponse::Ok().json(&p);
+
+    actix_rt::spawn(async move {
+        cache_pool
+            .remove_post_send_fail(p, addr.get_ref().clone())
+            .await
+    });
+
+    Ok(res)
+}
+
+pub(crate) fn restore_post_send_fail(
+    db_pool: DataRc<MyPostgresPool>,
+    cache_pool: DataRc<MyRedisPool>,
+    p: Vec<Post>,
+    addr: DataRc<CacheServiceAddr>,
+) {
+    actix_rt::spawn(async move {
+        cache_pool
+            .restore_post_send_fail(db_pool.get_ref(), p, addr.get_ref().clone())
+            .await
+    });
+}
+
 pub(crate) fn update_post_send_fail(
     cache_pool: DataRc<MyRedisPool>,
     p: Vec<Post>,
@@ -75,6 +133,7 @@ pub(crate) fn update_post_send_fail(
 pub async fn get(
     db_pool: DataRc<MyPostgresPool>,
     cache_pool: DataRc<MyRedisPool>,
+    jwt: Option<UserJwt>,
     id: Path<u32>,
 ) -> Result<HttpResponse, Error> {
     let id = id.into_inner();
@@ -94,6 +153,11 @@ pub async fn get(
         }
     };
 
+    if let Some(p) = p.first() {
+        let (_, hidden) = categories_with_hidden(&db_pool, &cache_pool, jwt.as_ref()).await?;
+        check_hidden(&hidden, p.category_id)?;
+    }
+
     let u = match cache_pool.get_users(uids).await {
         Ok(u) => u,
         Err(e) => {
@@ -119,3 +183,77 @@ pub async fn get(
 
     Ok(res)
 }
+
+// revisions of post in hidden category are not accessible. revisions of deleted post or post of
+// deleted or invisible topic are only accessible with post.edit_any permission.
+async fn check_revision_access(
+    db_pool: &DataRc<MyPostgresPool>,
+    cache_pool: &DataRc<MyRedisPool>,
+    perm: Option<&UserPerm>,
+    pid: u32,
+) -> Result<(), ResError> {
+    let (cid, is_alive) = db_pool.get_post_revision_target(pid).await?;
+
+    let (_, hidden) = categories_with_hidden(db_pool, cache_pool, perm.map(|p| &p.jwt)).await?;
+    check_hidden(&hidden, cid)?;
+
+    if !is_alive {
+        perm.ok_or(ResError::NotFound)?
+            .require(Permission::PostEditAny, Scope::Post(pid))
+            .await
+            .map_err(|_| ResError::NotFound)?;
+    }
+
+    Ok(())
+}
+
+pub async fn revisions(
+    db_pool: DataRc<MyPostgresPool>,
+    cache_pool: DataRc<MyRedisPool>,
+    perm: Option<UserPerm>,
+    id: Path<u32>,
+) -> Result<HttpResponse, Error> {
+    let pid = id.into_inner();
+    check_revision_access(&db_pool, &cache_pool, perm.as_ref(), pid).await?;
+
+    let r = db_pool.get_post_revisions(pid).await?;
+    Ok(HttpResponse::Ok().json(&r))
+}
+
+pub async fn revision_diff(
+    db_pool: DataRc<MyPostgresPool>,
+    cache_pool: DataRc<MyRedisPool>,
+    perm: Option<UserPerm>,
+    id: Path<u32>,
+    req: Query<RevisionDiffQuery>,
+) -> Result<HttpResponse, Error> {
+    let pid = id.into_inner();
+    check_revision_access(&db_pool, &cache_pool, perm.as_ref(), pid).await?;
+
+    let ids = match req.to {
+        Some(to) => vec![req.from, to],
+        None => vec![req.from],
+    };
+    let r = db_pool.get_post_revisions_by_id(&ids).await?;
+
+    // diff to current content when there is no target revision.
+    let (p, _) = match req.to {
+        Some(_) => (vec![], vec![]),
+        None => db_pool.get_posts(&[pid]).await?,
+    };
+
+    let find = |id: i32| {
+        r.iter()
+            .find(|r| r.id == id && r.post_id == pid)
+            .map(|r| r.post_content.as_str())
+    };
+
+    let from = find(req.from).ok_or(ResError::NotFound)?;
+    let to = match req.to {
+        Some(to) => find(to),
+        None => p.first().map(|p| p.post_content.as_str()),
+    }
+    .ok_or(ResError::NotFound)?;
+
+    Ok(HttpResponse::Ok().json(&PostRevisionDiff::new(from, to)))
+}
@@ -1,12 +1,13 @@
 use actix_web::{web::Query, Error, HttpResponse};
 
 use crate::handler::{
-    auth::UserJwt,
     cache::MyRedisPool,
     data::DataRc,
     db::MyPostgresPool,
+    permission::UserPerm,
     psn::{PSNRequest, PSNServiceAddr},
 };
+use crate::model::permission::{Permission, Scope};
 
 pub async fn query_handler(
     db_pool: DataRc<MyPostgresPool>,
@@ -57,13 +58,14 @@ pub async fn query_handler(
 }
 
 pub async fn query_handler_with_jwt(
-    jwt: UserJwt,
+    perm: UserPerm,
     req: Query<PSNRequest>,
     addr: DataRc<PSNServiceAddr>,
 ) -> Result<HttpResponse, Error> {
     match *req {
         PSNRequest::Auth { .. } => {
-            let req = req.into_inner().check_privilege(jwt.privilege)?;
+            perm.require(Permission::PsnAdmin, Scope::Global).await?;
+            let req = req.into_inner();
 
             // auth request is add to the front of queue.
             actix_rt::spawn(async move {
@@ -73,7 +75,11 @@ pub async fn query_handler_with_jwt(
         PSNRequest::Activation { .. } => {
             actix_rt::spawn(async move {
                 let _ = addr
-                    .send(req.into_inner().attach_user_id(jwt.user_id).into_msg(false))
+                    .send(
+                        req.into_inner()
+                            .attach_user_id(perm.jwt.user_id)
+                            .into_msg(false),
+                    )
                     .await;
             });
         }
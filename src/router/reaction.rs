@@ -0,0 +1,38 @@
+use actix_web::{web::Json, Error, HttpResponse};
+
+use crate::handler::{
+    auth::UserJwt, cache::MyRedisPool, cache_update::CacheServiceAddr, data::DataRc,
+    db::MyPostgresPool,
+};
+use crate::model::reaction::{ReactionRequest, ReactionSet};
+use crate::router::category::{categories_with_hidden, check_hidden};
+
+pub async fn react(
+    db_pool: DataRc<MyPostgresPool>,
+    cache_pool: DataRc<MyRedisPool>,
+    jwt: UserJwt,
+    req: Json<ReactionRequest>,
+    reactions: DataRc<ReactionSet>,
+    addr: DataRc<CacheServiceAddr>,
+) -> Result<HttpResponse, Error> {
+    jwt.check_privilege()?;
+
+    let req = req.into_inner().check(&reactions)?;
+
+    let target = db_pool.get_reaction_target(&req).await?;
+
+    let (_, hidden) = categories_with_hidden(&db_pool, &cache_pool, Some(&jwt)).await?;
+    check_hidden(&hidden, target.category_id)?;
+
+    let r = db_pool.set_reaction(target, jwt.user_id, req).await?;
+
+    let res = HttpResponse::Ok().json(&r);
+
+    actix_rt::spawn(async move {
+        cache_pool
+            .update_reaction_send_fail(r, addr.get_ref().clone())
+            .await
+    });
+
+    Ok(res)
+}
@@ -0,0 +1,51 @@
+use actix_web::{web::Query, Error, HttpResponse};
+
+use crate::handler::{auth::UserJwt, cache::MyRedisPool, data::DataRc, db::MyPostgresPool};
+use crate::model::{
+    errors::ResError,
+    search::{SearchHit, SearchQuery},
+};
+use crate::router::category::categories_with_hidden;
+
+pub async fn query_handler(
+    db_pool: DataRc<MyPostgresPool>,
+    cache_pool: DataRc<MyRedisPool>,
+    jwt: Option<UserJwt>,
+    req: Query<SearchQuery>,
+) -> Result<HttpResponse, Error> {
+    let req = req.into_inner().check_query()?;
+
+    let (_, hidden) = categories_with_hidden(&db_pool, &cache_pool, jwt.as_ref()).await?;
+
+    let hits = db_pool.search(&req, &hidden).await?;
+
+    if hits.is_empty() {
+        return Ok(HttpResponse::Ok().json(&hits));
+    }
+
+    let uids = hits.iter().map(|h| h.user_id).collect::<Vec<u32>>();
+
+    let mut should_update_u = false;
+
+    let u = match cache_pool.get_users(uids).await {
+        Ok(u) => u,
+        Err(e) => {
+            if let ResError::IdsFromCache(uids) = e {
+                should_update_u = true;
+                db_pool.get_users(&uids).await?
+            } else {
+                vec![]
+            }
+        }
+    };
+
+    let res = HttpResponse::Ok().json(&SearchHit::attach_users(&hits, &u));
+
+    if should_update_u {
+        actix_rt::spawn(async move {
+            let _ = cache_pool.update_users(&u).await;
+        });
+    }
+
+    Ok(res)
+}
@@ -7,12 +7,12 @@ use serde::Deserialize;
 
 use crate::handler::data::DataRc;
 use crate::handler::talk::{
-    Admin, AuthRequest, ConnectRequest, CreateTalkRequest, DeleteTalkRequest, GetHistory,
-    JoinTalkRequest, RemoveUserRequest, TalkByIdRequest, TalkServiceAddr, TextMessageRequest,
-    UserRelationRequest, UsersByIdRequest,
+    Admin, AuthRequest, ConnectRequest, CreateTalkRequest, DeleteMessageRequest, DeleteTalkRequest,
+    EditMessageRequest, GetHistory, JoinTalkRequest, RemoveUserRequest, TalkByIdRequest,
+    TalkServiceAddr, TextMessageRequest, UserRelationRequest, UsersByIdRequest,
 };
 use crate::model::{
-    actors::WsChatSession,
+    actors::{next_conn_id, WsChatSession},
     talk::{SendMessage, SessionMessage},
 };
 use crate::util::jwt::JwtPayLoad;
@@ -27,6 +27,7 @@ pub async fn talk(
     ws::start(
         WsChatSession {
             id: 0,
+            conn_id: next_conn_id(),
             hb: Instant::now(),
             addr: talk.get_ref().clone(),
         },
@@ -89,7 +90,16 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for WsChatSession {
                         "/relation" => general_msg_handler::<UserRelationRequest>(self, v[1], ctx),
                         "/join" => general_msg_handler::<JoinTalkRequest>(self, v[1], ctx),
                         "/create" => general_msg_handler::<CreateTalkRequest>(self, v[1], ctx),
-                        "/delete" => general_msg_handler::<DeleteTalkRequest>(self, v[1], ctx),
+                        "/edit" => general_msg_handler::<EditMessageRequest>(self, v[1], ctx),
+                        // payload with id field deletes the message. otherwise it deletes the talk.
+                        // a malformed message payload is a parsing error and never deletes the talk.
+                        "/delete" => {
+                            if has_message_id(v[1]) {
+                                general_msg_handler::<DeleteMessageRequest>(self, v[1], ctx)
+                            } else {
+                                general_msg_handler::<DeleteTalkRequest>(self, v[1], ctx)
+                            }
+                        }
                         _ => ctx.text(command_error().as_str()),
                     }
                 }
@@ -100,68 +110,93 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for WsChatSession {
 }
 
 // We reattach session_id using the server side record as the id from client can't be trust.
+// conn_id is attached too so the reply goes back to the connection sending the request.
 trait SessionId {
-    fn attach_session_id(&mut self, id: u32);
+    fn attach_session_id(&mut self, id: u32, conn_id: u32);
 }
 
 impl SessionId for Admin {
-    fn attach_session_id(&mut self, id: u32) {
+    fn attach_session_id(&mut self, id: u32, conn_id: u32) {
         self.session_id = Some(id);
+        self.conn_id = Some(conn_id);
     }
 }
 
 impl SessionId for RemoveUserRequest {
-    fn attach_session_id(&mut self, id: u32) {
+    fn attach_session_id(&mut self, id: u32, conn_id: u32) {
         self.session_id = Some(id);
+        self.conn_id = Some(conn_id);
     }
 }
 
 impl SessionId for GetHistory {
-    fn attach_session_id(&mut self, id: u32) {
+    fn attach_session_id(&mut self, id: u32, conn_id: u32) {
         self.session_id = Some(id);
+        self.conn_id = Some(conn_id);
     }
 }
 
 impl SessionId for TextMessageRequest {
-    fn attach_session_id(&mut self, id: u32) {
+    fn attach_session_id(&mut self, id: u32, conn_id: u32) {
         self.session_id = Some(id);
+        self.conn_id = Some(conn_id);
     }
 }
 
 impl SessionId for JoinTalkRequest {
-    fn attach_session_id(&mut self, id: u32) {
+    fn attach_session_id(&mut self, id: u32, conn_id: u32) {
         self.session_id = Some(id);
+        self.conn_id = Some(conn_id);
     }
 }
 
 impl SessionId for DeleteTalkRequest {
-    fn attach_session_id(&mut self, id: u32) {
+    fn attach_session_id(&mut self, id: u32, conn_id: u32) {
         self.session_id = Some(id);
+        self.conn_id = Some(conn_id);
+    }
+}
+
+impl SessionId for EditMessageRequest {
+    fn attach_session_id(&mut self, id: u32, conn_id: u32) {
+        self.session_id = Some(id);
+        self.conn_id = Some(conn_id);
+    }
+}
+
+impl SessionId for DeleteMessageRequest {
+    fn attach_session_id(&mut self, id: u32, conn_id: u32) {
+        self.session_id = Some(id);
+        self.conn_id = Some(conn_id);
     }
 }
 
 impl SessionId for CreateTalkRequest {
-    fn attach_session_id(&mut self, id: u32) {
+    fn attach_session_id(&mut self, id: u32, conn_id: u32) {
         self.owner = id;
         self.session_id = Some(id);
+        self.conn_id = Some(conn_id);
     }
 }
 
 impl SessionId for TalkByIdRequest {
-    fn attach_session_id(&mut self, id: u32) {
+    fn attach_session_id(&mut self, id: u32, conn_id: u32) {
         self.session_id = Some(id);
+        self.conn_id = Some(conn_id);
     }
 }
 
 impl SessionId for UsersByIdRequest {
-    fn attach_session_id(&mut self, id: u32) {
+    fn attach_session_id(&mut self, id: u32, conn_id: u32) {
         self.session_id = Some(id);
+        self.conn_id = Some(conn_id);
     }
 }
 
 impl SessionId for UserRelationRequest {
-    fn attach_session_id(&mut self, id: u32) {
+    fn attach_session_id(&mut self, id: u32, conn_id: u32) {
         self.session_id = Some(id);
+        self.conn_id = Some(conn_id);
     }
 }
 
@@ -181,7 +216,7 @@ fn general_msg_handler<'a, T>(
     let r: Result<T, _> = serde_json::from_str::<T>(text);
     match r {
         Ok(mut msg) => {
-            msg.attach_session_id(session.id);
+            msg.attach_session_id(session.id, session.conn_id);
             // we use do_send and ignore the return type as we already send the session actor's address to talk service actor.
             // the return message will be send back later as SessionMessage
             session.addr.do_send(msg)
@@ -190,6 +225,13 @@ fn general_msg_handler<'a, T>(
     }
 }
 
+// the delete target is told by the id field instead of which request the payload parses into.
+fn has_message_id(text: &str) -> bool {
+    serde_json::from_str::<serde_json::Value>(text)
+        .map(|v| v.get("id").is_some())
+        .unwrap_or(false)
+}
+
 fn auth(session: &mut WsChatSession, text: &str, ctx: &mut ws::WebsocketContext<WsChatSession>) {
     let r: Result<AuthRequest, _> = serde_json::from_str(text);
     match r {
@@ -199,6 +241,7 @@ fn auth(session: &mut WsChatSession, text: &str, ctx: &mut ws::WebsocketContext<
                 // when doing authentication we also send the session actor's address to talk service actor.
                 session.addr.do_send(ConnectRequest {
                     session_id: session.id,
+                    conn_id: session.conn_id,
                     online_status: auth.online_status,
                     addr: ctx.address(),
                 });
@@ -6,6 +6,7 @@ use crate::handler::{
     cache_update::CacheServiceAddr,
     data::DataRc,
     db::{GetStatement, MyPostgresPool, ParseRowStream},
+    mail_transport::take_captured_mails,
 };
 use crate::model::{
     errors::ResError,
@@ -27,12 +28,14 @@ pub async fn add_topic(
         body: Some("test body".to_string()),
         is_locked: None,
         is_visible: Some(true),
+        pin: None,
     };
 
     let jwt = UserJwt {
         exp: 0,
         user_id: 1,
-        privilege: 9,
+        privilege: 2,
+        gen: 0,
     };
 
     crate::router::topic::add(db_pool, cache_pool, jwt, Json(req), addr).await
@@ -56,7 +59,8 @@ pub async fn add_post(
     let jwt = UserJwt {
         exp: 0,
         user_id: 1,
-        privilege: 9,
+        privilege: 2,
+        gen: 0,
     };
 
     crate::router::post::add(db_pool, cache_pool, jwt, Json(req), addr).await
@@ -116,3 +120,8 @@ pub async fn raw_cache(cache_pool: DataRc<MyRedisPool>) -> Result<HttpResponse,
 
     Ok(HttpResponse::Ok().json(&Topic::attach_users(&t, &u)))
 }
+
+// return mails captured when MAIL_TRANSPORT=memory.
+pub async fn mails() -> Result<HttpResponse, Error> {
+    Ok(HttpResponse::Ok().json(&take_captured_mails()))
+}
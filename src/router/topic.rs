@@ -1,17 +1,21 @@
 use actix_web::{
-    web::{Json, Query},
+    web::{Json, Path, Query},
     Error, HttpResponse,
 };
+use chrono::NaiveDateTime;
 
 use crate::handler::{
     auth::UserJwt, cache::MyRedisPool, cache_update::CacheServiceAddr, data::DataRc,
-    db::MyPostgresPool,
+    db::MyPostgresPool, permission::UserPerm,
 };
 use crate::model::{
     errors::ResError,
+    permission::{Permission, Scope},
     post::Post,
+    revision::{RevisionDiffQuery, TopicRevisionDiff},
     topic::{QueryType, Topic, TopicQuery, TopicRequest},
 };
+use crate::router::category::{categories_with_hidden, check_hidden};
 
 pub async fn add(
     db_pool: DataRc<MyPostgresPool>,
@@ -27,6 +31,9 @@ pub async fn add(
         .add_user_id(Some(jwt.user_id))
         .check_new()?;
 
+    let (_, hidden) = categories_with_hidden(&db_pool, &cache_pool, Some(&jwt)).await?;
+    check_hidden(&hidden, req.category_id)?;
+
     let t = db_pool.add_topic(&req).await?;
 
     let res = HttpResponse::Ok().json(&t);
@@ -52,7 +59,7 @@ pub async fn update(
         .add_user_id(Some(jwt.user_id))
         .check_update()?;
 
-    let t = db_pool.update_topic(&req).await?;
+    let t = db_pool.update_topic(&req, jwt.user_id).await?;
 
     let res = HttpResponse::Ok().json(&t);
 
@@ -61,6 +68,58 @@ pub async fn update(
     Ok(res)
 }
 
+pub async fn delete(
+    db_pool: DataRc<MyPostgresPool>,
+    cache_pool: DataRc<MyRedisPool>,
+    perm: UserPerm,
+    id: Path<u32>,
+    addr: DataRc<CacheServiceAddr>,
+) -> Result<HttpResponse, Error> {
+    perm.jwt.check_privilege()?;
+
+    let id = id.into_inner();
+
+    // users without topic.delete_any permission could only delete their own topics.
+    let uid = if perm
+        .resolve(Scope::Topic(id))
+        .await?
+        .has(Permission::TopicDeleteAny)
+    {
+        None
+    } else {
+        Some(perm.jwt.user_id)
+    };
+
+    let t = db_pool.delete_topic(id, perm.jwt.user_id, uid).await?;
+
+    if t.is_empty() {
+        return Err(ResError::NotFound.into());
+    }
+
+    let res = HttpResponse::Ok().json(&t);
+
+    actix_rt::spawn(async move {
+        cache_pool
+            .remove_topic_send_fail(db_pool.get_ref(), t, addr.get_ref().clone())
+            .await
+    });
+
+    Ok(res)
+}
+
+pub(crate) fn restore_topic_send_fail(
+    db_pool: DataRc<MyPostgresPool>,
+    cache_pool: DataRc<MyRedisPool>,
+    t: Vec<Topic>,
+    addr: DataRc<CacheServiceAddr>,
+) {
+    actix_rt::spawn(async move {
+        cache_pool
+            .restore_topic_send_fail(db_pool.get_ref(), t, addr.get_ref().clone())
+            .await
+    });
+}
+
 pub(crate) fn update_topic_send_fail(
     cache_pool: DataRc<MyRedisPool>,
     t: Vec<Topic>,
@@ -76,27 +135,161 @@ pub(crate) fn update_topic_send_fail(
 pub async fn query_handler(
     db_pool: DataRc<MyPostgresPool>,
     cache_pool: DataRc<MyRedisPool>,
+    jwt: Option<UserJwt>,
     req: Query<TopicQuery>,
 ) -> Result<HttpResponse, Error> {
-    match req.query_type {
+    let (_, hidden) = categories_with_hidden(&db_pool, &cache_pool, jwt.as_ref()).await?;
+
+    let tid = req.topic_id;
+    let cache = cache_pool.clone();
+
+    let (res, last) = match req.query_type {
         QueryType::Oldest => {
             let result = cache_pool.get_posts_old(req.topic_id, req.page).await;
-            if_query_db(db_pool, cache_pool, req.topic_id, req.page, result).await
+            if_query_db(db_pool, cache_pool, &hidden, req.topic_id, req.page, result).await
         }
         QueryType::Popular => {
             let result = cache_pool.get_posts_pop(req.topic_id, req.page).await;
-            if_query_db(db_pool, cache_pool, req.topic_id, req.page, result).await
+            if_query_db(db_pool, cache_pool, &hidden, req.topic_id, req.page, result).await
         }
+        QueryType::Thread => query_thread(db_pool, cache_pool, &hidden, req.into_inner()).await,
+    }?;
+
+    // viewing topic marks it read up to the latest post returned.
+    if let (Some(jwt), Some((pid, read_at))) = (jwt, last) {
+        actix_rt::spawn(async move {
+            let _ = cache.mark_topic_read(jwt.user_id, tid, pid, read_at).await;
+        });
+    }
+
+    Ok(res)
+}
+
+// id and created time of the latest post returned. it's the topic's created time when only topic is returned.
+fn last_returned(t: &[Topic], p: &[Post]) -> Option<(Option<u32>, NaiveDateTime)> {
+    match p.iter().max_by_key(|p| p.created_at) {
+        Some(p) => Some((Some(p.id), p.created_at)),
+        None => t.first().map(|t| (None, t.created_at)),
+    }
+}
+
+// topic and its posts are in the same category. topic set invisible by admin is not served either.
+fn check_topic_hidden(hidden: &[u32], t: &[Topic], p: &[Post]) -> Result<(), ResError> {
+    if t.first().map(|t| !t.is_visible).unwrap_or(false) {
+        return Err(ResError::NotFound);
     }
+    match t
+        .first()
+        .map(|t| t.category_id)
+        .or_else(|| p.first().map(|p| p.category_id))
+    {
+        Some(cid) => check_hidden(hidden, cid),
+        None => Ok(()),
+    }
+}
+
+async fn query_thread(
+    db_pool: DataRc<MyPostgresPool>,
+    cache_pool: DataRc<MyRedisPool>,
+    hidden: &[u32],
+    req: TopicQuery,
+) -> Result<(HttpResponse, Option<(Option<u32>, NaiveDateTime)>), Error> {
+    let mut should_update_u = false;
+    let mut should_update_p = false;
+    let mut should_update_t = false;
+
+    let mut pids = db_pool
+        .get_thread_ids(req.topic_id, req.post_id, req.page, req.depth)
+        .await?;
+
+    // subtree is returned along with its root post.
+    if let Some(pid) = req.post_id {
+        pids.insert(0, pid);
+    }
+
+    let (p, mut uids) = if pids.is_empty() {
+        (vec![], vec![])
+    } else {
+        match cache_pool.get_posts(pids).await {
+            Ok((p, uids)) => (p, uids),
+            Err(e) => {
+                if let ResError::IdsFromCache(pids) = e {
+                    should_update_p = true;
+                    db_pool.get_posts(&pids).await?
+                } else {
+                    return Err(e.into());
+                }
+            }
+        }
+    };
+
+    // root of the reply tree. None is the topic itself.
+    let root = match req.post_id {
+        Some(pid) => match p.iter().find(|p| p.id == pid && p.topic_id == req.topic_id) {
+            Some(p) => p.post_id,
+            None => return Err(ResError::NotFound.into()),
+        },
+        None => None,
+    };
+
+    let (t, mut uid) = if req.page == 1 && req.post_id.is_none() {
+        match cache_pool.get_topics(vec![req.topic_id]).await {
+            Ok((t, uid)) => (t, uid),
+            Err(e) => {
+                if let ResError::IdsFromCache(tids) = e {
+                    should_update_t = true;
+                    db_pool.get_topics(&tids).await?
+                } else {
+                    return Err(e.into());
+                }
+            }
+        }
+    } else {
+        (vec![], vec![])
+    };
+
+    check_topic_hidden(hidden, &t, &p)?;
+
+    uids.append(&mut uid);
+
+    let u = match cache_pool.get_users(uids).await {
+        Ok(u) => u,
+        Err(e) => {
+            if let ResError::IdsFromCache(uids) = e {
+                should_update_u = true;
+                db_pool.get_users(&uids).await?
+            } else {
+                vec![]
+            }
+        }
+    };
+
+    let res = HttpResponse::Ok().json(&Topic::attach_users_with_thread(t.first(), root, &p, &u));
+    let last = last_returned(&t, &p);
+
+    actix_rt::spawn(async move {
+        if should_update_u {
+            let _ = cache_pool.update_users(&u).await;
+        };
+        if should_update_t {
+            let _ = cache_pool.update_topics(&t).await;
+        };
+        if should_update_p {
+            let _ = cache_pool.update_posts(&p).await;
+        };
+    });
+
+    Ok((res, last))
 }
 
 async fn if_query_db(
     db_pool: DataRc<MyPostgresPool>,
     cache_pool: DataRc<MyRedisPool>,
+    hidden: &[u32],
     tid: u32,
     page: usize,
     result: Result<(Vec<Post>, Vec<u32>), ResError>,
-) -> Result<HttpResponse, Error> {
+) -> Result<(HttpResponse, Option<(Option<u32>, NaiveDateTime)>), Error> {
     let mut should_update_u = false;
     let mut should_update_p = false;
     let mut should_update_t = false;
@@ -129,6 +322,8 @@ async fn if_query_db(
         (vec![], vec![])
     };
 
+    check_topic_hidden(hidden, &t, &p)?;
+
     uids.append(&mut uid);
 
     let u = match cache_pool.get_users(uids).await {
@@ -144,6 +339,7 @@ async fn if_query_db(
     };
 
     let res = HttpResponse::Ok().json(&Topic::attach_users_with_post(t.first(), &p, &u));
+    let last = last_returned(&t, &p);
 
     actix_rt::spawn(async move {
         if should_update_u {
@@ -157,5 +353,81 @@ async fn if_query_db(
         };
     });
 
-    Ok(res)
+    Ok((res, last))
+}
+
+// revisions of topic in hidden category are not accessible. revisions of deleted or invisible
+// topic are only accessible with topic.edit_any permission.
+async fn check_revision_access(
+    db_pool: &DataRc<MyPostgresPool>,
+    cache_pool: &DataRc<MyRedisPool>,
+    perm: Option<&UserPerm>,
+    tid: u32,
+) -> Result<(), ResError> {
+    let (cid, is_alive) = db_pool.get_topic_revision_target(tid).await?;
+
+    let (_, hidden) = categories_with_hidden(db_pool, cache_pool, perm.map(|p| &p.jwt)).await?;
+    check_hidden(&hidden, cid)?;
+
+    if !is_alive {
+        perm.ok_or(ResError::NotFound)?
+            .require(Permission::TopicEditAny, Scope::Topic(tid))
+            .await
+            .map_err(|_| ResError::NotFound)?;
+    }
+
+    Ok(())
+}
+
+pub async fn revisions(
+    db_pool: DataRc<MyPostgresPool>,
+    cache_pool: DataRc<MyRedisPool>,
+    perm: Option<UserPerm>,
+    id: Path<u32>,
+) -> Result<HttpResponse, Error> {
+    let tid = id.into_inner();
+    check_revision_access(&db_pool, &cache_pool, perm.as_ref(), tid).await?;
+
+    let r = db_pool.get_topic_revisions(tid).await?;
+    Ok(HttpResponse::Ok().json(&r))
+}
+
+pub async fn revision_diff(
+    db_pool: DataRc<MyPostgresPool>,
+    cache_pool: DataRc<MyRedisPool>,
+    perm: Option<UserPerm>,
+    id: Path<u32>,
+    req: Query<RevisionDiffQuery>,
+) -> Result<HttpResponse, Error> {
+    let tid = id.into_inner();
+    check_revision_access(&db_pool, &cache_pool, perm.as_ref(), tid).await?;
+
+    let ids = match req.to {
+        Some(to) => vec![req.from, to],
+        None => vec![req.from],
+    };
+    let r = db_pool.get_topic_revisions_by_id(&ids).await?;
+
+    // diff to current content when there is no target revision.
+    let (t, _) = match req.to {
+        Some(_) => (vec![], vec![]),
+        None => db_pool.get_topics(&[tid]).await?,
+    };
+
+    let find = |id: i32| {
+        r.iter()
+            .find(|r| r.id == id && r.topic_id == tid)
+            .map(|r| (r.title.as_str(), r.body.as_str(), r.thumbnail.as_str()))
+    };
+
+    let from = find(req.from).ok_or(ResError::NotFound)?;
+    let to = match req.to {
+        Some(to) => find(to),
+        None => t
+            .first()
+            .map(|t| (t.title.as_str(), t.body.as_str(), t.thumbnail.as_str())),
+    }
+    .ok_or(ResError::NotFound)?;
+
+    Ok(HttpResponse::Ok().json(&TopicRevisionDiff::new(from, to)))
 }
@@ -41,7 +41,8 @@ pub async fn update(
     let req = req
         .into_inner()
         .attach_id(Some(jwt.user_id))
-        .check_update()?;
+        .check_update()?
+        .check_locale()?;
 
     let u = db_pool.update_user(req).await?;
 
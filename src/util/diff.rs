@@ -0,0 +1,80 @@
+#[derive(Serialize, Debug, PartialEq, Clone, Copy)]
+pub enum DiffKind {
+    Equal,
+    Insert,
+    Delete,
+}
+
+#[derive(Serialize, Debug)]
+pub struct DiffChunk {
+    pub kind: DiffKind,
+    pub text: String,
+}
+
+// word level diff from old to new. whitespace is kept so joining the Equal and Insert chunks gives the new text.
+pub fn diff(old: &str, new: &str) -> Vec<DiffChunk> {
+    let a = tokenize(old);
+    let b = tokenize(new);
+
+    // lcs[i][j] is the length of the longest common subsequence of a[i..] and b[j..]
+    let mut lcs = vec![vec![0u32; b.len() + 1]; a.len() + 1];
+    for i in (0..a.len()).rev() {
+        for j in (0..b.len()).rev() {
+            lcs[i][j] = if a[i] == b[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+
+    let mut chunks = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() || j < b.len() {
+        if i < a.len() && j < b.len() && a[i] == b[j] {
+            push_chunk(&mut chunks, DiffKind::Equal, a[i]);
+            i += 1;
+            j += 1;
+        } else if j < b.len() && (i == a.len() || lcs[i][j + 1] >= lcs[i + 1][j]) {
+            push_chunk(&mut chunks, DiffKind::Insert, b[j]);
+            j += 1;
+        } else {
+            push_chunk(&mut chunks, DiffKind::Delete, a[i]);
+            i += 1;
+        }
+    }
+
+    chunks
+}
+
+// merge the token into last chunk if they are the same kind.
+fn push_chunk(chunks: &mut Vec<DiffChunk>, kind: DiffKind, token: &str) {
+    match chunks.last_mut() {
+        Some(c) if c.kind == kind => c.text.push_str(token),
+        _ => chunks.push(DiffChunk {
+            kind,
+            text: token.to_owned(),
+        }),
+    }
+}
+
+// split text into words and the whitespace between them.
+fn tokenize(s: &str) -> Vec<&str> {
+    let mut tokens = Vec::new();
+    let mut start = 0;
+    let mut last = None;
+
+    for (i, c) in s.char_indices() {
+        let is_ws = c.is_whitespace();
+        if last.map(|l| l != is_ws).unwrap_or(false) {
+            tokens.push(&s[start..i]);
+            start = i;
+        }
+        last = Some(is_ws);
+    }
+    if start < s.len() {
+        tokens.push(&s[start..]);
+    }
+
+    tokens
+}
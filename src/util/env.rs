@@ -1,5 +1,28 @@
 use std::env::var;
 
+// backend used by MailerService to deliver mails.
+#[derive(Clone)]
+pub enum MailTransportKind {
+    Smtp,
+    // write mails to the directory as .eml files. print to stdout when no directory is given.
+    File(Option<String>),
+    // keep mails in memory for tests.
+    Memory,
+}
+
+// weights used by CacheService to sort popular and hot lists.
+#[derive(Clone, Copy, Debug)]
+pub struct RankConfig {
+    // reactions are blended into popular list when weight is greater than 0.
+    pub reaction_weight: f64,
+    // weight of reactions in hot score.
+    pub hot_reaction_weight: f64,
+    // hours for the hot score of topic to decay to half since the last reply.
+    pub hot_half_life: f64,
+    // hours since the last reply for topic to stay in hot list.
+    pub hot_window: i64,
+}
+
 #[derive(Clone)]
 pub struct Env {
     postgres_url: String,
@@ -8,8 +31,12 @@ pub struct Env {
     server_port: String,
     cors_origin: String,
     use_mail: bool,
+    mail_transport: MailTransportKind,
+    mail_template_dir: Option<String>,
     use_sms: bool,
     use_rep: bool,
+    reactions: String,
+    rank: RankConfig,
 }
 
 impl Env {
@@ -25,6 +52,15 @@ impl Env {
             .unwrap_or_else(|_| "true".to_owned())
             .parse::<bool>()
             .unwrap_or(true);
+        let mail_transport = match var("MAIL_TRANSPORT")
+            .unwrap_or_else(|_| "smtp".to_owned())
+            .as_str()
+        {
+            "file" => MailTransportKind::File(var("MAIL_FILE_DIR").ok().filter(|d| !d.is_empty())),
+            "memory" => MailTransportKind::Memory,
+            _ => MailTransportKind::Smtp,
+        };
+        let mail_template_dir = var("MAIL_TEMPLATE_DIR").ok().filter(|d| !d.is_empty());
         let use_sms = var("USE_SMS")
             .unwrap_or_else(|_| "false".to_owned())
             .parse::<bool>()
@@ -33,6 +69,26 @@ impl Env {
             .unwrap_or_else(|_| "false".to_owned())
             .parse::<bool>()
             .unwrap_or(false);
+        // comma separated reaction names. like is always allowed.
+        let reactions = var("REACTIONS").unwrap_or_default();
+        let reaction_weight = var("REACTION_WEIGHT")
+            .unwrap_or_else(|_| "0".to_owned())
+            .parse::<f64>()
+            .unwrap_or(0.0);
+        let hot_reaction_weight = var("HOT_REACTION_WEIGHT")
+            .unwrap_or_else(|_| "1".to_owned())
+            .parse::<f64>()
+            .unwrap_or(1.0);
+        let hot_half_life = var("HOT_HALF_LIFE")
+            .unwrap_or_else(|_| "12".to_owned())
+            .parse::<f64>()
+            .ok()
+            .filter(|h| *h > 0.0)
+            .unwrap_or(12.0);
+        let hot_window = var("HOT_WINDOW")
+            .unwrap_or_else(|_| "168".to_owned())
+            .parse::<i64>()
+            .unwrap_or(168);
 
         Self {
             postgres_url,
@@ -41,8 +97,17 @@ impl Env {
             server_port,
             cors_origin,
             use_mail,
+            mail_transport,
+            mail_template_dir,
             use_sms,
             use_rep,
+            reactions,
+            rank: RankConfig {
+                reaction_weight,
+                hot_reaction_weight,
+                hot_half_life,
+                hot_window,
+            },
         }
     }
 
@@ -58,6 +123,19 @@ impl Env {
         self.use_mail
     }
 
+    pub fn mail_transport(&self) -> &MailTransportKind {
+        &self.mail_transport
+    }
+
+    // captured mails hold live activation and reset uuids so they are only exposed with memory transport.
+    pub fn capture_mails(&self) -> bool {
+        matches!(self.mail_transport, MailTransportKind::Memory)
+    }
+
+    pub fn mail_template_dir(&self) -> Option<&str> {
+        self.mail_template_dir.as_deref()
+    }
+
     pub fn use_sms(&self) -> bool {
         self.use_sms
     }
@@ -66,6 +144,14 @@ impl Env {
         self.use_rep
     }
 
+    pub fn reactions(&self) -> &str {
+        &self.reactions
+    }
+
+    pub fn rank(&self) -> RankConfig {
+        self.rank
+    }
+
     pub fn cors_origin(&self) -> &str {
         &self.cors_origin
     }
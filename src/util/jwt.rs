@@ -5,19 +5,25 @@ use jsonwebtoken::{decode, encode, DecodingKey, EncodingKey, Header, Validation}
 
 use crate::model::errors::ResError;
 
+// access token life in minutes. long lived sessions are kept with refresh tokens.
+const ACCESS_TOKEN_LIFE: i64 = 15;
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct JwtPayLoad {
     pub exp: i64,
     pub user_id: u32,
     pub privilege: u32,
+    // token generation of user. token is revoked when it's lower than the counter stored in redis.
+    pub gen: u32,
 }
 
 impl JwtPayLoad {
-    pub fn new(user_id: u32, privilege: u32) -> Self {
+    pub fn new(user_id: u32, privilege: u32, gen: u32) -> Self {
         JwtPayLoad {
-            exp: (Local::now() + Duration::days(30)).timestamp(),
+            exp: (Local::now() + Duration::minutes(ACCESS_TOKEN_LIFE)).timestamp(),
             user_id,
             privilege,
+            gen,
         }
     }
 
@@ -0,0 +1,226 @@
+use std::fmt;
+
+use futures::FutureExt;
+use tokio_postgres::{tls::NoTls, Client};
+
+use crate::model::errors::ResError;
+
+// migrations are embedded from ./migrations at compile time and applied in version order.
+// new migrations should always be appended to the end with a bigger version number.
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        name: "init",
+        up: include_str!("../../migrations/0001_init.up.sql"),
+        down: include_str!("../../migrations/0001_init.down.sql"),
+    },
+    Migration {
+        version: 2,
+        name: "user_locale",
+        up: include_str!("../../migrations/0002_user_locale.up.sql"),
+        down: include_str!("../../migrations/0002_user_locale.down.sql"),
+    },
+    Migration {
+        version: 3,
+        name: "search",
+        up: include_str!("../../migrations/0003_search.up.sql"),
+        down: include_str!("../../migrations/0003_search.down.sql"),
+    },
+    Migration {
+        version: 4,
+        name: "soft_delete",
+        up: include_str!("../../migrations/0004_soft_delete.up.sql"),
+        down: include_str!("../../migrations/0004_soft_delete.down.sql"),
+    },
+    Migration {
+        version: 5,
+        name: "revisions",
+        up: include_str!("../../migrations/0005_revisions.up.sql"),
+        down: include_str!("../../migrations/0005_revisions.down.sql"),
+    },
+    Migration {
+        version: 6,
+        name: "roles",
+        up: include_str!("../../migrations/0006_roles.up.sql"),
+        down: include_str!("../../migrations/0006_roles.down.sql"),
+    },
+    Migration {
+        version: 7,
+        name: "category_visibility",
+        up: include_str!("../../migrations/0007_category_visibility.up.sql"),
+        down: include_str!("../../migrations/0007_category_visibility.down.sql"),
+    },
+    Migration {
+        version: 8,
+        name: "category_tree",
+        up: include_str!("../../migrations/0008_category_tree.up.sql"),
+        down: include_str!("../../migrations/0008_category_tree.down.sql"),
+    },
+    Migration {
+        version: 9,
+        name: "tags",
+        up: include_str!("../../migrations/0009_tags.up.sql"),
+        down: include_str!("../../migrations/0009_tags.down.sql"),
+    },
+    Migration {
+        version: 10,
+        name: "pins",
+        up: include_str!("../../migrations/0010_pins.up.sql"),
+        down: include_str!("../../migrations/0010_pins.down.sql"),
+    },
+    Migration {
+        version: 11,
+        name: "topic_move",
+        up: include_str!("../../migrations/0011_topic_move.up.sql"),
+        down: include_str!("../../migrations/0011_topic_move.down.sql"),
+    },
+    Migration {
+        version: 12,
+        name: "reactions",
+        up: include_str!("../../migrations/0012_reactions.up.sql"),
+        down: include_str!("../../migrations/0012_reactions.down.sql"),
+    },
+    Migration {
+        version: 13,
+        name: "reads",
+        up: include_str!("../../migrations/0013_reads.up.sql"),
+        down: include_str!("../../migrations/0013_reads.down.sql"),
+    },
+    Migration {
+        version: 14,
+        name: "messages",
+        up: include_str!("../../migrations/0014_messages.up.sql"),
+        down: include_str!("../../migrations/0014_messages.down.sql"),
+    },
+];
+
+const CREATE_MIGRATIONS_TABLE: &str = "
+CREATE TABLE IF NOT EXISTS schema_migrations
+(
+version         OID          NOT NULL UNIQUE PRIMARY KEY,
+name            VARCHAR(128) NOT NULL,
+applied_at      TIMESTAMP    NOT NULL DEFAULT CURRENT_TIMESTAMP
+)";
+const SELECT_APPLIED: &str = "SELECT version FROM schema_migrations ORDER BY version";
+const INSERT_APPLIED: &str = "INSERT INTO schema_migrations (version, name) VALUES ($1, $2)";
+const DELETE_APPLIED: &str = "DELETE FROM schema_migrations WHERE version = $1";
+// databases built before schema_migrations existed already have the initial schema.
+const COUNT_LEGACY_TABLE: &str =
+    "SELECT COUNT(*) FROM information_schema.tables WHERE table_name = 'categories'";
+
+pub struct Migration {
+    pub version: u32,
+    pub name: &'static str,
+    up: &'static str,
+    down: &'static str,
+}
+
+impl fmt::Display for Migration {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:04}_{}", self.version, self.name)
+    }
+}
+
+// apply all pending migrations and return the applied ones.
+pub(crate) async fn migrate_up(postgres_url: &str) -> Result<Vec<&'static Migration>, ResError> {
+    let mut c = connect(postgres_url).await?;
+    let applied = applied_versions(&c).await?;
+
+    let mut result = Vec::new();
+    for m in MIGRATIONS.iter().filter(|m| !applied.contains(&m.version)) {
+        let tx = c.transaction().await?;
+        tx.batch_execute(m.up).await?;
+        tx.execute(INSERT_APPLIED, &[&m.version, &m.name]).await?;
+        tx.commit().await?;
+
+        result.push(m);
+    }
+
+    Ok(result)
+}
+
+// revert the last applied migration.
+pub(crate) async fn migrate_down(
+    postgres_url: &str,
+) -> Result<Option<&'static Migration>, ResError> {
+    let mut c = connect(postgres_url).await?;
+    let applied = applied_versions(&c).await?;
+
+    match applied
+        .last()
+        .and_then(|v| MIGRATIONS.iter().find(|m| m.version == *v))
+    {
+        Some(m) => {
+            revert(&mut c, m).await?;
+            Ok(Some(m))
+        }
+        None => Ok(None),
+    }
+}
+
+pub(crate) async fn migrate_down_all(postgres_url: &str) -> Result<(), ResError> {
+    let mut c = connect(postgres_url).await?;
+    let applied = applied_versions(&c).await?;
+
+    for m in MIGRATIONS
+        .iter()
+        .rev()
+        .filter(|m| applied.contains(&m.version))
+    {
+        revert(&mut c, m).await?;
+    }
+
+    Ok(())
+}
+
+// return all known migrations and if they are applied.
+pub(crate) async fn status(
+    postgres_url: &str,
+) -> Result<Vec<(&'static Migration, bool)>, ResError> {
+    let c = connect(postgres_url).await?;
+    let applied = applied_versions(&c).await?;
+
+    Ok(MIGRATIONS
+        .iter()
+        .map(|m| (m, applied.contains(&m.version)))
+        .collect())
+}
+
+async fn revert(c: &mut Client, m: &Migration) -> Result<(), ResError> {
+    let tx = c.transaction().await?;
+    tx.batch_execute(m.down).await?;
+    tx.execute(DELETE_APPLIED, &[&m.version]).await?;
+    tx.commit().await?;
+    Ok(())
+}
+
+async fn applied_versions(c: &Client) -> Result<Vec<u32>, ResError> {
+    c.batch_execute(CREATE_MIGRATIONS_TABLE).await?;
+
+    let mut versions = c
+        .query(SELECT_APPLIED, &[])
+        .await?
+        .iter()
+        .map(|r| r.try_get(0))
+        .collect::<Result<Vec<u32>, _>>()?;
+
+    if versions.is_empty() {
+        let count =
+            crate::handler::db::simple_query_one_column::<u32>(c, COUNT_LEGACY_TABLE, 0).await?;
+        if count > 0 {
+            let m = &MIGRATIONS[0];
+            c.execute(INSERT_APPLIED, &[&m.version, &m.name]).await?;
+            versions.push(m.version);
+        }
+    }
+
+    Ok(versions)
+}
+
+async fn connect(postgres_url: &str) -> Result<Client, ResError> {
+    let (c, conn) = tokio_postgres::connect(postgres_url, NoTls).await?;
+
+    actix_rt::spawn(conn.map(|_| ()));
+
+    Ok(c)
+}
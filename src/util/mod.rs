@@ -1,5 +1,7 @@
+pub mod diff;
 pub mod env;
 pub mod hash;
 pub mod jwt;
+pub mod migration;
 pub mod startup;
 pub mod validation;
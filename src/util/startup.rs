@@ -9,240 +9,21 @@ use crate::handler::{
         build_users_cache,
     },
     db::ParseRowStream,
+    pin::{build_pins_cache, SELECT_TOPIC_PINS},
+    reaction::{build_reactions_cache, reaction_counts},
+    read::{build_reads_cache, SELECT_CATEGORY_READS, SELECT_TOPIC_READS},
+    tag::build_tags_cache,
 };
 use crate::model::{
     category::Category,
     common::{global, GlobalSessions, GlobalTalks},
     errors::ResError,
+    read::{CategoryRead, TopicRead},
     talk::Talk,
     topic::Topic,
     user::User,
 };
-
-const BUILD_TABLES: &str = "
-CREATE TABLE users
-(
-id              OID          NOT NULL UNIQUE PRIMARY KEY,
-username        VARCHAR(32)  NOT NULL UNIQUE,
-email           VARCHAR(100) NOT NULL UNIQUE,
-hashed_password VARCHAR(64)  NOT NULL,
-avatar_url      VARCHAR(128) NOT NULL,
-signature       VARCHAR(256) NOT NULL,
-created_at      TIMESTAMP    NOT NULL DEFAULT CURRENT_TIMESTAMP,
-privilege       OID          NOT NULL DEFAULT 1,
-show_email      BOOLEAN      NOT NULL DEFAULT TRUE
-);
-
-CREATE TABLE categories
-(
-id               OID          NOT NULL UNIQUE PRIMARY KEY,
-name             VARCHAR(128) NOT NULL UNIQUE,
-thumbnail        VARCHAR(256) NOT NULL
-);
-
-CREATE TABLE topics
-(
-id              OID           NOT NULL UNIQUE PRIMARY KEY,
-user_id         OID           NOT NULL,
-category_id     OID           NOT NULL,
-title           VARCHAR(1024) NOT NULL,
-body            VARCHAR(1024) NOT NULL,
-thumbnail       VARCHAR(1024) NOT NULL,
-created_at      TIMESTAMP     NOT NULL DEFAULT CURRENT_TIMESTAMP,
-updated_at      TIMESTAMP     NOT NULL DEFAULT CURRENT_TIMESTAMP,
-is_locked       BOOLEAN       NOT NULL DEFAULT FALSE,
-is_visible      BOOLEAN       NOT NULL DEFAULT TRUE
-);
-
-CREATE TABLE posts
-(
-id              OID           NOT NULL UNIQUE PRIMARY KEY,
-user_id         OID           NOT NULL,
-topic_id        OID           NOT NULL,
-category_id     OID           NOT NULL,
-post_id         OID,
-post_content    VARCHAR(1024) NOT NULL,
-created_at      TIMESTAMP     NOT NULL DEFAULT CURRENT_TIMESTAMP,
-updated_at      TIMESTAMP     NOT NULL DEFAULT CURRENT_TIMESTAMP,
-is_locked       BOOLEAN       NOT NULL DEFAULT FALSE
-);
-
-CREATE TABLE associates
-(
-id               OID          NOT NULL UNIQUE PRIMARY KEY,
-user_id          OID          NOT NULL UNIQUE,
-psn_id           VARCHAR(128) UNIQUE,
-live_id          VARCHAR(128) UNIQUE,
-last_update_time TIMESTAMP    NOT NULL DEFAULT CURRENT_TIMESTAMP
-);
-
-CREATE TABLE talks
-(
-id              OID             NOT NULL UNIQUE PRIMARY KEY,
-name            VARCHAR(128)    NOT NULL UNIQUE,
-description     VARCHAR(128)    NOT NULL,
-secret          VARCHAR(128)    NOT NULL DEFAULT '1',
-privacy         OID             NOT NULL DEFAULT 0,
-owner           OID             NOT NULL,
-admin           OID[]           NOT NULL,
-users           OID[]           NOT NULL
-);
-
-CREATE TABLE relations
-(
-id          OID             NOT NULL UNIQUE PRIMARY KEY,
-friends     OID[]
-);
-
-CREATE TABLE public_messages1
-(
-talk_id     OID             NOT NULL PRIMARY KEY,
-time        TIMESTAMP       NOT NULL DEFAULT CURRENT_TIMESTAMP,
-text        VARCHAR(1024)   NOT NULL
-);
-
-CREATE TABLE private_messages1
-(
-from_id     OID             NOT NULL,
-to_id       OID             NOT NULL PRIMARY KEY,
-time        TIMESTAMP       NOT NULL DEFAULT CURRENT_TIMESTAMP,
-text        VARCHAR(1024)   NOT NULL
-);
-
-CREATE INDEX pub_message_time_order ON public_messages1 (time DESC);
-CREATE INDEX prv_message_time_order ON private_messages1 (time DESC);
-
-CREATE UNIQUE INDEX users_username ON users (username);
-CREATE UNIQUE INDEX users_email ON users (email);
-CREATE UNIQUE INDEX categories_name ON categories (name);
-CREATE UNIQUE INDEX talks_name ON talks (name);
-CREATE UNIQUE INDEX associates_psn_id ON associates (psn_id);
-CREATE UNIQUE INDEX associates_live_id ON associates (live_id);
-
-
-CREATE TABLE psn_user_trophy_titles
-(
-np_id                   VARCHAR(32)         NOT NULL,
-np_communication_id     VARCHAR(32)         NOT NULL,
-is_visible              BOOLEAN             NOT NULL DEFAULT TRUE,
-progress                OID                 NOT NULL DEFAULT 0,
-earned_platinum         OID                 NOT NULL DEFAULT 0,
-earned_gold             OID                 NOT NULL DEFAULT 0,
-earned_silver           OID                 NOT NULL DEFAULT 0,
-earned_bronze           OID                 NOT NULL DEFAULT 0,
-last_update_date        TIMESTAMP           NOT NULL
-);
-
-CREATE TABLE trophy_sets
-(
-np_communication_id     VARCHAR(32)         NOT NULL,
-trophy_id               OID                 NOT NULL,
-trophy_hidden           BOOLEAN             NOT NULL,
-trophy_type             VARCHAR(16)         NOT NULL,
-trophy_name             VARCHAR(128)        NOT NULL,
-trophy_detail           VARCHAR(1024)       NOT NULL,
-trophy_icon_url         VARCHAR(1024)       NOT NULL,
-trophy_rare             INTEGER             NOT NULL,
-trophy_earned_rate      VARCHAR(16)         NOT NULL
-);
-
-CREATE TYPE should_before_after AS
-(
-trophy_id               OID,
-reason                  VARCHAR(1024),
-agreement               OID,
-disagreement            OID
-);
-
-CREATE TYPE should_absent_time AS
-(
-beginning               TIMESTAMP,
-ending                  TIMESTAMP,
-is_regular              BOOLEAN,
-reason                  VARCHAR(1024),
-agreement               OID,
-disagreement            OID
-);
-
-CREATE TABLE trophy_sets_argument
-(
-np_communication_id     VARCHAR(32)         NOT NULL,
-trophy_id               INTEGER             NOT NULL,
-should_before           should_before_after[],
-should_after            should_before_after[],
-should_absent_time      should_absent_time[]
-);
-
-CREATE TYPE trophy_set AS
-(
-trophy_id               INTEGER,
-earned_date             TIMESTAMP,
-first_earned_date       TIMESTAMP
-);
-
-CREATE TABLE psn_user_trophy_sets
-(
-np_id                   VARCHAR(32)         NOT NULL,
-np_communication_id     VARCHAR(32)         NOT NULL,
-is_visible              BOOLEAN             NOT NULL DEFAULT TRUE,
-trophy_set              trophy_set[]
-);
-
-CREATE UNIQUE INDEX user_trophy_titles ON psn_user_trophy_titles (np_id, np_communication_id);
-CREATE UNIQUE INDEX user_trophy_sets ON psn_user_trophy_sets (np_id, np_communication_id);
-
-INSERT INTO users (id, username, email, hashed_password, signature, avatar_url, privilege)
-VALUES (1, 'adminuser', 'admin@pixelshare', '$2y$06$z6K5TMA2TQbls77he7cEsOQQ4ekgCNvuxkg6eSKdHHLO9u6sY9d3C', 'AdminUser', 'ac.jpg', 9),
-(2, 'testtest1', 'test123@test123', '$2y$06$z6K5TMA2TQbls77he7cEsOQQ4ekgCNvuxkg6eSKdHHLO9u6sY9d3C', 'AdminUser', 'ac.jpg', 0),
-(3, 'testtest2', 'test223@test123', '$2y$06$z6K5TMA2TQbls77he7cEsOQQ4ekgCNvuxkg6eSKdHHLO9u6sY9d3C', 'AdminUser', 'ac.jpg', 1),
-(4, 'testtest3', 'test323@test123', '$2y$06$z6K5TMA2TQbls77he7cEsOQQ4ekgCNvuxkg6eSKdHHLO9u6sY9d3C', 'AdminUser', 'ac.jpg', 2);
-
-INSERT INTO relations (id, friends)
-VALUES (1, ARRAY[2,3,4]);
-
-INSERT INTO categories (id, name, thumbnail)
-VALUES (1, 'General', 'category_default.png');
-
-INSERT INTO categories (id, name, thumbnail)
-VALUES (2, 'Announcement', 'category_default.png'),
-(3, 'Armored Core', 'ac.jpg'),
-(4, 'Ace Combat', 'ace.jpg'),
-(5, 'Persona', 'persona.jpg');
-
-INSERT INTO talks (id, name, description, owner, admin, users)
-VALUES (1, 'general', 'ac.jpg', 1, ARRAY [1], ARRAY [1]),
-(2, 'special', 'ac.jpg', 1, ARRAY [1], ARRAY [1]),
-(3, 'test1', 'ac.jpg', 1, ARRAY [1], ARRAY [1]),
-(4, 'test2', 'ac.jpg', 1, ARRAY [1], ARRAY [1]),
-(5, 'test3', 'ac.jpg', 1, ARRAY [1], ARRAY [1]);
-
-INSERT INTO topics (id, user_id, category_id, title, body, thumbnail)
-VALUES (1, 1, 1, 'Welcome To PixelShare', 'PixelShare is a gaming oriented community.', '');
-
-INSERT INTO posts (id, user_id, topic_id, category_id, post_content)
-VALUES (1, 1, 1, 1, 'First Reply Only to stop cache build from complaining');
-";
-
-const DROP_TABLES: &str = "
-DROP TABLE IF EXISTS associates;
-DROP TABLE IF EXISTS talks;
-DROP TABLE IF EXISTS users;
-DROP TABLE IF EXISTS categories;
-DROP TABLE IF EXISTS public_messages1;
-DROP TABLE IF EXISTS private_messages1;
-DROP TABLE IF EXISTS relations;
-
-DROP TABLE IF EXISTS psn_user_trophy_titles;
-DROP TABLE IF EXISTS psn_user_trophy_sets;
-DROP TABLE IF EXISTS trophy_sets;
-DROP TABLE IF EXISTS trophy_sets_argument;
-
-DROP TYPE IF EXISTS trophy_set;
-DROP TYPE IF EXISTS should_before_after;
-DROP TYPE IF EXISTS should_absent_time;
-
-DROP TABLE IF EXISTS topics;
-DROP TABLE IF EXISTS posts;";
+use crate::util::migration;
 
 //return global arc after building cache
 pub async fn build_cache(
@@ -275,14 +56,17 @@ pub async fn build_cache(
 
         // count posts and topics for each category and write to redis
         let query = format!(
-            "SELECT COUNT(id) FROM topics WHERE category_id = {}",
+            "SELECT COUNT(id) FROM topics WHERE category_id = {} AND is_visible AND deleted_at IS NULL",
             cat.id
         );
         let t_count = crate::handler::db::simple_query_one_column::<u32>(&c, query.as_str(), 0)
             .await
             .unwrap_or(0);
 
-        let query = format!("SELECT COUNT(id) FROM posts WHERE category_id = {}", cat.id);
+        let query = format!(
+            "SELECT COUNT(id) FROM posts WHERE category_id = {} AND deleted_at IS NULL AND topic_id IN (SELECT id FROM topics WHERE is_visible AND deleted_at IS NULL)",
+            cat.id
+        );
         let p_count = crate::handler::db::simple_query_one_column::<u32>(&c, query.as_str(), 0)
             .await
             .unwrap_or(0);
@@ -290,8 +74,8 @@ pub async fn build_cache(
         redis::cmd("HMSET")
             .arg(&format!("category:{}:set", cat.id))
             .arg(&[
-                ("topic_count", t_count.to_string()),
-                ("post_count", p_count.to_string()),
+                ("topic_count_own", t_count.to_string()),
+                ("post_count_own", p_count.to_string()),
             ])
             .query_async::<_, ()>(c_cache)
             .await?;
@@ -299,7 +83,7 @@ pub async fn build_cache(
         // ToDo: don't update popular list for categories by created_at order. Use set_perm key and last_reply_time field instead.
         // load topics belong to category
         let st = c
-            .prepare("SELECT * FROM topics WHERE category_id = $1 ORDER BY created_at DESC")
+            .prepare("SELECT * FROM topics WHERE category_id = $1 AND deleted_at IS NULL ORDER BY created_at DESC")
             .await?;
 
         let params: [&(dyn ToSql + Sync); 1] = [&cat.id];
@@ -312,7 +96,7 @@ pub async fn build_cache(
 
         // load topics reply count
         let query = format!(
-            "SELECT COUNT(topic_id), topic_id FROM posts WHERE category_id = {} GROUP BY topic_id",
+            "SELECT COUNT(topic_id), topic_id FROM posts WHERE category_id = {} AND deleted_at IS NULL GROUP BY topic_id",
             cat.id
         );
         let rows = c.simple_query(query.as_str()).await?;
@@ -343,15 +127,18 @@ pub async fn build_cache(
             })
             .collect::<Vec<Topic>>();
 
-        // build topics cache list.
+        // build topics cache list. hidden topics are not listed but still count for the last topic id.
         let mut tids = Vec::new();
         let mut sets = Vec::new();
         for t in t.into_iter() {
-            tids.push(t.id);
-            sets.push((t.id, t.category_id, t.reply_count, t.created_at));
             if t.id > last_tid {
                 last_tid = t.id
             };
+            if !t.is_visible {
+                continue;
+            }
+            tids.push(t.id);
+            sets.push((t.id, t.category_id, t.reply_count, t.created_at));
         }
 
         build_topics_cache_list(is_init, sets, c_cache).await?;
@@ -359,9 +146,11 @@ pub async fn build_cache(
 
     build_list(c_cache, category_ids, "category_id:meta".to_owned()).await?;
 
-    // load all posts with tid id and created_at
+    // load all alive posts with tid id and created_at
     let rows = c
-        .simple_query("SELECT topic_id, id, created_at FROM posts")
+        .simple_query(
+            "SELECT topic_id, id, created_at FROM posts WHERE deleted_at IS NULL AND topic_id IN (SELECT id FROM topics WHERE is_visible AND deleted_at IS NULL)",
+        )
         .await?;
 
     let mut posts = Vec::new();
@@ -371,13 +160,13 @@ pub async fn build_cache(
             let pid = row.get(1).unwrap().parse::<u32>().unwrap();
             let time =
                 NaiveDateTime::parse_from_str(row.get(2).unwrap(), "%Y-%m-%d %H:%M:%S%.f").unwrap();
-            posts.push((tid, pid, None, time));
+            posts.push((tid, pid, None, None, time));
         }
     }
 
-    // load topics reply count
+    // load posts reply count and last reply time
     let rows = c
-        .simple_query("SELECT COUNT(post_id), post_id FROM posts GROUP BY post_id")
+        .simple_query("SELECT COUNT(post_id), post_id, MAX(created_at) FROM posts WHERE deleted_at IS NULL GROUP BY post_id")
         .await?;
 
     let mut reply_count = Vec::new();
@@ -387,7 +176,10 @@ pub async fn build_cache(
                 if let Ok(count) = str.parse::<u32>() {
                     if let Some(str) = row.get(1) {
                         if let Ok(pid) = str.parse::<u32>() {
-                            reply_count.push((pid, count));
+                            let time = row.get(2).and_then(|t| {
+                                NaiveDateTime::parse_from_str(t, "%Y-%m-%d %H:%M:%S%.f").ok()
+                            });
+                            reply_count.push((pid, count, time));
                         }
                     }
                 }
@@ -408,18 +200,78 @@ pub async fn build_cache(
             for i in 0..reply_count.len() {
                 if p.1 == reply_count[i].0 {
                     p.2 = Some(reply_count[i].1);
+                    p.3 = reply_count[i].2;
                     reply_count.remove(i);
                     break;
                 }
             }
             p
         })
-        .collect::<Vec<(u32, u32, Option<u32>, NaiveDateTime)>>();
+        .collect::<Vec<(u32, u32, Option<u32>, Option<NaiveDateTime>, NaiveDateTime)>>();
 
     if !posts.is_empty() {
         let _ = build_posts_cache_list(is_init, posts, c_cache).await;
     }
 
+    // load tags of all alive topics
+    let rows = c
+        .simple_query("SELECT topic_id, tag FROM topic_tags WHERE topic_id IN (SELECT id FROM topics WHERE is_visible AND deleted_at IS NULL)")
+        .await?;
+
+    let mut tags = Vec::new();
+    for row in rows.into_iter() {
+        if let SimpleQueryMessage::Row(row) = row {
+            if let (Some(tid), Some(tag)) = (row.get(0), row.get(1)) {
+                if let Ok(tid) = tid.parse::<u32>() {
+                    tags.push((tid, tag.to_owned()));
+                }
+            }
+        }
+    }
+
+    if !tags.is_empty() {
+        build_tags_cache(c_cache, tags).await?;
+    }
+
+    // load alive pins of all alive topics
+    let st = c.prepare(SELECT_TOPIC_PINS).await?;
+    let params: [&(dyn ToSql + Sync); 0] = [];
+    let pins = c
+        .query_raw(&st, params.iter().map(|s| *s as &dyn ToSql))
+        .await?
+        .parse_row()
+        .await?;
+
+    if !pins.is_empty() {
+        build_pins_cache(c_cache, pins).await?;
+    }
+
+    // load reaction counts of all alive topics and posts
+    let reactions = reaction_counts(&c, None).await?;
+    if !reactions.topics.is_empty() || !reactions.posts.is_empty() {
+        build_reactions_cache(c_cache, &reactions).await?;
+    }
+
+    // load read states of all users
+    let st = c.prepare(SELECT_TOPIC_READS).await?;
+    let params: [&(dyn ToSql + Sync); 0] = [];
+    let topic_reads = c
+        .query_raw(&st, params.iter().map(|s| *s as &dyn ToSql))
+        .await?
+        .parse_row::<TopicRead>()
+        .await?;
+
+    let st = c.prepare(SELECT_CATEGORY_READS).await?;
+    let category_reads = c
+        .query_raw(&st, params.iter().map(|s| *s as &dyn ToSql))
+        .await?
+        .parse_row::<CategoryRead>()
+        .await?;
+
+    if !topic_reads.is_empty() || !category_reads.is_empty() {
+        build_reads_cache(c_cache, &topic_reads, &category_reads).await?;
+    }
+
     let last_uid = build_users_cache_local(&c, c_cache).await?;
 
     let st = c.prepare("SELECT * FROM talks").await?;
@@ -453,7 +305,9 @@ async fn build_categories_cache(
     c: &Client,
     c_cache: &mut MultiplexedConnection,
 ) -> Result<Vec<Category>, ResError> {
-    let st = c.prepare_typed("SELECT * FROM categories", &[]).await?;
+    let st = c
+        .prepare_typed(crate::handler::category::GET_CATEGORY_ALL, &[])
+        .await?;
     let params: [&(dyn ToSql + Sync); 0] = [];
     let categories = c
         .query_raw(&st, params.iter().map(|s| *s as &dyn ToSql))
@@ -500,54 +354,80 @@ async fn build_users_cache_local(
     Ok(last_uid)
 }
 
-// return Ok(false) if tables already exist.
-async fn create_table(postgres_url: &str) -> Result<bool, ResError> {
-    let (c, conn) = tokio_postgres::connect(postgres_url, NoTls).await?;
+pub(crate) async fn init_table_cache(args: &[String], postgres_url: &str, redis_url: &str) -> bool {
+    let mut is_init = false;
+    let mut args = args.iter().skip(1).map(String::as_str);
 
-    actix_rt::spawn(conn.map(|_| ()));
+    while let Some(arg) = args.next() {
+        match arg {
+            "drop" => {
+                migration::migrate_down_all(&postgres_url)
+                    .await
+                    .unwrap_or_else(|e| panic!("{}", e));
 
-    let st = c.prepare("SELECT * FROM categories").await;
+                let _ = crate::handler::cache::clear_cache(&redis_url);
 
-    if st.is_ok() {
-        return Ok(false);
+                std::process::exit(1);
+            }
+            // build is kept as an alias of migrate up.
+            "build" => is_init = migrate_up(&postgres_url).await,
+            "migrate" => match args.next() {
+                Some("up") => is_init = migrate_up(&postgres_url).await,
+                Some("down") => {
+                    match migration::migrate_down(&postgres_url)
+                        .await
+                        .unwrap_or_else(|e| panic!("{}", e))
+                    {
+                        Some(m) => println!("reverted migration {}", m),
+                        None => println!("no migration to revert"),
+                    }
+                    std::process::exit(0);
+                }
+                Some("status") => {
+                    let status = migration::status(&postgres_url)
+                        .await
+                        .unwrap_or_else(|e| panic!("{}", e));
+                    for (m, applied) in status.iter() {
+                        let state = if *applied { "applied" } else { "pending" };
+                        println!("{} {}", m, state);
+                    }
+                    std::process::exit(0);
+                }
+                _ => {
+                    println!("usage: pixel_social migrate up|down|status");
+                    std::process::exit(1);
+                }
+            },
+            _ => (),
+        }
     }
 
-    c.simple_query(BUILD_TABLES).await?;
+    if let Ok(status) = migration::status(&postgres_url).await {
+        let pending = status.iter().filter(|(_, applied)| !*applied).count();
+        if pending > 0 {
+            println!(
+                "{} pending migration(s). run `pixel_social migrate up` to apply them",
+                pending
+            );
+        }
+    }
 
-    Ok(true)
+    is_init
 }
 
-async fn drop_table(postgres_url: &str) -> Result<(), ResError> {
-    let (c, conn) = tokio_postgres::connect(postgres_url, NoTls).await?;
+// return true if the initial migration is applied in this run and we are working on a new database.
+async fn migrate_up(postgres_url: &str) -> bool {
+    let applied = migration::migrate_up(postgres_url)
+        .await
+        .unwrap_or_else(|e| panic!("{}", e));
 
-    actix_rt::spawn(conn.map(|_| ()));
-
-    c.simple_query(DROP_TABLES).await?;
-    Ok(())
-}
-
-pub(crate) async fn init_table_cache(args: &[String], postgres_url: &str, redis_url: &str) -> bool {
-    let mut is_init = false;
-    for arg in args.iter() {
-        if arg == "drop" {
-            drop_table(&postgres_url)
-                .await
-                .unwrap_or_else(|e| panic!("{}", e));
-
-            let _ = crate::handler::cache::clear_cache(&redis_url);
+    if applied.is_empty() {
+        println!("no pending migration. building cache with is_init = false");
+    }
 
-            std::process::exit(1);
-        }
-        if arg == "build" {
-            let success = create_table(&postgres_url)
-                .await
-                .unwrap_or_else(|e| panic!("{}", e));
-            if success {
-                is_init = true;
-            } else {
-                println!("tables already exists. building cache with is_init = false");
-            }
-        }
+    for m in applied.iter() {
+        println!("applied migration {}", m);
     }
-    is_init
+
+    applied.iter().any(|m| m.version == 1)
 }